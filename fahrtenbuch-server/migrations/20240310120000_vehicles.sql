@@ -0,0 +1,43 @@
+-- Create vehicles table. Every vehicle has its own odometer.
+create table if not exists vehicles
+(
+    id integer primary key not null,
+    name text not null unique
+);
+
+-- All existing entries belong to the vehicle the logbook was started with.
+insert into vehicles (id, name) values (1, 'Default');
+
+-- Recreate the trips table, because the odometer values are only unique per vehicle.
+--
+-- The trip_users table references the trips table, so its rows are moved out of the
+-- way while the table is replaced, otherwise the foreign key constraints would fail.
+create table trip_users_backup as select * from trip_users;
+delete from trip_users;
+
+create table trips_new
+(
+    id integer primary key not null,
+    vehicle_id integer not null,
+    created_at datetime not null,
+    start integer not null,
+    end integer not null,
+    description text,
+
+    constraint UQ_vehicle_start unique (vehicle_id, start),
+    constraint UQ_vehicle_end unique (vehicle_id, end),
+    constraint FK_vehicle_id foreign key(vehicle_id) references vehicles(id)
+);
+
+insert into trips_new (id, vehicle_id, created_at, start, end, description)
+    select id, 1, created_at, start, end, description from trips;
+
+drop table trips;
+alter table trips_new rename to trips;
+
+insert into trip_users (trip_id, user_id) select trip_id, user_id from trip_users_backup;
+drop table trip_users_backup;
+
+-- Expenses (like fuel or repairs) are made for a specific vehicle.
+alter table expenses add column vehicle_id integer references vehicles(id);
+update expenses set vehicle_id = 1;
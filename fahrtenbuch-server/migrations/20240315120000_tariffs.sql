@@ -0,0 +1,12 @@
+-- Create tariffs table. A tariff is valid from its start date until the next tariff
+-- becomes valid.
+create table if not exists tariffs
+(
+    id integer primary key not null,
+    valid_from datetime not null unique,
+    -- the price per kilometre in tenths of a cent (0.139 €/km = 139)
+    price_per_km integer not null
+);
+
+-- This has been the price per kilometre before tariffs were introduced.
+insert into tariffs (valid_from, price_per_km) values ('1970-01-01T00:00:00+00:00', 139);
@@ -0,0 +1,5 @@
+-- Add the role of each user, the roles are 'admin', 'member' and 'viewer'.
+alter table users add column role text not null default 'member';
+
+-- The first user of an existing installation manages it.
+update users set role = 'admin' where id = (select min(id) from users);
@@ -0,0 +1,25 @@
+-- Create settings table. The settings can be changed at runtime by an admin.
+create table if not exists settings
+(
+    key text primary key not null,
+    value text not null
+);
+
+-- Registration is closed by default, new users need an invitation.
+insert into settings (key, value) values ('registration_open', 'false');
+
+-- Create invitations table. Only the hash of the invitation token is stored.
+create table if not exists invitations
+(
+    id integer primary key not null,
+    token_hash text not null unique,
+    -- if set, only this username can be registered with the invitation
+    username text,
+    role text not null default 'member',
+    created_by integer not null,
+    created_at datetime not null,
+    expires_at datetime not null,
+    used_at datetime,
+
+    constraint FK_created_by foreign key(created_by) references users(id)
+);
@@ -0,0 +1,15 @@
+-- Create password_resets table. An admin can issue a reset token for a user who
+-- forgot their password, only the hash of the token is stored.
+create table if not exists password_resets
+(
+    id integer primary key not null,
+    token_hash text not null unique,
+    user_id integer not null,
+    created_by integer not null,
+    created_at datetime not null,
+    expires_at datetime not null,
+    used_at datetime,
+
+    constraint FK_user_id foreign key(user_id) references users(id),
+    constraint FK_created_by foreign key(created_by) references users(id)
+);
@@ -0,0 +1,16 @@
+-- Create api_tokens table. An api token allows scripts to use the api without a
+-- session, only the hash of the token is stored.
+create table if not exists api_tokens
+(
+    id integer primary key not null,
+    user_id integer not null,
+    name text not null,
+    token_hash text not null unique,
+    -- a read-only token can only be used to list and export entries
+    read_only boolean not null default false,
+    created_at datetime not null,
+    expires_at datetime,
+    last_used_at datetime,
+
+    constraint FK_user_id foreign key(user_id) references users(id)
+);
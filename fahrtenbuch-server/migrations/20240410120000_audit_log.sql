@@ -0,0 +1,31 @@
+-- Create audit_log table. Every change to trips, expenses and users is recorded with
+-- a json snapshot of the entry before and after the change.
+create table if not exists audit_log
+(
+    id integer primary key not null,
+    -- null if the change has been made with the command line
+    actor_id integer,
+    created_at datetime not null,
+    -- trip, expense or user
+    entity text not null,
+    entity_id integer not null,
+    -- insert, update, delete or password
+    action text not null,
+    before text,
+    after text,
+
+    constraint FK_actor_id foreign key(actor_id) references users(id)
+);
+
+create index if not exists IX_audit_log_entity on audit_log (entity, entity_id);
+
+-- The audit log is append-only.
+create trigger if not exists audit_log_no_update before update on audit_log
+begin
+    select raise(abort, 'the audit log is append-only');
+end;
+
+create trigger if not exists audit_log_no_delete before delete on audit_log
+begin
+    select raise(abort, 'the audit log is append-only');
+end;
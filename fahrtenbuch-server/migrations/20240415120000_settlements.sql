@@ -0,0 +1,22 @@
+-- Create settlements table. A settlement records that a user has paid money to
+-- another user to balance the expenses. Changes are recorded in the audit_log
+-- with the entity 'settlement'.
+create table if not exists settlements
+(
+    id integer primary key not null,
+    -- the user who paid
+    from_user_id integer not null,
+    -- the user who received the money
+    to_user_id integer not null,
+    -- the amount in cents
+    amount integer not null check (amount > 0),
+    created_at datetime not null,
+    -- the date of the period that is settled, if it is not the date of the payment
+    -- (e.g. the balance of January is paid in February)
+    settles_at datetime,
+    note text,
+
+    constraint CK_different_users check (from_user_id != to_user_id),
+    constraint FK_from_user_id foreign key(from_user_id) references users(id),
+    constraint FK_to_user_id foreign key(to_user_id) references users(id)
+);
@@ -0,0 +1,3 @@
+-- The amount in cents a user has paid for an expense. If it is null for all users
+-- of an expense, the amount of the expense is split equally between them.
+alter table expense_users add column amount integer check (amount is null or amount > 0);
@@ -0,0 +1,34 @@
+-- Create categories table. The allocation decides how the expenses of a category
+-- are split between the users in the summary:
+--   'distance': by the distance each user has driven
+--   'equal': equally between all users
+--   'percentage': by the percentages in category_percentages
+create table if not exists categories
+(
+    id integer primary key not null,
+    name text not null unique,
+    allocation text not null check (allocation in ('distance', 'equal', 'percentage'))
+);
+
+-- Create category_percentages table. The percentages of a category add up to 100,
+-- users without a percentage do not pay for the expenses of the category.
+create table if not exists category_percentages
+(
+    category_id integer not null,
+    user_id integer not null,
+    percentage integer not null check (percentage > 0 and percentage <= 100),
+
+    constraint PK_category_percentages primary key (category_id, user_id),
+    constraint FK_category_id foreign key(category_id) references categories(id),
+    constraint FK_user_id foreign key(user_id) references users(id)
+);
+
+insert into categories (name, allocation) values
+    ('fuel', 'distance'),
+    ('insurance', 'equal'),
+    ('tax', 'equal'),
+    ('repair', 'distance'),
+    ('cleaning', 'equal');
+
+-- Expenses without a category are split by distance, like before categories existed.
+alter table expenses add column category_id integer references categories(id);
@@ -0,0 +1,19 @@
+-- Create refuels table. A refuel belongs to the expense that records what has been
+-- paid, so it is part of the balances like every other expense. The vehicle and
+-- the date are those of the expense.
+create table if not exists refuels
+(
+    id integer primary key not null,
+    expense_id integer not null unique,
+    -- the value of the odometer when refuelling
+    odometer integer not null check (odometer >= 0),
+    -- the amount of fuel in millilitres
+    millilitres integer not null check (millilitres > 0),
+    -- the price per litre in tenths of a cent (1.799 €/l = 1799)
+    price_per_litre integer not null check (price_per_litre > 0),
+    -- the consumption is only known between two refuels that filled up the tank
+    full_tank boolean not null,
+    station text,
+
+    constraint FK_expense_id foreign key(expense_id) references expenses(id)
+);
@@ -0,0 +1,32 @@
+-- Create recurring_expenses table. An expense is added for every occurrence, the
+-- first one is at the start and the next ones follow after each interval.
+create table if not exists recurring_expenses
+(
+    id integer primary key not null,
+    vehicle_id integer not null,
+    -- the amount of each occurrence in cents
+    amount integer not null check (amount > 0),
+    description text,
+    category_id integer,
+    interval text not null check (interval in ('monthly', 'quarterly', 'yearly')),
+    start datetime not null,
+    -- there are no occurrences after the end
+    end datetime,
+    -- the number of occurrences for which an expense has been added, so an
+    -- occurrence is never added twice, even if its expense has been deleted
+    occurrences integer not null default 0,
+
+    constraint FK_vehicle_id foreign key(vehicle_id) references vehicles(id),
+    constraint FK_category_id foreign key(category_id) references categories(id)
+);
+
+-- Create recurring_expense_users table. The users who pay for each occurrence.
+create table if not exists recurring_expense_users
+(
+    recurring_expense_id integer not null,
+    user_id integer not null,
+
+    constraint PK_recurring_expense_users primary key (recurring_expense_id, user_id),
+    constraint FK_recurring_expense_id foreign key(recurring_expense_id) references recurring_expenses(id),
+    constraint FK_user_id foreign key(user_id) references users(id)
+);
@@ -0,0 +1,21 @@
+-- Create closed_periods table. While a period is closed, the trips, expenses and
+-- settlements inside it can not be changed. Reopening a period keeps the row, so
+-- it stays visible that the period had been closed.
+create table if not exists closed_periods
+(
+    id integer primary key not null,
+    start datetime not null,
+    end datetime not null,
+    closed_at datetime not null,
+    -- the admin who closed the period
+    closed_by integer not null,
+    -- the summary of each user at the time the period has been closed, as json
+    summary text not null,
+    reopened_at datetime,
+    reopened_by integer,
+    reopen_reason text,
+
+    constraint CK_period check (start < end),
+    constraint FK_closed_by foreign key(closed_by) references users(id),
+    constraint FK_reopened_by foreign key(reopened_by) references users(id)
+);
@@ -0,0 +1,10 @@
+-- Add the fields a logbook needs for the tax office. The classification of a trip:
+--   'business': a business trip, which needs a destination and a purpose
+--   'commuting': a trip between home and the regular place of work
+--   'private': any other trip
+-- The existing trips are private, because they have only been used to split the costs.
+alter table trips add column classification text not null default 'private'
+    check (classification in ('business', 'commuting', 'private'));
+alter table trips add column destination text;
+alter table trips add column purpose text;
+alter table trips add column business_partner text;
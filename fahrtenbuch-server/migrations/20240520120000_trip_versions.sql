@@ -0,0 +1,57 @@
+-- Create trip_versions table. Every change to a trip is recorded as a new version,
+-- the trips table only holds the latest version of each trip.
+--
+-- The versions of each vehicle form a hash chain: every version links to the
+-- previous version of the vehicle and its hash covers the hash of that version, so
+-- a version can not be changed or removed without breaking the chain.
+create table if not exists trip_versions
+(
+    id integer primary key not null,
+    trip_id integer not null,
+    -- starts at 1 and is increased by every correction
+    version integer not null,
+    vehicle_id integer not null,
+    created_at datetime not null,
+    start integer not null,
+    end integer not null,
+    description text,
+    classification text not null,
+    destination text,
+    purpose text,
+    business_partner text,
+    -- the ids of the users of the trip, sorted and separated by commas
+    users text not null,
+    -- the trip has been deleted with this version
+    deleted boolean not null default false,
+    recorded_at datetime not null,
+    -- null if the change has been made with the command line
+    recorded_by integer,
+    -- the previous version of the vehicle, null for its first version
+    previous_id integer,
+    -- the hash of the previous version of the vehicle, empty for its first version
+    previous_hash text not null,
+    -- covers the previous hash and all the other columns except the ids of the versions
+    hash text not null,
+
+    constraint UQ_trip_version unique (trip_id, version),
+    constraint UQ_previous_id unique (previous_id),
+    constraint FK_previous_id foreign key(previous_id) references trip_versions(id),
+    constraint FK_recorded_by foreign key(recorded_by) references users(id)
+);
+
+-- The versions are append-only, a correction is a new version.
+create trigger if not exists trip_versions_no_update before update on trip_versions
+begin
+    select raise(abort, 'the versions of a trip can not be changed');
+end;
+
+create trigger if not exists trip_versions_no_delete before delete on trip_versions
+begin
+    select raise(abort, 'the versions of a trip can not be changed');
+end;
+
+-- The latest version of the trip and its hash. The existing trips have no version
+-- yet, they are recorded when the server starts, because sqlite can not calculate
+-- the hashes.
+alter table trips add column version integer not null default 0;
+alter table trips add column hash text;
@@ -0,0 +1,15 @@
+-- Create skipped_occurrences table. An occurrence of a recurring expense inside a
+-- closed period is not added, it is recorded here instead so it can be added by
+-- hand once the period has been reopened.
+create table if not exists skipped_occurrences
+(
+    id integer primary key not null,
+    recurring_expense_id integer not null,
+    -- the date of the occurrence
+    date datetime not null,
+    -- the closed period the date is part of
+    closed_period_id integer not null,
+
+    constraint FK_recurring_expense_id foreign key(recurring_expense_id) references recurring_expenses(id),
+    constraint FK_closed_period_id foreign key(closed_period_id) references closed_periods(id)
+);
@@ -0,0 +1,86 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::auth::{self, AuthSession, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiTokenData {
+    /// Describes what the token is used for, e.g. `home automation`.
+    name: String,
+    /// A read-only token can only be used to list and export entries.
+    #[serde(default)]
+    read_only: bool,
+    /// How long the token can be used, forever if omitted.
+    #[serde(default)]
+    valid_for_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiTokenResult {
+    id: i64,
+    /// The token is only returned once, it can not be recovered later.
+    token: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+async fn query_add_api_token(
+    db: &mut SqliteConnection,
+    user_id: UserId,
+    data: ApiTokenData,
+) -> Result<ApiTokenResult, ApiError> {
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::invalid_field(
+            "name",
+            "The name of a token must not be empty",
+        ));
+    }
+
+    if data.valid_for_days == Some(0) {
+        return Err(ApiError::invalid_field(
+            "valid_for_days",
+            "A token must be valid for at least one day",
+        ));
+    }
+
+    let expires_at = data
+        .valid_for_days
+        .map(|days| Utc::now() + Duration::days(days as i64));
+    let (id, token) = auth::create_api_token(db, user_id, name, data.read_only, expires_at).await?;
+
+    Ok(ApiTokenResult {
+        id,
+        token: token.to_string(),
+        expires_at,
+    })
+}
+
+pub async fn add_api_token(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<ApiTokenData>,
+) -> ApiResult<ApiTokenResult> {
+    let Some(user) = auth_session.user.clone() else {
+        return ApiResult::error(ApiError::unauthorized("You are not logged in"));
+    };
+
+    // otherwise a read-only token could be used to create a token that can write
+    if user.read_only {
+        return ApiResult::error(ApiError::forbidden(
+            "A read-only token can not create other tokens",
+        ));
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_add_api_token(tx, user.id(), data)))
+        .await
+        .into()
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::category::{self, Allocation, Category, CategoryId};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CategoryData {
+    name: String,
+    allocation: Allocation,
+    /// The percentage each user pays, required for the allocation `percentage`.
+    #[serde(default)]
+    percentages: HashMap<UserId, u64>,
+}
+
+async fn query_add_category(
+    db: &mut SqliteConnection,
+    data: CategoryData,
+) -> Result<CategoryId, ApiError> {
+    let mut category = Category {
+        id: 0,
+        name: data.name.trim().to_string(),
+        allocation: data.allocation,
+        percentages: data.percentages,
+    };
+    category::validate_category(&mut *db, &category).await?;
+
+    let result = sqlx::query("insert into categories (name, allocation) values (?, ?)")
+        .bind(&category.name)
+        .bind(category.allocation)
+        .execute(&mut *db)
+        .await?;
+
+    category.id = result.last_insert_rowid();
+    category::store_percentages(&mut *db, &category).await?;
+
+    Ok(category.id)
+}
+
+pub async fn add_category(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<CategoryData>,
+) -> ApiResult<CategoryId> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_add_category(tx, data)))
+        .await
+        .into()
+}
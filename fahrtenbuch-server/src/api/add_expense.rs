@@ -1,63 +1,213 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use axum::Json;
+use axum_login::AuthUser;
 use axum_messages::Messages;
 
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::SqlitePool;
+use sqlx::SqliteConnection;
 
-use crate::auth::{AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::api::category::{self, CategoryId};
+use crate::api::list_expenses::{store_expense_users, validate_shares};
+use crate::api::list_users;
+use crate::api::period;
+use crate::api::vehicle::{self, VehicleId};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExpenseData {
+    /// The vehicle the expense was made for, can be omitted if there is only one.
     #[serde(default)]
-    created_at: Option<DateTime<Utc>>,
-    amount: u64,
+    pub vehicle_id: Option<VehicleId>,
     #[serde(default)]
-    description: Option<String>,
-    users: HashSet<UserId>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub amount: u64,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Decides how the expense is split, by the driven distance if omitted.
+    #[serde(default)]
+    pub category_id: Option<CategoryId>,
+    pub users: HashSet<UserId>,
+    /// How much each of the users has paid, the amount is split equally if omitted.
+    #[serde(default)]
+    pub shares: HashMap<UserId, u64>,
 }
 
-async fn query_add_expense(db: &SqlitePool, data: ExpenseData) -> anyhow::Result<()> {
+pub async fn query_add_expense(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: ExpenseData,
+) -> Result<i64, ApiError> {
     if data.users.is_empty() {
-        return Err(anyhow::anyhow!("No users provided"));
+        return Err(ApiError::invalid_field(
+            "users",
+            "An expense must have at least one user associated with it",
+        ));
     }
 
-    let created_at = data.created_at.unwrap_or_else(Utc::now);
-    sqlx::query("insert into expenses (created_at, amount, description) values (?, ?, ?)")
-        .bind(created_at)
-        .bind(data.amount as i64)
-        .bind(data.description)
-        .execute(db)
-        .await?;
-
-    let (expense_id,): (i64,) =
-        sqlx::query_as("select id from expenses where created_at = ? and amount = ?")
-            .bind(created_at)
-            .bind(data.amount as i64)
-            .fetch_one(db)
-            .await?;
-
-    for user_id in data.users {
-        sqlx::query("insert into expense_users (expense_id, user_id) values (?, ?)")
-            .bind(expense_id)
-            .bind(user_id)
-            .execute(db)
-            .await?;
+    validate_shares(data.amount, &data.users, &data.shares)?;
+    list_users::validate_users(&mut *db, &data.users).await?;
+
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, data.vehicle_id).await?;
+    if let Some(category_id) = data.category_id {
+        category::ensure_category_exists(&mut *db, category_id).await?;
     }
 
-    Ok(())
+    let created_at = data.created_at.unwrap_or_else(Utc::now);
+    period::ensure_open(&mut *db, [created_at]).await?;
+
+    let result = sqlx::query(
+        "insert into expenses (vehicle_id, created_at, amount, description, category_id) values (?, ?, ?, ?, ?)",
+    )
+    .bind(vehicle_id)
+    .bind(created_at)
+    .bind(data.amount as i64)
+    .bind(data.description)
+    .bind(data.category_id)
+    .execute(&mut *db)
+    .await?;
+
+    // the date and the amount are not unique, e.g. for recurring expenses
+    let expense_id = result.last_insert_rowid();
+
+    store_expense_users(&mut *db, expense_id, &data.users, &data.shares).await?;
+
+    audit::record_change(db, actor, Entity::Expense, expense_id, None).await?;
+
+    Ok(expense_id)
 }
 
 pub async fn add_expense(
     auth_session: AuthSession,
     _messages: Messages,
     Json(data): Json<ExpenseData>,
-) -> ApiResult<Option<()>> {
-    match query_add_expense(auth_session.backend.db().await, data).await {
-        Ok(_) => ApiResult::empty(),
-        Err(e) => ApiResult::error(format!("Failed to add_trip: {:?}", e)),
+) -> ApiResult<i64> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    if let Err(error) = auth::require_participation(&user, &data.users) {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(query_add_expense(tx, Some(user.id()), data))
+    })
+    .await
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_failed_add_expense_is_rolled_back() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "create trigger fail_expense_users before insert on expense_users \
+             begin select raise(abort, 'failed'); end",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // the expense is inserted before the users, which fail because of the trigger
+        let data = ExpenseData {
+            vehicle_id: None,
+            created_at: None,
+            amount: 1000,
+            description: None,
+            category_id: None,
+            users: HashSet::from([1, 2]),
+            shares: HashMap::new(),
+        };
+        let result =
+            utils::transaction(&db, |tx| Box::pin(query_add_expense(tx, Some(1), data))).await;
+        assert!(result.is_err());
+
+        let (expenses,): (i64,) = sqlx::query_as("select count(*) from expenses")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let (expense_users,): (i64,) = sqlx::query_as("select count(*) from expense_users")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+
+        assert_eq!((0, 0), (expenses, expense_users));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_users_are_invalid() {
+        let db = utils::test_db().await;
+
+        let data = ExpenseData {
+            vehicle_id: None,
+            created_at: None,
+            amount: 1000,
+            description: None,
+            category_id: None,
+            users: HashSet::from([1, 3]),
+            shares: HashMap::new(),
+        };
+        let error = utils::transaction(&db, |tx| Box::pin(query_add_expense(tx, Some(1), data)))
+            .await
+            .unwrap_err();
+
+        let ApiError::Validation { details, .. } = error else {
+            panic!("expected a validation error, got {:?}", error);
+        };
+        assert_eq!("users", details[0].field);
+    }
+
+    #[tokio::test]
+    async fn test_shares_must_add_up_to_the_amount() {
+        let db = utils::test_db().await;
+        let data = |shares: HashMap<UserId, u64>| ExpenseData {
+            vehicle_id: None,
+            created_at: None,
+            amount: 6000,
+            description: None,
+            category_id: None,
+            users: HashSet::from([1, 2]),
+            shares,
+        };
+
+        for shares in [
+            HashMap::from([(1, 4000), (2, 1000)]),
+            HashMap::from([(1, 6000), (2, 0)]),
+            HashMap::from([(1, 6000)]),
+        ] {
+            let result = utils::transaction(&db, |tx| {
+                Box::pin(query_add_expense(tx, Some(1), data(shares)))
+            })
+            .await;
+            assert!(matches!(result, Err(ApiError::Validation { .. })));
+        }
+
+        let shares = HashMap::from([(1, 4000), (2, 2000)]);
+        utils::transaction(&db, |tx| {
+            Box::pin(query_add_expense(tx, Some(1), data(shares)))
+        })
+        .await
+        .unwrap();
+
+        let expenses = crate::api::list_expenses::query_expenses(
+            &mut db.acquire().await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap()
+        .items;
+        assert_eq!(vec![(1, 4000), (2, 2000)], expenses[0].amounts(),);
     }
 }
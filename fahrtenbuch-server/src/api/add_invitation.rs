@@ -0,0 +1,80 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::auth::{self, AuthSession, Permission, Role, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::username::Username;
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvitationData {
+    /// Only this username can be registered with the invitation.
+    #[serde(default)]
+    username: Option<Username>,
+    /// The role of the invited user, a member if omitted.
+    #[serde(default)]
+    role: Option<Role>,
+    /// How long the invitation can be used, 7 days if omitted.
+    #[serde(default)]
+    valid_for_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvitationResult {
+    id: i64,
+    /// The token is only returned once, it can not be recovered later.
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+async fn query_add_invitation(
+    db: &mut SqliteConnection,
+    created_by: UserId,
+    data: InvitationData,
+) -> Result<InvitationResult, ApiError> {
+    let valid_for_days = data.valid_for_days.unwrap_or(7);
+    if valid_for_days == 0 || valid_for_days > 365 {
+        return Err(ApiError::invalid_field(
+            "valid_for_days",
+            "An invitation must be valid for 1 to 365 days",
+        ));
+    }
+
+    let expires_at = Utc::now() + Duration::days(valid_for_days as i64);
+    let (id, token) = auth::create_invitation(
+        db,
+        created_by,
+        data.username,
+        data.role.unwrap_or(Role::Member),
+        expires_at,
+    )
+    .await?;
+
+    Ok(InvitationResult {
+        id,
+        token: token.to_string(),
+        expires_at,
+    })
+}
+
+pub async fn add_invitation(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<InvitationData>,
+) -> ApiResult<InvitationResult> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_add_invitation(tx, user.id(), data)))
+        .await
+        .into()
+}
@@ -0,0 +1,79 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordResetData {
+    user_id: UserId,
+    /// How long the token can be used, 24 hours if omitted.
+    #[serde(default)]
+    valid_for_hours: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordResetResult {
+    /// The token is only returned once, it must be handed to the user.
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+async fn query_add_password_reset(
+    db: &mut SqliteConnection,
+    created_by: UserId,
+    data: PasswordResetData,
+) -> Result<PasswordResetResult, ApiError> {
+    let valid_for_hours = data.valid_for_hours.unwrap_or(24);
+    if valid_for_hours == 0 || valid_for_hours > 24 * 7 {
+        return Err(ApiError::invalid_field(
+            "valid_for_hours",
+            "A reset token must be valid for 1 to 168 hours",
+        ));
+    }
+
+    let existing: Option<(UserId,)> = sqlx::query_as("select id from users where id = ?")
+        .bind(data.user_id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    if existing.is_none() {
+        return Err(ApiError::not_found(format!(
+            "The user {} does not exist",
+            data.user_id
+        )));
+    }
+
+    let expires_at = Utc::now() + Duration::hours(valid_for_hours as i64);
+    let token = auth::create_password_reset(db, created_by, data.user_id, expires_at).await?;
+
+    Ok(PasswordResetResult {
+        token: token.to_string(),
+        expires_at,
+    })
+}
+
+pub async fn add_password_reset(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<PasswordResetData>,
+) -> ApiResult<PasswordResetResult> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(query_add_password_reset(tx, user.id(), data))
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use axum::Json;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::category::{self, CategoryId};
+use crate::api::list_users;
+use crate::api::recurring_expense::{self, Interval};
+use crate::api::vehicle::{self, VehicleId};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecurringExpenseData {
+    /// The vehicle the expenses are made for, can be omitted if there is only one.
+    #[serde(default)]
+    vehicle_id: Option<VehicleId>,
+    /// The amount of each occurrence in cents.
+    amount: u64,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    category_id: Option<CategoryId>,
+    interval: Interval,
+    /// The date of the first occurrence, it may be in the past.
+    start: DateTime<Utc>,
+    #[serde(default)]
+    end: Option<DateTime<Utc>>,
+    users: HashSet<UserId>,
+}
+
+async fn query_add_recurring_expense(
+    db: &mut SqliteConnection,
+    data: RecurringExpenseData,
+) -> Result<i64, ApiError> {
+    if data.users.is_empty() {
+        return Err(ApiError::invalid_field(
+            "users",
+            "An expense must have at least one user associated with it",
+        ));
+    }
+
+    list_users::validate_users(&mut *db, &data.users).await?;
+
+    if data.amount == 0 {
+        return Err(ApiError::invalid_field(
+            "amount",
+            "The amount must be greater than 0",
+        ));
+    }
+
+    if data.end.is_some_and(|end| end < data.start) {
+        return Err(ApiError::invalid_field(
+            "end",
+            "The end must not be before the start",
+        ));
+    }
+
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, data.vehicle_id).await?;
+    if let Some(category_id) = data.category_id {
+        category::ensure_category_exists(&mut *db, category_id).await?;
+    }
+
+    let result = sqlx::query(
+        "insert into recurring_expenses (vehicle_id, amount, description, category_id, interval, start, end) values (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(vehicle_id)
+    .bind(data.amount as i64)
+    .bind(data.description)
+    .bind(data.category_id)
+    .bind(data.interval)
+    .bind(data.start)
+    .bind(data.end)
+    .execute(&mut *db)
+    .await?;
+
+    let id = result.last_insert_rowid();
+
+    for user_id in utils::sorted_vec(data.users) {
+        sqlx::query(
+            "insert into recurring_expense_users (recurring_expense_id, user_id) values (?, ?)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *db)
+        .await?;
+    }
+
+    Ok(id)
+}
+
+pub async fn add_recurring_expense(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<RecurringExpenseData>,
+) -> ApiResult<i64> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    if let Err(error) = auth::require_participation(&user, &data.users) {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    let id =
+        match utils::transaction(db, |tx| Box::pin(query_add_recurring_expense(tx, data))).await {
+            Ok(id) => id,
+            Err(error) => return ApiResult::error(error),
+        };
+
+    // occurrences in the past are added right away, instead of by the next run of the task
+    recurring_expense::add_due_expenses(db, chrono::Utc::now())
+        .await
+        .map(|_| id)
+        .into()
+}
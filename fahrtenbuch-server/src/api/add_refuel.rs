@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::add_expense::{self, query_add_expense};
+use crate::api::category::CategoryId;
+use crate::api::refuel;
+use crate::api::vehicle::{self, VehicleId};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RefuelData {
+    /// The vehicle that has been refuelled, can be omitted if there is only one.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// The value of the odometer when refuelling.
+    pub odometer: u64,
+    /// The amount of fuel in millilitres.
+    pub millilitres: u64,
+    /// The price per litre in tenths of a cent (1.799 €/l = 1799).
+    pub price_per_litre: u64,
+    /// Whether the tank has been filled up, the consumption is only known between full tanks.
+    pub full_tank: bool,
+    #[serde(default)]
+    pub station: Option<String>,
+    /// The users who have paid for the fuel.
+    pub users: HashSet<UserId>,
+}
+
+/// Adds the refuel and the expense for it, the amount of the expense is the price
+/// of the fuel. Returns the id of the refuel.
+pub async fn query_add_refuel(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: RefuelData,
+) -> Result<i64, ApiError> {
+    if data.millilitres == 0 {
+        return Err(ApiError::invalid_field(
+            "millilitres",
+            "The amount of fuel must be greater than 0",
+        ));
+    }
+
+    if data.price_per_litre == 0 {
+        return Err(ApiError::invalid_field(
+            "price_per_litre",
+            "The price per litre must be greater than 0",
+        ));
+    }
+
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, data.vehicle_id).await?;
+
+    let existing: Option<(i64,)> = sqlx::query_as(
+        "select refuels.id from refuels join expenses on expenses.id = refuels.expense_id \
+         where expenses.vehicle_id = ? and refuels.odometer = ?",
+    )
+    .bind(vehicle_id)
+    .bind(data.odometer as i64)
+    .fetch_optional(&mut *db)
+    .await?;
+
+    if existing.is_some() {
+        return Err(ApiError::conflict(format!(
+            "There is already a refuel at the odometer value {}",
+            data.odometer
+        )));
+    }
+
+    // the fuel is split like the other fuel expenses, as long as the category exists
+    let category: Option<(CategoryId,)> =
+        sqlx::query_as("select id from categories where name = 'fuel'")
+            .fetch_optional(&mut *db)
+            .await?;
+
+    let expense_id = query_add_expense(
+        &mut *db,
+        actor,
+        add_expense::ExpenseData {
+            vehicle_id: Some(vehicle_id),
+            created_at: data.created_at,
+            amount: refuel::price_for(data.millilitres, data.price_per_litre),
+            description: data.station.clone(),
+            category_id: category.map(|(id,)| id),
+            users: data.users,
+            shares: Default::default(),
+        },
+    )
+    .await?;
+
+    let result = sqlx::query(
+        "insert into refuels (expense_id, odometer, millilitres, price_per_litre, full_tank, station) values (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(expense_id)
+    .bind(data.odometer as i64)
+    .bind(data.millilitres as i64)
+    .bind(data.price_per_litre as i64)
+    .bind(data.full_tank)
+    .bind(data.station)
+    .execute(&mut *db)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn add_refuel(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<RefuelData>,
+) -> ApiResult<i64> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    if let Err(error) = auth::require_participation(&user, &data.users) {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(query_add_refuel(tx, Some(user.id()), data))
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,170 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::period;
+use crate::api::settlement::{self, Settlement};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SettlementData {
+    pub from_user_id: UserId,
+    pub to_user_id: UserId,
+    /// The amount in cents.
+    pub amount: u64,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// The date of the settled period, if the payment is made after it.
+    #[serde(default)]
+    pub settles_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+pub async fn query_add_settlement(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: SettlementData,
+) -> Result<i64, ApiError> {
+    let settlement = Settlement {
+        id: 0,
+        from_user_id: data.from_user_id,
+        to_user_id: data.to_user_id,
+        amount: data.amount as i64,
+        created_at: data.created_at.unwrap_or_else(Utc::now),
+        settles_at: data.settles_at,
+        note: data.note,
+    };
+    settlement::validate_settlement(&mut *db, &settlement).await?;
+    period::ensure_open(&mut *db, settlement.dates()).await?;
+
+    let id = sqlx::query(
+        "insert into settlements (from_user_id, to_user_id, amount, created_at, settles_at, note) values (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(settlement.from_user_id)
+    .bind(settlement.to_user_id)
+    .bind(settlement.amount)
+    .bind(settlement.created_at)
+    .bind(settlement.settles_at)
+    .bind(settlement.note)
+    .execute(&mut *db)
+    .await?
+    .last_insert_rowid();
+
+    audit::record_change(db, actor, Entity::Settlement, id, None).await?;
+
+    Ok(id)
+}
+
+/// Records a payment between two users, returns the id of the settlement.
+pub async fn add_settlement(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<SettlementData>,
+) -> ApiResult<i64> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let users = [data.from_user_id, data.to_user_id].into_iter().collect();
+    if let Err(error) = auth::require_participation(&user, &users) {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(query_add_settlement(tx, Some(user.id()), data))
+    })
+    .await
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(from_user_id: UserId, to_user_id: UserId, amount: u64) -> SettlementData {
+        SettlementData {
+            from_user_id,
+            to_user_id,
+            amount,
+            created_at: None,
+            settles_at: None,
+            note: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_settled_period_must_be_open() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into closed_periods (start, end, closed_at, closed_by, summary) values \
+             ('2024-01-01T00:00:00+00:00', '2024-01-31T23:59:59+00:00', '2024-02-01T00:00:00+00:00', 1, '{}')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let paid_for = |settles_at: &str| SettlementData {
+            created_at: Some("2024-02-03T00:00:00Z".parse().unwrap()),
+            settles_at: Some(settles_at.parse().unwrap()),
+            ..data(1, 2, 100)
+        };
+
+        let error = utils::transaction(&db, |tx| {
+            Box::pin(query_add_settlement(
+                tx,
+                Some(1),
+                paid_for("2024-01-31T00:00:00Z"),
+            ))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!("conflict", error.code());
+
+        utils::transaction(&db, |tx| {
+            Box::pin(query_add_settlement(
+                tx,
+                Some(1),
+                paid_for("2024-02-01T00:00:00Z"),
+            ))
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_settlements() {
+        let db = utils::test_db().await;
+
+        for (data, field) in [
+            (data(1, 2, 0), "amount"),
+            (data(1, 1, 100), "to_user_id"),
+            (data(1, 3, 100), "to_user_id"),
+        ] {
+            let error =
+                utils::transaction(&db, |tx| Box::pin(query_add_settlement(tx, Some(1), data)))
+                    .await
+                    .unwrap_err();
+
+            let ApiError::Validation { details, .. } = error else {
+                panic!("expected a validation error, got {:?}", error);
+            };
+            assert_eq!(field, details[0].field);
+        }
+
+        utils::transaction(&db, |tx| {
+            Box::pin(query_add_settlement(tx, Some(1), data(1, 2, 100)))
+        })
+        .await
+        .unwrap();
+    }
+}
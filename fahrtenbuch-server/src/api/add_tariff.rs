@@ -0,0 +1,67 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::tariff;
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TariffData {
+    /// From when on the tariff should be used.
+    valid_from: DateTime<Utc>,
+    /// The price per kilometre in tenths of a cent (0.139 €/km = 139).
+    price_per_km: u64,
+}
+
+async fn query_add_tariff(db: &mut SqliteConnection, data: TariffData) -> Result<i64, ApiError> {
+    if data.price_per_km == 0 {
+        return Err(ApiError::invalid_field(
+            "price_per_km",
+            "The price per km must be greater than 0",
+        ));
+    }
+
+    // the price of trips that have already been made must not change
+    tariff::ensure_no_trips_after(&mut *db, data.valid_from).await?;
+
+    let existing: Option<(i64,)> = sqlx::query_as("select id from tariffs where valid_from = ?")
+        .bind(data.valid_from)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    if existing.is_some() {
+        return Err(ApiError::conflict(format!(
+            "There is already a tariff valid from {}",
+            data.valid_from
+        )));
+    }
+
+    let result = sqlx::query("insert into tariffs (valid_from, price_per_km) values (?, ?)")
+        .bind(data.valid_from)
+        .bind(data.price_per_km as i64)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn add_tariff(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<TariffData>,
+) -> ApiResult<i64> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_add_tariff(tx, data)))
+        .await
+        .into()
+}
@@ -1,27 +1,45 @@
 use std::collections::HashSet;
 
 use axum::Json;
+use axum_login::AuthUser;
 use axum_messages::Messages;
 
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::SqlitePool;
+use sqlx::SqliteConnection;
 
-use crate::api::trip::Trip;
-use crate::auth::{AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::api::list_users;
+use crate::api::period;
+use crate::api::trip::{Classification, Trip};
+use crate::api::trip_chain;
+use crate::api::vehicle::{self, VehicleId};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct TripData {
+    /// The vehicle that has been driven, can be omitted if there is only one.
     #[serde(default)]
-    created_at: Option<DateTime<Utc>>,
-    start: i64,
-    end: i64,
+    pub vehicle_id: Option<VehicleId>,
     #[serde(default)]
-    description: Option<String>,
-    users: HashSet<UserId>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub start: i64,
+    pub end: i64,
     #[serde(default)]
-    disable_start_check: bool,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub classification: Classification,
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub purpose: Option<String>,
+    #[serde(default)]
+    pub business_partner: Option<String>,
+    pub users: HashSet<UserId>,
+    #[serde(default)]
+    pub disable_start_check: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -29,28 +47,58 @@ pub struct TripValidationConfig {
     pub disable_start_check: bool,
     pub ignore_id: Option<i64>,
     pub ignore_gaps: bool,
+    /// Only reject trips that overlap another trip, so trips can be added before the
+    /// existing ones, e.g. when the history of a paper logbook is imported.
+    pub only_reject_overlaps: bool,
 }
 
 /// Ensures that the provided trip will be valid in the database.
 pub async fn validate_trip(
-    db: &SqlitePool,
+    db: &mut SqliteConnection,
     trip: Trip,
     config: TripValidationConfig,
-) -> anyhow::Result<()> {
+) -> Result<(), ApiError> {
     if trip.users.is_empty() {
-        return Err(anyhow::anyhow!(
-            "A trip must have at least one user associated with it"
+        return Err(ApiError::invalid_field(
+            "users",
+            "A trip must have at least one user associated with it",
         ));
     }
 
+    list_users::validate_users(&mut *db, &trip.users).await?;
+
+    // a business trip is only accepted by the tax office with its destination and purpose
+    if trip.classification == Classification::Business {
+        let is_missing =
+            |text: &Option<String>| text.as_deref().is_none_or(|text| text.trim().is_empty());
+
+        if is_missing(&trip.destination) {
+            return Err(ApiError::invalid_field(
+                "destination",
+                "A business trip must have a destination",
+            ));
+        }
+
+        if is_missing(&trip.purpose) {
+            return Err(ApiError::invalid_field(
+                "purpose",
+                "A business trip must have a purpose",
+            ));
+        }
+    }
+
     if trip.start >= trip.end {
-        return Err(anyhow::anyhow!(
-            "The start {} must be before the end {}",
-            trip.start,
-            trip.end
+        return Err(ApiError::invalid_field(
+            "end",
+            format!(
+                "The start {} must be before the end {}",
+                trip.start, trip.end
+            ),
         ));
     }
 
+    // all checks are done for the odometer of the vehicle of the trip
+    //
     // check that for a start value, there is a trip with that end value
     //
     // this prevents gaps like:
@@ -61,16 +109,20 @@ pub async fn validate_trip(
     // (here the trip 3 - 4 is missing)
     if trip.start > 0 && !config.disable_start_check && !config.ignore_gaps {
         let value: Option<(i64,)> =
-            sqlx::query_as("select id from trips where end = ? and id != ?")
+            sqlx::query_as("select id from trips where vehicle_id = ? and end = ? and id != ?")
+                .bind(trip.vehicle_id)
                 .bind(trip.start as i64)
                 .bind(config.ignore_id.unwrap_or(-1))
-                .fetch_optional(db)
+                .fetch_optional(&mut *db)
                 .await?;
 
         if value.is_none() {
-            return Err(anyhow::anyhow!(
-                "The start value {} is not connected to any end value",
-                trip.start
+            return Err(ApiError::invalid_field(
+                "start",
+                format!(
+                    "The start value {} is not connected to any end value",
+                    trip.start
+                ),
             ));
         }
     }
@@ -85,66 +137,121 @@ pub async fn validate_trip(
 
     // check that the trip is not conflicting with another trip in the database:
     if !config.ignore_gaps {
-        let value: Option<(i64,)> =
-            sqlx::query_as("select id from trips where (end > ? or start = ?) and id != ?")
-                .bind(trip.start as i64)
-                .bind(trip.start as i64)
-                .bind(config.ignore_id.unwrap_or(-1))
-                .fetch_optional(db)
-                .await?;
+        let value: Option<(i64,)> = if config.only_reject_overlaps {
+            sqlx::query_as(
+                "select id from trips where vehicle_id = ? and start < ? and end > ? and id != ?",
+            )
+            .bind(trip.vehicle_id)
+            .bind(trip.end as i64)
+            .bind(trip.start as i64)
+            .bind(config.ignore_id.unwrap_or(-1))
+            .fetch_optional(&mut *db)
+            .await?
+        } else {
+            sqlx::query_as(
+                "select id from trips where vehicle_id = ? and (end > ? or start = ?) and id != ?",
+            )
+            .bind(trip.vehicle_id)
+            .bind(trip.start as i64)
+            .bind(trip.start as i64)
+            .bind(config.ignore_id.unwrap_or(-1))
+            .fetch_optional(&mut *db)
+            .await?
+        };
 
         if value.is_some() {
-            return Err(anyhow::anyhow!(
+            return Err(ApiError::conflict(format!(
                 "The start value {} is conflicting with another trip",
                 trip.start
-            ));
+            )));
         }
     }
 
     Ok(())
 }
 
-async fn query_add_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()> {
+pub async fn query_add_trip(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: TripData,
+) -> Result<(), ApiError> {
+    let config = TripValidationConfig {
+        disable_start_check: data.disable_start_check,
+        ..Default::default()
+    };
+
+    insert_trip(db, actor, data, config).await
+}
+
+/// Validates the trip with the given config and stores it.
+pub async fn insert_trip(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: TripData,
+    config: TripValidationConfig,
+) -> Result<(), ApiError> {
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, data.vehicle_id).await?;
+    let created_at = data.created_at.unwrap_or_else(Utc::now);
+
+    period::ensure_open(&mut *db, [created_at]).await?;
+
     validate_trip(
-        db,
+        &mut *db,
         Trip {
             id: 0,
-            created_at: data.created_at.unwrap_or_else(|| Utc::now()),
+            vehicle_id,
+            created_at,
             start: data.start as u64,
             end: data.end as u64,
             description: data.description.clone(),
+            classification: data.classification,
+            destination: data.destination.clone(),
+            purpose: data.purpose.clone(),
+            business_partner: data.business_partner.clone(),
             users: data.users.clone(),
             price: 0,
+            version: 0,
+            hash: None,
         },
-        TripValidationConfig {
-            disable_start_check: data.disable_start_check,
-            ..Default::default()
-        },
+        config,
     )
     .await?;
 
-    sqlx::query("insert into trips (created_at, start, end, description) values (?, ?, ?, ?)")
-        .bind(data.created_at.unwrap_or_else(|| Utc::now()))
-        .bind(data.start)
-        .bind(data.end)
-        .bind(data.description)
-        .execute(db)
-        .await?;
+    sqlx::query(
+        "insert into trips (vehicle_id, created_at, start, end, description, classification, destination, purpose, business_partner) \
+         values (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(vehicle_id)
+    .bind(created_at)
+    .bind(data.start)
+    .bind(data.end)
+    .bind(data.description)
+    .bind(data.classification)
+    .bind(data.destination)
+    .bind(data.purpose)
+    .bind(data.business_partner)
+    .execute(&mut *db)
+    .await?;
 
-    let (trip_id,): (i64,) = sqlx::query_as("select id from trips where start = ? and end = ?")
-        .bind(data.start)
-        .bind(data.end)
-        .fetch_one(db)
-        .await?;
+    let (trip_id,): (i64,) =
+        sqlx::query_as("select id from trips where vehicle_id = ? and start = ? and end = ?")
+            .bind(vehicle_id)
+            .bind(data.start)
+            .bind(data.end)
+            .fetch_one(&mut *db)
+            .await?;
 
     for user_id in data.users {
         sqlx::query("insert into trip_users (trip_id, user_id) values (?, ?)")
             .bind(trip_id)
             .bind(user_id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
     }
 
+    audit::record_change(&mut *db, actor, Entity::Trip, trip_id, None).await?;
+    trip_chain::record_version(db, actor, trip_id).await?;
+
     Ok(())
 }
 
@@ -152,9 +259,182 @@ pub async fn add_trip(
     auth_session: AuthSession,
     _messages: Messages,
     Json(data): Json<TripData>,
-) -> ApiResult<Option<()>> {
-    match query_add_trip(auth_session.backend.db().await, data).await {
-        Ok(_) => ApiResult::empty(),
-        Err(e) => ApiResult::error(format!("Failed to add_trip: {:?}", e)),
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    if let Err(error) = auth::require_participation(&user, &data.users) {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_add_trip(tx, Some(user.id()), data)))
+        .await
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use sqlx::SqlitePool;
+
+    async fn add(db: &SqlitePool, data: TripData) -> Result<(), ApiError> {
+        utils::transaction(db, |tx| Box::pin(query_add_trip(tx, Some(1), data))).await
+    }
+
+    fn trip_data(vehicle_id: VehicleId, start: i64, end: i64) -> TripData {
+        TripData {
+            vehicle_id: Some(vehicle_id),
+            created_at: None,
+            start,
+            end,
+            description: None,
+            classification: Classification::Private,
+            destination: None,
+            purpose: None,
+            business_partner: None,
+            users: HashSet::from([1]),
+            disable_start_check: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_odometer_chains_are_per_vehicle() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into vehicles (id, name) values (2, 'Second')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        add(&db, trip_data(1, 0, 100)).await.unwrap();
+        // the same odometer values are fine for another vehicle
+        add(&db, trip_data(2, 0, 100)).await.unwrap();
+        add(&db, trip_data(2, 100, 150)).await.unwrap();
+
+        // the chain of the first vehicle ends at 100
+        assert!(add(&db, trip_data(1, 150, 200)).await.is_err());
+        add(&db, trip_data(1, 100, 120)).await.unwrap();
+
+        let counts: Vec<(VehicleId, i64)> =
+            sqlx::query_as("select vehicle_id, count(*) from trips group by vehicle_id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+
+        assert_eq!(vec![(1, 2), (2, 2)], counts);
+    }
+
+    #[tokio::test]
+    async fn test_vehicle_is_required_with_multiple_vehicles() {
+        let db = utils::test_db().await;
+
+        // with only the default vehicle, it can be omitted
+        add(
+            &db,
+            TripData {
+                vehicle_id: None,
+                ..trip_data(1, 0, 100)
+            },
+        )
+        .await
+        .unwrap();
+
+        sqlx::query("insert into vehicles (id, name) values (2, 'Second')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert!(add(
+            &db,
+            TripData {
+                vehicle_id: None,
+                ..trip_data(1, 100, 200)
+            },
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_add_trip_is_rolled_back() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "create trigger fail_trip_users before insert on trip_users \
+             begin select raise(abort, 'failed'); end",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // the trip is inserted before the users, which fail because of the trigger
+        let result = add(
+            &db,
+            TripData {
+                users: HashSet::from([1, 2]),
+                ..trip_data(1, 0, 100)
+            },
+        )
+        .await;
+        assert!(result.is_err());
+
+        let (trips,): (i64,) = sqlx::query_as("select count(*) from trips")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let (trip_users,): (i64,) = sqlx::query_as("select count(*) from trip_users")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+
+        assert_eq!((0, 0), (trips, trip_users));
+    }
+
+    #[tokio::test]
+    async fn test_business_trip_needs_destination_and_purpose() {
+        let db = utils::test_db().await;
+
+        let business = |destination: Option<&str>, purpose: Option<&str>| TripData {
+            classification: Classification::Business,
+            destination: destination.map(str::to_string),
+            purpose: purpose.map(str::to_string),
+            business_partner: Some("ACME GmbH".to_string()),
+            ..trip_data(1, 0, 100)
+        };
+
+        assert!(add(&db, business(None, Some("Meeting"))).await.is_err());
+        assert!(add(&db, business(Some("Berlin"), Some(" "))).await.is_err());
+        // a private trip does not need them
+        add(&db, trip_data(1, 0, 50)).await.unwrap();
+        add(
+            &db,
+            TripData {
+                start: 50,
+                ..business(Some("Berlin"), Some("Meeting"))
+            },
+        )
+        .await
+        .unwrap();
+
+        let trips: Vec<(Classification, Option<String>)> = sqlx::query_as(
+            "select classification, destination || ', ' || purpose || ', ' || business_partner from trips order by start",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (Classification::Private, None),
+                (
+                    Classification::Business,
+                    Some("Berlin, Meeting, ACME GmbH".to_string())
+                ),
+            ],
+            trips
+        );
     }
 }
@@ -0,0 +1,63 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::vehicle::VehicleId;
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VehicleData {
+    name: String,
+}
+
+async fn query_add_vehicle(
+    db: &mut SqliteConnection,
+    data: VehicleData,
+) -> Result<VehicleId, ApiError> {
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::invalid_field(
+            "name",
+            "The name of a vehicle must not be empty",
+        ));
+    }
+
+    let existing: Option<(VehicleId,)> = sqlx::query_as("select id from vehicles where name = ?")
+        .bind(name)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    if existing.is_some() {
+        return Err(ApiError::conflict(format!(
+            "The vehicle '{}' already exists",
+            name
+        )));
+    }
+
+    let result = sqlx::query("insert into vehicles (name) values (?)")
+        .bind(name)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn add_vehicle(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<VehicleData>,
+) -> ApiResult<VehicleId> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_add_vehicle(tx, data)))
+        .await
+        .into()
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::auth::UserId;
+use crate::response::ApiError;
+use crate::utils;
+
+pub type CategoryId = i64;
+
+/// How the expenses of a category are split between the users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Allocation {
+    /// By the distance each user has driven, e.g. for fuel.
+    Distance,
+    /// Equally between all users, e.g. for the insurance.
+    Equal,
+    /// By a fixed percentage per user.
+    Percentage,
+}
+
+/// A category of expenses, like fuel or insurance.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow)]
+pub struct Category {
+    pub id: CategoryId,
+    pub name: String,
+    pub allocation: Allocation,
+    /// The percentage each user pays, only used by [`Allocation::Percentage`].
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub percentages: HashMap<UserId, u64>,
+}
+
+impl Category {
+    /// Returns the weights for splitting the expenses of the category between
+    /// the users, `distances` are the distances driven by the `users`.
+    pub fn weights(&self, users: &[UserId], distances: &[u64]) -> Vec<u64> {
+        match self.allocation {
+            Allocation::Distance => distances.to_vec(),
+            Allocation::Equal => vec![1; users.len()],
+            Allocation::Percentage => users
+                .iter()
+                .map(|user| self.percentages.get(user).copied().unwrap_or_default())
+                .collect(),
+        }
+    }
+}
+
+/// Returns all categories, sorted by their ids.
+pub async fn query_categories(db: &mut SqliteConnection) -> Result<Vec<Category>, sqlx::Error> {
+    let mut categories: Vec<Category> = sqlx::query_as("select * from categories order by id")
+        .fetch_all(&mut *db)
+        .await?;
+
+    let percentages: Vec<(CategoryId, UserId, i64)> =
+        sqlx::query_as("select category_id, user_id, percentage from category_percentages")
+            .fetch_all(db)
+            .await?;
+
+    for (category_id, user_id, percentage) in percentages {
+        if let Some(category) = categories
+            .iter_mut()
+            .find(|category| category.id == category_id)
+        {
+            category.percentages.insert(user_id, percentage as u64);
+        }
+    }
+
+    Ok(categories)
+}
+
+pub async fn query_category(
+    db: &mut SqliteConnection,
+    id: CategoryId,
+) -> Result<Category, ApiError> {
+    let category: Option<Category> = sqlx::query_as("select * from categories where id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    let Some(mut category) = category else {
+        return Err(ApiError::not_found(format!(
+            "The category {} does not exist",
+            id
+        )));
+    };
+
+    let percentages: Vec<(UserId, i64)> = sqlx::query_as(
+        "select user_id, percentage from category_percentages where category_id = ?",
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+
+    category.percentages = percentages
+        .into_iter()
+        .map(|(user_id, percentage)| (user_id, percentage as u64))
+        .collect();
+
+    Ok(category)
+}
+
+/// Ensures that the category exists, before an expense refers to it.
+pub async fn ensure_category_exists(
+    db: &mut SqliteConnection,
+    id: CategoryId,
+) -> Result<(), ApiError> {
+    let value: Option<(CategoryId,)> = sqlx::query_as("select id from categories where id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    match value {
+        Some(_) => Ok(()),
+        None => Err(ApiError::not_found(format!(
+            "The category {} does not exist",
+            id
+        ))),
+    }
+}
+
+/// Ensures that the category will be valid in the database.
+pub async fn validate_category(
+    db: &mut SqliteConnection,
+    category: &Category,
+) -> Result<(), ApiError> {
+    if category.name.trim().is_empty() {
+        return Err(ApiError::invalid_field(
+            "name",
+            "The name of a category must not be empty",
+        ));
+    }
+
+    let existing: Option<(CategoryId,)> =
+        sqlx::query_as("select id from categories where name = ? and id != ?")
+            .bind(category.name.trim())
+            .bind(category.id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+    if existing.is_some() {
+        return Err(ApiError::conflict(format!(
+            "The category '{}' already exists",
+            category.name.trim()
+        )));
+    }
+
+    if category.allocation != Allocation::Percentage {
+        if !category.percentages.is_empty() {
+            return Err(ApiError::invalid_field(
+                "percentages",
+                "Only the allocation 'percentage' uses percentages",
+            ));
+        }
+
+        return Ok(());
+    }
+
+    if category
+        .percentages
+        .values()
+        .any(|percentage| *percentage == 0)
+    {
+        return Err(ApiError::invalid_field(
+            "percentages",
+            "A percentage must be greater than 0",
+        ));
+    }
+
+    let total = category.percentages.values().sum::<u64>();
+    if total != 100 {
+        return Err(ApiError::invalid_field(
+            "percentages",
+            format!(
+                "The percentages add up to {}, but must add up to 100",
+                total
+            ),
+        ));
+    }
+
+    for user_id in category.percentages.keys() {
+        let user: Option<(UserId,)> = sqlx::query_as("select id from users where id = ?")
+            .bind(user_id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+        if user.is_none() {
+            return Err(ApiError::invalid_field(
+                "percentages",
+                format!("The user {} does not exist", user_id),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the percentages of the category.
+pub async fn store_percentages(
+    db: &mut SqliteConnection,
+    category: &Category,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("delete from category_percentages where category_id = ?")
+        .bind(category.id)
+        .execute(&mut *db)
+        .await?;
+
+    for (user_id, percentage) in utils::sorted_vec(category.percentages.iter()) {
+        sqlx::query(
+            "insert into category_percentages (category_id, user_id, percentage) values (?, ?, ?)",
+        )
+        .bind(category.id)
+        .bind(user_id)
+        .bind(*percentage as i64)
+        .execute(&mut *db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_default_categories() {
+        let db = utils::test_db().await;
+        let categories = query_categories(&mut db.acquire().await.unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                ("fuel", Allocation::Distance),
+                ("insurance", Allocation::Equal),
+                ("tax", Allocation::Equal),
+                ("repair", Allocation::Distance),
+                ("cleaning", Allocation::Equal),
+            ],
+            categories
+                .iter()
+                .map(|category| (category.name.as_str(), category.allocation))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_percentages_must_add_up_to_100() {
+        let db = utils::test_db().await;
+        let mut conn = db.acquire().await.unwrap();
+
+        let category = |percentages: &[(UserId, u64)]| Category {
+            id: 0,
+            name: "parking".to_string(),
+            allocation: Allocation::Percentage,
+            percentages: percentages.iter().copied().collect(),
+        };
+
+        for percentages in [
+            &[][..],
+            &[(1, 60), (2, 30)],
+            &[(1, 100), (2, 0)],
+            &[(3, 100)],
+        ] {
+            assert!(validate_category(&mut conn, &category(percentages))
+                .await
+                .is_err());
+        }
+
+        validate_category(&mut conn, &category(&[(1, 60), (2, 40)]))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::summary::{query_summaries, SummaryResult};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClosePeriodData {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+async fn query_close_period(
+    db: &mut SqliteConnection,
+    actor: UserId,
+    data: ClosePeriodData,
+) -> Result<i64, ApiError> {
+    if data.start >= data.end {
+        return Err(ApiError::invalid_field(
+            "end",
+            "The end must be after the start",
+        ));
+    }
+
+    let overlapping: Option<(i64,)> = sqlx::query_as(
+        "select id from closed_periods where reopened_at is null \
+         and datetime(start, 'utc') <= datetime(?, 'utc') and datetime(?, 'utc') <= datetime(end, 'utc')",
+    )
+    .bind(data.end)
+    .bind(data.start)
+    .fetch_optional(&mut *db)
+    .await?;
+
+    if let Some((id,)) = overlapping {
+        return Err(ApiError::conflict(format!(
+            "The period overlaps with the closed period {}",
+            id
+        )));
+    }
+
+    // the summaries are computed in the transaction, so no change can slip in before the period is closed
+    let summaries = query_summaries(&mut *db, Some(data.start), Some(data.end), None).await?;
+    let users: Vec<(UserId,)> = sqlx::query_as("select id from users")
+        .fetch_all(&mut *db)
+        .await?;
+    let summaries: HashMap<UserId, SummaryResult> = users
+        .into_iter()
+        .map(|(user,)| (user, summaries.for_user(user)))
+        .collect();
+
+    let result = sqlx::query(
+        "insert into closed_periods (start, end, closed_at, closed_by, summary) values (?, ?, ?, ?, ?)",
+    )
+    .bind(data.start)
+    .bind(data.end)
+    .bind(Utc::now())
+    .bind(actor)
+    .bind(sqlx::types::Json(summaries))
+    .execute(&mut *db)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Closes the period, the summary of each user is stored with it.
+pub async fn close_period(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<ClosePeriodData>,
+) -> ApiResult<i64> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_close_period(tx, user.id(), data)))
+        .await
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use crate::api::add_expense::{self, query_add_expense};
+    use crate::api::reopen_period::{query_reopen_period, ReopenPeriodData};
+
+    fn expense(created_at: &str) -> add_expense::ExpenseData {
+        add_expense::ExpenseData {
+            vehicle_id: None,
+            created_at: Some(created_at.parse().unwrap()),
+            amount: 1000,
+            description: None,
+            category_id: None,
+            users: HashSet::from([1]),
+            shares: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_closed_period_rejects_changes_until_reopened() {
+        let db = utils::test_db().await;
+        let data = ClosePeriodData {
+            start: "2024-01-01T00:00:00Z".parse().unwrap(),
+            end: "2024-01-31T23:59:59Z".parse().unwrap(),
+        };
+        let id = utils::transaction(&db, |tx| Box::pin(query_close_period(tx, 1, data)))
+            .await
+            .unwrap();
+
+        // an overlapping period can not be closed twice
+        let overlapping = ClosePeriodData {
+            start: "2024-01-31T00:00:00Z".parse().unwrap(),
+            end: "2024-02-29T23:59:59Z".parse().unwrap(),
+        };
+        assert!(utils::transaction(&db, |tx| {
+            Box::pin(query_close_period(tx, 1, overlapping))
+        })
+        .await
+        .is_err());
+
+        let add = |created_at: &'static str| {
+            utils::transaction(&db, move |tx| {
+                Box::pin(query_add_expense(tx, Some(1), expense(created_at)))
+            })
+        };
+
+        assert!(add("2024-01-15T00:00:00Z").await.is_err());
+        add("2024-02-01T00:00:00Z").await.unwrap();
+
+        let reopen = |reason: &str| {
+            let data = ReopenPeriodData {
+                id,
+                reason: reason.to_string(),
+            };
+            utils::transaction(&db, |tx| Box::pin(query_reopen_period(tx, 2, data)))
+        };
+
+        // reopening needs a reason and is only possible once
+        assert!(reopen(" ").await.is_err());
+        reopen("forgot the insurance").await.unwrap();
+        assert!(reopen("again").await.is_err());
+
+        add("2024-01-15T00:00:00Z").await.unwrap();
+
+        let (closed_by, reopened_by, summary): (UserId, Option<UserId>, String) =
+            sqlx::query_as("select closed_by, reopened_by, summary from closed_periods")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!((1, Some(2)), (closed_by, reopened_by));
+        assert!(
+            serde_json::from_str::<HashMap<UserId, serde_json::Value>>(&summary)
+                .unwrap()
+                .contains_key(&2)
+        );
+    }
+}
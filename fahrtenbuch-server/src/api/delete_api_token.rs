@@ -0,0 +1,68 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteApiTokenData {
+    id: i64,
+}
+
+/// Users can revoke their own tokens, users who can manage can revoke all tokens.
+async fn query_delete_api_token(
+    db: &mut SqliteConnection,
+    user_id: UserId,
+    can_manage: bool,
+    data: DeleteApiTokenData,
+) -> Result<(), ApiError> {
+    let owner: Option<(UserId,)> = sqlx::query_as("select user_id from api_tokens where id = ?")
+        .bind(data.id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    match owner {
+        Some((owner,)) if owner == user_id || can_manage => {}
+        _ => {
+            return Err(ApiError::not_found(format!(
+                "The api token {} does not exist",
+                data.id
+            )))
+        }
+    }
+
+    sqlx::query("delete from api_tokens where id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_api_token(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteApiTokenData>,
+) -> ApiResult<()> {
+    // a read-only token must not be able to revoke other tokens
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let can_manage = auth::require_permission(&auth_session, Permission::Manage)
+        .await
+        .is_ok();
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(query_delete_api_token(tx, user.id(), can_manage, data))
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,75 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::category::{self, CategoryId};
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteCategoryData {
+    id: CategoryId,
+}
+
+async fn query_delete_category(
+    db: &mut SqliteConnection,
+    data: DeleteCategoryData,
+) -> Result<(), ApiError> {
+    category::ensure_category_exists(&mut *db, data.id).await?;
+
+    let (count,): (i64,) = sqlx::query_as("select count(*) from expenses where category_id = ?")
+        .bind(data.id)
+        .fetch_one(&mut *db)
+        .await?;
+
+    if count > 0 {
+        return Err(ApiError::conflict(format!(
+            "The category is used by {} expenses",
+            count
+        )));
+    }
+
+    let (count,): (i64,) =
+        sqlx::query_as("select count(*) from recurring_expenses where category_id = ?")
+            .bind(data.id)
+            .fetch_one(&mut *db)
+            .await?;
+
+    if count > 0 {
+        return Err(ApiError::conflict(format!(
+            "The category is used by {} recurring expenses",
+            count
+        )));
+    }
+
+    sqlx::query("delete from category_percentages where category_id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("delete from categories where id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_category(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteCategoryData>,
+) -> ApiResult<()> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_delete_category(tx, data)))
+        .await
+        .into()
+}
@@ -0,0 +1,84 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::list_expenses::query_expense_users;
+use crate::api::period;
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteExpenseData {
+    id: i64,
+}
+
+async fn query_delete_expense(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: DeleteExpenseData,
+) -> Result<(), ApiError> {
+    let snapshot = audit::snapshot(&mut *db, Entity::Expense, data.id).await?;
+
+    if snapshot.is_none() {
+        return Err(ApiError::not_found(format!(
+            "The expense {} does not exist",
+            data.id
+        )));
+    }
+
+    let (created_at,): (DateTime<Utc>,) =
+        sqlx::query_as("select created_at from expenses where id = ?")
+            .bind(data.id)
+            .fetch_one(&mut *db)
+            .await?;
+    period::ensure_open(&mut *db, [created_at]).await?;
+
+    // a refuel can not exist without its expense
+    sqlx::query("delete from refuels where expense_id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("delete from expense_users where expense_id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("delete from expenses where id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    audit::record_change(db, actor, Entity::Expense, data.id, snapshot).await?;
+
+    Ok(())
+}
+
+pub async fn delete_expense(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteExpenseData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move {
+            let users = query_expense_users(&mut *tx, data.id).await?;
+            auth::require_participation(&user, &users)?;
+
+            query_delete_expense(tx, Some(user.id()), data).await
+        })
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,49 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteInvitationData {
+    id: i64,
+}
+
+async fn query_delete_invitation(
+    db: &mut SqliteConnection,
+    data: DeleteInvitationData,
+) -> Result<(), ApiError> {
+    let result = sqlx::query("delete from invitations where id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found(format!(
+            "The invitation {} does not exist",
+            data.id
+        )));
+    }
+
+    Ok(())
+}
+
+pub async fn delete_invitation(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteInvitationData>,
+) -> ApiResult<()> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_delete_invitation(tx, data)))
+        .await
+        .into()
+}
@@ -0,0 +1,66 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::recurring_expense::query_recurring_expense;
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteRecurringExpenseData {
+    id: i64,
+}
+
+/// Stops adding expenses, the expenses that have already been added are kept.
+async fn query_delete_recurring_expense(
+    db: &mut SqliteConnection,
+    data: DeleteRecurringExpenseData,
+) -> Result<(), ApiError> {
+    sqlx::query("delete from skipped_occurrences where recurring_expense_id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("delete from recurring_expense_users where recurring_expense_id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("delete from recurring_expenses where id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_recurring_expense(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteRecurringExpenseData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move {
+            let Some(recurring_expense) = query_recurring_expense(&mut *tx, data.id).await? else {
+                return Err(ApiError::not_found(format!(
+                    "The recurring expense {} does not exist",
+                    data.id
+                )));
+            };
+            auth::require_participation(&user, &recurring_expense.users)?;
+
+            query_delete_recurring_expense(tx, data).await
+        })
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,61 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::period;
+use crate::api::settlement;
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteSettlementData {
+    id: i64,
+}
+
+async fn query_delete_settlement(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: DeleteSettlementData,
+) -> Result<(), ApiError> {
+    let settlement = settlement::query_settlement(&mut *db, data.id).await?;
+    period::ensure_open(&mut *db, settlement.dates()).await?;
+
+    let snapshot = audit::snapshot(&mut *db, Entity::Settlement, data.id).await?;
+
+    sqlx::query("delete from settlements where id = ?")
+        .bind(data.id)
+        .execute(&mut *db)
+        .await?;
+
+    audit::record_change(db, actor, Entity::Settlement, data.id, snapshot).await?;
+
+    Ok(())
+}
+
+pub async fn delete_settlement(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteSettlementData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move {
+            let settlement = settlement::query_settlement(&mut *tx, data.id).await?;
+            auth::require_participation(&user, &settlement.users())?;
+
+            query_delete_settlement(tx, Some(user.id()), data).await
+        })
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,57 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::tariff::{self, Tariff};
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteTariffData {
+    id: i64,
+}
+
+async fn query_delete_tariff(
+    db: &mut SqliteConnection,
+    data: DeleteTariffData,
+) -> Result<(), ApiError> {
+    let Some(tariff): Option<Tariff> = sqlx::query_as("select * from tariffs where id = ?")
+        .bind(data.id)
+        .fetch_optional(&mut *db)
+        .await?
+    else {
+        return Err(ApiError::not_found(format!(
+            "The tariff {} does not exist",
+            data.id
+        )));
+    };
+
+    // trips made while the tariff was in effect would get the price of the previous tariff
+    tariff::ensure_no_trips_after(&mut *db, tariff.valid_from).await?;
+
+    sqlx::query("delete from tariffs where id = ?")
+        .bind(tariff.id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_tariff(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteTariffData>,
+) -> ApiResult<()> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_delete_tariff(tx, data)))
+        .await
+        .into()
+}
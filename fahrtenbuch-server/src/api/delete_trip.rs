@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::list_trips::TripEntry;
+use crate::api::period;
+use crate::api::trip;
+use crate::api::trip_chain;
+use crate::api::update_trip::{self, query_update_trip};
+use crate::api::vehicle::{self, VehicleId};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, User, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteTripData {
+    /// The vehicle of the trip, can be omitted if there is only one.
+    #[serde(default)]
+    vehicle_id: Option<VehicleId>,
+    /// The end of the trip, which uniquely identifies the trip of a vehicle.
+    end: i64,
+    /// If the trip is followed by another trip, the following trip is extended to
+    /// start where the deleted trip started, so the odometer chain stays intact.
+    ///
+    /// Without this, only the last trip of a vehicle can be deleted.
+    #[serde(default)]
+    merge: bool,
+}
+
+/// Deletes the trip and returns the ids of the trips that have been extended to close the gap.
+async fn query_delete_trip(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: DeleteTripData,
+) -> Result<Vec<i64>, ApiError> {
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, data.vehicle_id).await?;
+
+    let Some(trip): Option<TripEntry> =
+        sqlx::query_as("select * from trips where vehicle_id = ? and end = ?")
+            .bind(vehicle_id)
+            .bind(data.end)
+            .fetch_optional(&mut *db)
+            .await?
+    else {
+        return Err(ApiError::not_found(format!(
+            "the trip with the end {} does not exist",
+            data.end
+        )));
+    };
+
+    let trip_after: Option<TripEntry> =
+        sqlx::query_as("select * from trips where vehicle_id = ? and start = ?")
+            .bind(vehicle_id)
+            .bind(trip.end)
+            .fetch_optional(&mut *db)
+            .await?;
+
+    // deleting a trip in the middle would leave a gap in the odometer chain
+    if trip_after.is_some() && !data.merge {
+        return Err(ApiError::conflict(format!(
+            "The trip {} - {} is followed by another trip, it can only be deleted by merging it into the following trip",
+            trip.start,
+            trip.end
+        )));
+    }
+
+    period::ensure_open(&mut *db, [trip.created_at]).await?;
+
+    let snapshot = audit::snapshot(&mut *db, Entity::Trip, trip.id).await?;
+
+    sqlx::query("delete from trip_users where trip_id = ?")
+        .bind(trip.id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("delete from trips where id = ?")
+        .bind(trip.id)
+        .execute(&mut *db)
+        .await?;
+
+    audit::record_change(&mut *db, actor, Entity::Trip, trip.id, snapshot).await?;
+    trip_chain::record_version(&mut *db, actor, trip.id).await?;
+
+    let mut extended = Vec::new();
+    if let Some(after) = trip_after {
+        extended.push(after.id);
+
+        // close the gap by letting the following trip start where the deleted trip started
+        let shifted = query_update_trip(
+            &mut *db,
+            actor,
+            update_trip::TripData {
+                vehicle_id: Some(vehicle_id),
+                original_end: after.end,
+                start: Some(trip.start),
+                end: None,
+                description: None,
+                classification: None,
+                destination: None,
+                purpose: None,
+                business_partner: None,
+                users: HashSet::new(),
+            },
+        )
+        .await?;
+        extended.extend(shifted);
+    }
+
+    Ok(extended)
+}
+
+/// Deletes the trip as the user, who must be part of the deleted and the extended trips.
+async fn delete_trip_as(
+    db: &mut SqliteConnection,
+    user: &User,
+    data: DeleteTripData,
+) -> Result<(), ApiError> {
+    let users = trip::query_trip_users(&mut *db, data.vehicle_id, data.end).await?;
+    auth::require_participation(user, &users)?;
+
+    let extended = query_delete_trip(&mut *db, Some(user.id()), data).await?;
+    trip::require_participation(db, user, extended).await
+}
+
+pub async fn delete_trip(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<DeleteTripData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move { delete_trip_as(tx, &user, data).await })
+    })
+    .await
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use sqlx::SqlitePool;
+
+    async fn delete(db: &SqlitePool, data: DeleteTripData) -> Result<Vec<i64>, ApiError> {
+        utils::transaction(db, |tx| Box::pin(query_delete_trip(tx, Some(2), data))).await
+    }
+
+    fn delete_data(end: i64, merge: bool) -> DeleteTripData {
+        DeleteTripData {
+            vehicle_id: None,
+            end,
+            merge,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_last_trip() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+
+        delete(&db, delete_data(150, false)).await.unwrap();
+
+        assert_eq!(vec![(0, 100)], utils::list_chain(&db, 1).await);
+
+        let (trip_users,): (i64,) = sqlx::query_as("select count(*) from trip_users")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(1, trip_users);
+    }
+
+    #[tokio::test]
+    async fn test_delete_trip_in_the_middle() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+
+        // without merging the gap, the trip can not be deleted
+        assert!(delete(&db, delete_data(150, false)).await.is_err());
+        assert_eq!(
+            vec![(0, 100), (100, 150), (150, 200)],
+            utils::list_chain(&db, 1).await
+        );
+
+        delete(&db, delete_data(150, true)).await.unwrap();
+        assert_eq!(vec![(0, 100), (100, 200)], utils::list_chain(&db, 1).await);
+    }
+
+    #[tokio::test]
+    async fn test_merge_is_recorded_in_the_audit_log() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+
+        delete(&db, delete_data(150, true)).await.unwrap();
+
+        let log: Vec<(Option<UserId>, Entity, i64, audit::Action)> =
+            sqlx::query_as("select actor_id, entity, entity_id, action from audit_log order by id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            vec![
+                (Some(2), Entity::Trip, 2, audit::Action::Delete),
+                // the following trip has been extended implicitly
+                (Some(2), Entity::Trip, 3, audit::Action::Update),
+            ],
+            log
+        );
+    }
+
+    #[tokio::test]
+    async fn test_members_only_merge_into_their_trips() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+        sqlx::query("update trip_users set user_id = 2 where trip_id = 2")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let bob = utils::test_user(&db, 2).await;
+
+        // merging would extend the following trip of alice
+        let result = utils::transaction(&db, |tx| {
+            Box::pin(async move { delete_trip_as(tx, &bob, delete_data(150, true)).await })
+        })
+        .await;
+        assert_eq!("forbidden", result.unwrap_err().code());
+
+        assert_eq!(
+            vec![(0, 100), (100, 150), (150, 200)],
+            utils::list_chain(&db, 1).await
+        );
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+
+use axum::body::Body;
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum_messages::Messages;
+
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::api::list_expenses::{query_expenses, ListExpensesOptions};
+use crate::api::list_trips::{query_trips, ListTripsOptions};
+use crate::api::page::SortOrder;
+use crate::api::summary::query_summaries;
+use crate::api::vehicle::VehicleId;
+use crate::auth::{AuthSession, UserId};
+use crate::response::ApiError;
+use crate::username::Username;
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportSummaryOptions {
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    /// Only export the summaries of specific user(s).
+    #[serde(default)]
+    pub users: Vec<UserId>,
+    /// Only consider trips and expenses of a specific vehicle.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
+}
+
+/// Formats an amount in cents as euros, e.g. `1234` as `12.34`.
+fn format_cents(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+
+    format!("{}{}.{:02}", sign, cents.abs() / 100, cents.abs() % 100)
+}
+
+/// Returns the first day of the month of the given date.
+fn start_of_month(date: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(date.year(), date.month(), 1, 0, 0, 0)
+        .unwrap()
+}
+
+fn next_month(date: DateTime<Utc>) -> DateTime<Utc> {
+    if date.month() == 12 {
+        Utc.with_ymd_and_hms(date.year() + 1, 1, 1, 0, 0, 0)
+            .unwrap()
+    } else {
+        Utc.with_ymd_and_hms(date.year(), date.month() + 1, 1, 0, 0, 0)
+            .unwrap()
+    }
+}
+
+/// Splits the time frame into months, the first and last month are cut to the time frame.
+fn months_between(start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut result = Vec::new();
+
+    let mut month = start_of_month(start);
+    while month <= end {
+        let month_end = next_month(month) - Duration::seconds(1);
+        result.push((month.max(start), month_end.min(end)));
+        month = next_month(month);
+    }
+
+    result
+}
+
+async fn usernames(db: &SqlitePool) -> Result<HashMap<UserId, Username>, ApiError> {
+    let users: Vec<(UserId, Username)> = sqlx::query_as("select id, username from users")
+        .fetch_all(db)
+        .await?;
+
+    Ok(users.into_iter().collect())
+}
+
+async fn vehicle_names(db: &SqlitePool) -> Result<HashMap<VehicleId, String>, ApiError> {
+    let vehicles: Vec<(VehicleId, String)> = sqlx::query_as("select id, name from vehicles")
+        .fetch_all(db)
+        .await?;
+
+    Ok(vehicles.into_iter().collect())
+}
+
+fn format_users(users: &HashSet<UserId>, names: &HashMap<UserId, Username>) -> String {
+    utils::sorted_vec(users.iter())
+        .into_iter()
+        .map(|id| match names.get(id) {
+            Some(name) => name.to_string(),
+            None => id.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn csv_response(
+    filename: &str,
+    rows: impl Stream<Item = Result<Vec<u8>, ApiError>> + Send + 'static,
+) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(rows),
+    )
+        .into_response()
+}
+
+fn csv_error(error: csv::Error) -> ApiError {
+    ApiError::Internal(error.into())
+}
+
+fn write_records<R, T>(records: impl IntoIterator<Item = R>) -> Result<Vec<u8>, ApiError>
+where
+    R: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer.write_record(record).map_err(csv_error)?;
+    }
+
+    writer
+        .into_inner()
+        .map_err(|error| ApiError::Internal(anyhow::anyhow!("{}", error)))
+}
+
+/// The number of entries that are loaded and sent at once.
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// What is needed to load the next page of an export, `next` is `None` after the last page.
+struct ExportState<T> {
+    db: SqlitePool,
+    names: HashMap<UserId, Username>,
+    vehicles: HashMap<VehicleId, String>,
+    next: Option<T>,
+}
+
+impl<T> ExportState<T> {
+    async fn new(db: &SqlitePool, next: T) -> Result<Self, ApiError> {
+        Ok(Self {
+            db: db.clone(),
+            names: usernames(db).await?,
+            vehicles: vehicle_names(db).await?,
+            next: Some(next),
+        })
+    }
+}
+
+type PageResult<T> = Result<Option<(Vec<Vec<String>>, ExportState<T>)>, ApiError>;
+
+/// Streams the header and then the rows of each page, so the export is never held
+/// in memory as a whole.
+///
+/// Once the response has started, errors can only abort it, so they are logged.
+fn csv_stream<T, F, Fut>(
+    header: &'static [&'static str],
+    state: ExportState<T>,
+    next_page: F,
+) -> impl Stream<Item = Result<Vec<u8>, ApiError>> + Send + 'static
+where
+    T: Send + 'static,
+    F: FnMut(ExportState<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = PageResult<T>> + Send + 'static,
+{
+    stream::once(async move { write_records([header]) })
+        .chain(
+            stream::try_unfold(state, next_page)
+                .and_then(|rows| async move { write_records(rows) }),
+        )
+        .inspect_err(|error| log::error!("Failed to export: {}", error))
+}
+
+const TRIPS_HEADER: &[&str] = &[
+    "date",
+    "vehicle",
+    "start",
+    "end",
+    "distance",
+    "users",
+    "price",
+    "description",
+    "classification",
+    "destination",
+    "purpose",
+    "business_partner",
+];
+
+async fn next_trips_page(mut state: ExportState<ListTripsOptions>) -> PageResult<ListTripsOptions> {
+    let Some(mut options) = state.next.take() else {
+        return Ok(None);
+    };
+
+    let page = query_trips(&mut *state.db.acquire().await?, options.clone()).await?;
+    if page.next_cursor.is_some() {
+        options.cursor = page.next_cursor;
+        state.next = Some(options);
+    }
+
+    let rows = page
+        .items
+        .into_iter()
+        .map(|trip| {
+            vec![
+                trip.created_at.format("%Y-%m-%d").to_string(),
+                state
+                    .vehicles
+                    .get(&trip.vehicle_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                trip.start.to_string(),
+                trip.end.to_string(),
+                trip.distance().to_string(),
+                format_users(&trip.users, &state.names),
+                format_cents(trip.price as i64),
+                trip.description.unwrap_or_default(),
+                trip.classification.to_string(),
+                trip.destination.unwrap_or_default(),
+                trip.purpose.unwrap_or_default(),
+                trip.business_partner.unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    Ok(Some((rows, state)))
+}
+
+pub async fn query_export_trips(
+    db: &SqlitePool,
+    options: ListTripsOptions,
+) -> Result<impl Stream<Item = Result<Vec<u8>, ApiError>> + Send + 'static, ApiError> {
+    // the oldest trip should be at the top, like in a paper logbook
+    let options = ListTripsOptions {
+        limit: Some(EXPORT_PAGE_SIZE),
+        cursor: None,
+        sort: SortOrder::Asc,
+        ..options
+    };
+
+    Ok(csv_stream(
+        TRIPS_HEADER,
+        ExportState::new(db, options).await?,
+        next_trips_page,
+    ))
+}
+
+const EXPENSES_HEADER: &[&str] = &["date", "vehicle", "amount", "users", "description"];
+
+async fn next_expenses_page(
+    mut state: ExportState<ListExpensesOptions>,
+) -> PageResult<ListExpensesOptions> {
+    let Some(mut options) = state.next.take() else {
+        return Ok(None);
+    };
+
+    let page = query_expenses(&mut *state.db.acquire().await?, options.clone()).await?;
+    if page.next_cursor.is_some() {
+        options.cursor = page.next_cursor;
+        state.next = Some(options);
+    }
+
+    let rows = page
+        .items
+        .into_iter()
+        .map(|expense| {
+            vec![
+                expense.created_at.format("%Y-%m-%d").to_string(),
+                expense
+                    .vehicle_id
+                    .and_then(|id| state.vehicles.get(&id).cloned())
+                    .unwrap_or_default(),
+                format_cents(expense.amount),
+                format_users(&expense.users, &state.names),
+                expense.description.unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    Ok(Some((rows, state)))
+}
+
+pub async fn query_export_expenses(
+    db: &SqlitePool,
+    options: ListExpensesOptions,
+) -> Result<impl Stream<Item = Result<Vec<u8>, ApiError>> + Send + 'static, ApiError> {
+    let options = ListExpensesOptions {
+        limit: Some(EXPORT_PAGE_SIZE),
+        cursor: None,
+        sort: SortOrder::Asc,
+        ..options
+    };
+
+    Ok(csv_stream(
+        EXPENSES_HEADER,
+        ExportState::new(db, options).await?,
+        next_expenses_page,
+    ))
+}
+
+/// The months whose summaries have not been exported yet.
+struct SummaryMonths {
+    months: VecDeque<(DateTime<Utc>, DateTime<Utc>)>,
+    users: Vec<UserId>,
+    vehicle_id: Option<VehicleId>,
+}
+
+const SUMMARY_HEADER: &[&str] = &[
+    "month",
+    "user",
+    "distance",
+    "prepaid",
+    "balance",
+    "total_distance",
+    "total_amount",
+];
+
+/// Exports one month, the summary of the month is shared by all users.
+async fn next_summary_month(mut state: ExportState<SummaryMonths>) -> PageResult<SummaryMonths> {
+    let Some(months) = state.next.as_mut() else {
+        return Ok(None);
+    };
+    let Some((month_start, month_end)) = months.months.pop_front() else {
+        return Ok(None);
+    };
+
+    let summaries = query_summaries(
+        &mut *state.db.acquire().await?,
+        Some(month_start),
+        Some(month_end),
+        months.vehicle_id,
+    )
+    .await?;
+
+    let rows = months
+        .users
+        .iter()
+        .map(|user| {
+            let summary = summaries.for_user(*user);
+
+            vec![
+                month_start.format("%Y-%m").to_string(),
+                state.names[user].to_string(),
+                summary.distance.to_string(),
+                format_cents(summary.prepaid as i64),
+                format_cents(summary.balances.get(user).copied().unwrap_or_default()),
+                summary.total_distance.to_string(),
+                format_cents(summary.total_amount as i64),
+            ]
+        })
+        .collect();
+
+    Ok(Some((rows, state)))
+}
+
+/// Exports the summary of each user for every month in the time frame.
+pub async fn query_export_summary(
+    db: &SqlitePool,
+    options: ExportSummaryOptions,
+) -> Result<impl Stream<Item = Result<Vec<u8>, ApiError>> + Send + 'static, ApiError> {
+    // without a start, the export begins with the first entry
+    let start = match options.start {
+        Some(start) => start,
+        None => {
+            let (first,): (Option<DateTime<Utc>>,) = sqlx::query_as(
+                "select min(created_at) from (select created_at from trips union all select created_at from expenses)",
+            )
+            .fetch_one(db)
+            .await?;
+
+            first.unwrap_or_else(Utc::now)
+        }
+    };
+    let end = options.end.unwrap_or_else(Utc::now);
+
+    let mut state = ExportState::new(
+        db,
+        SummaryMonths {
+            months: months_between(start, end).into(),
+            users: vec![],
+            vehicle_id: options.vehicle_id,
+        },
+    )
+    .await?;
+
+    if let Some(months) = state.next.as_mut() {
+        months.users = utils::sorted_vec(
+            state
+                .names
+                .keys()
+                .copied()
+                .filter(|id| options.users.is_empty() || options.users.contains(id)),
+        );
+    }
+
+    Ok(csv_stream(SUMMARY_HEADER, state, next_summary_month))
+}
+
+pub async fn export_trips(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<ListTripsOptions>,
+) -> Result<Response, ApiError> {
+    let rows = query_export_trips(auth_session.backend.db().await, options).await?;
+
+    Ok(csv_response("trips.csv", rows))
+}
+
+pub async fn export_expenses(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<ListExpensesOptions>,
+) -> Result<Response, ApiError> {
+    let rows = query_export_expenses(auth_session.backend.db().await, options).await?;
+
+    Ok(csv_response("expenses.csv", rows))
+}
+
+pub async fn export_summary(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<ExportSummaryOptions>,
+) -> Result<Response, ApiError> {
+    let rows = query_export_summary(auth_session.backend.db().await, options).await?;
+
+    Ok(csv_response("summary.csv", rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    async fn collect(
+        rows: Result<impl Stream<Item = Result<Vec<u8>, ApiError>>, ApiError>,
+    ) -> String {
+        let data: Vec<u8> = rows.unwrap().try_concat().await.unwrap();
+
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn test_format_cents() {
+        assert_eq!("0.00", format_cents(0));
+        assert_eq!("0.05", format_cents(5));
+        assert_eq!("12.34", format_cents(1234));
+        assert_eq!("-1.50", format_cents(-150));
+    }
+
+    #[test]
+    fn test_months_between() {
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 10, 0, 0, 0).unwrap();
+
+        assert_eq!(
+            vec![
+                (
+                    start,
+                    Utc.with_ymd_and_hms(2023, 11, 30, 23, 59, 59).unwrap()
+                ),
+                (
+                    Utc.with_ymd_and_hms(2023, 12, 1, 0, 0, 0).unwrap(),
+                    Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap()
+                ),
+                (Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(), end),
+            ],
+            months_between(start, end)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_trips() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end, description) values (1, 1, '2024-03-01T10:00:00+00:00', 0, 100, 'Holiday, part 1')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("insert into trip_users (trip_id, user_id) values (1, 1), (1, 2)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let data = collect(
+            query_export_trips(
+                &db,
+                ListTripsOptions {
+                    start: None,
+                    end: None,
+                    users: vec![],
+                    vehicle_id: None,
+                    ..Default::default()
+                },
+            )
+            .await,
+        )
+        .await;
+
+        assert_eq!(
+            "date,vehicle,start,end,distance,users,price,description,classification,destination,purpose,business_partner\n\
+             2024-03-01,Default,0,100,100,\"Alice, Bob\",13.90,\"Holiday, part 1\",private,,,\n",
+            data
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_summary() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end) values \
+             (1, 1, '2024-01-10T00:00:00Z', 0, 100), (2, 1, '2024-02-10T00:00:00Z', 100, 400); \
+             insert into trip_users (trip_id, user_id) values (1, 1), (2, 2); \
+             insert into expenses (id, vehicle_id, created_at, amount) values (1, 1, '2024-02-01T00:00:00Z', 3000); \
+             insert into expense_users (expense_id, user_id) values (1, 1)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let data = collect(
+            query_export_summary(
+                &db,
+                ExportSummaryOptions {
+                    start: None,
+                    end: Some(Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap()),
+                    users: vec![],
+                    vehicle_id: None,
+                },
+            )
+            .await,
+        )
+        .await;
+
+        assert_eq!(
+            "month,user,distance,prepaid,balance,total_distance,total_amount\n\
+             2024-01,Alice,100,0.00,0.00,100,0.00\n\
+             2024-01,Bob,0,0.00,0.00,100,0.00\n\
+             2024-02,Alice,0,30.00,30.00,300,30.00\n\
+             2024-02,Bob,300,0.00,-30.00,300,30.00\n",
+            data
+        );
+    }
+}
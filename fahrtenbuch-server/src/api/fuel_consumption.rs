@@ -0,0 +1,64 @@
+use axum::extract::Query;
+use axum_messages::Messages;
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::api::refuel::{self, query_refuels, Consumption, RefuelOptions};
+use crate::api::vehicle::{self, VehicleId};
+use crate::auth::AuthSession;
+use crate::response::{ApiError, ApiResult};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FuelConsumption {
+    pub vehicle_id: VehicleId,
+    /// The consumption between each two full tanks in the time frame.
+    pub intervals: Vec<Consumption>,
+    /// The consumption over all intervals, `None` if there are less than two full tanks.
+    pub litres_per_100km: Option<f64>,
+}
+
+/// Calculates the consumption of a vehicle, the vehicle can be omitted if there is only one.
+pub async fn query_fuel_consumption(
+    db: &SqlitePool,
+    options: RefuelOptions,
+) -> Result<FuelConsumption, ApiError> {
+    let vehicle_id =
+        vehicle::resolve_vehicle(&mut *db.acquire().await?, options.vehicle_id).await?;
+
+    let refuels = query_refuels(
+        db,
+        RefuelOptions {
+            vehicle_id: Some(vehicle_id),
+            ..options
+        },
+    )
+    .await?;
+
+    let intervals = refuel::consumption(&refuels);
+
+    let distance = intervals
+        .iter()
+        .map(|interval| interval.end - interval.start)
+        .sum::<i64>();
+    let millilitres = intervals
+        .iter()
+        .map(|interval| interval.millilitres)
+        .sum::<i64>();
+
+    Ok(FuelConsumption {
+        vehicle_id,
+        intervals,
+        litres_per_100km: (distance > 0).then(|| millilitres as f64 / distance as f64 / 10.0),
+    })
+}
+
+pub async fn fuel_consumption(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<RefuelOptions>,
+) -> ApiResult<FuelConsumption> {
+    query_fuel_consumption(auth_session.backend.db().await, options)
+        .await
+        .into()
+}
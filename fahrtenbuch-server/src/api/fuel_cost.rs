@@ -0,0 +1,152 @@
+use axum::extract::Query;
+use axum_messages::Messages;
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::api::list_trips::{query_trips, ListTripsOptions};
+use crate::api::refuel::{self, query_refuels, RefuelOptions};
+use crate::api::vehicle::{self, VehicleId};
+use crate::auth::AuthSession;
+use crate::response::{ApiError, ApiResult};
+
+/// The real cost of the fuel compared to the price of the trips.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FuelCost {
+    pub vehicle_id: VehicleId,
+    /// The distance driven in the time frame.
+    pub distance: u64,
+    /// The money spent on fuel in the time frame in cents.
+    pub fuel_cost: u64,
+    /// The cost of the fuel per kilometre in tenths of a cent, like the tariffs.
+    pub fuel_cost_per_km: Option<u64>,
+    /// The price of the trips in the time frame in cents.
+    pub trip_price: u64,
+    /// The price of the trips per kilometre in tenths of a cent.
+    pub trip_price_per_km: Option<u64>,
+}
+
+/// Returns the cost in tenths of a cent per kilometre, rounded to the nearest value.
+fn per_km(cents: u64, distance: u64) -> Option<u64> {
+    (distance > 0).then(|| (cents * 10 + distance / 2) / distance)
+}
+
+/// Compares the cost of the fuel with the price of the trips of a vehicle, the
+/// vehicle can be omitted if there is only one.
+pub async fn query_fuel_cost(
+    db: &SqlitePool,
+    options: RefuelOptions,
+) -> Result<FuelCost, ApiError> {
+    let vehicle_id =
+        vehicle::resolve_vehicle(&mut *db.acquire().await?, options.vehicle_id).await?;
+
+    let trips = query_trips(
+        &mut *db.acquire().await?,
+        ListTripsOptions {
+            start: options.start,
+            end: options.end,
+            vehicle_id: Some(vehicle_id),
+            ..Default::default()
+        },
+    )
+    .await?
+    .items;
+
+    let refuels = query_refuels(
+        db,
+        RefuelOptions {
+            vehicle_id: Some(vehicle_id),
+            ..options
+        },
+    )
+    .await?;
+
+    let distance = trips.iter().map(|trip| trip.distance()).sum::<u64>();
+    // this is the amount of the expenses of the refuels
+    let fuel_cost = refuels
+        .iter()
+        .map(|refuel| refuel::price_for(refuel.millilitres as u64, refuel.price_per_litre as u64))
+        .sum::<u64>();
+    let trip_price = trips.iter().map(|trip| trip.price).sum::<u64>();
+
+    Ok(FuelCost {
+        vehicle_id,
+        distance,
+        fuel_cost,
+        fuel_cost_per_km: per_km(fuel_cost, distance),
+        trip_price,
+        trip_price_per_km: per_km(trip_price, distance),
+    })
+}
+
+pub async fn fuel_cost(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<RefuelOptions>,
+) -> ApiResult<FuelCost> {
+    query_fuel_cost(auth_session.backend.db().await, options)
+        .await
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::api::add_refuel::{query_add_refuel, RefuelData};
+    use crate::utils;
+
+    #[tokio::test]
+    async fn test_fuel_cost_per_km() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end) values (1, 1, '2024-01-01T00:00:00Z', 0, 500); \
+             insert into trip_users (trip_id, user_id) values (1, 1)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // 40 l at 1.799 €/l = 71.96 €
+        let data = RefuelData {
+            vehicle_id: None,
+            created_at: Some("2024-01-02T00:00:00Z".parse().unwrap()),
+            odometer: 500,
+            millilitres: 40_000,
+            price_per_litre: 1799,
+            full_tank: true,
+            station: None,
+            users: HashSet::from([2]),
+        };
+        utils::transaction(&db, |tx| Box::pin(query_add_refuel(tx, Some(2), data)))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            FuelCost {
+                vehicle_id: 1,
+                distance: 500,
+                fuel_cost: 7196,
+                // 0.14392 €/km
+                fuel_cost_per_km: Some(144),
+                // the default tariff of 0.139 €/km
+                trip_price: 6950,
+                trip_price_per_km: Some(139),
+            },
+            query_fuel_cost(&db, Default::default()).await.unwrap()
+        );
+
+        // the refuel is an expense of bob
+        let (amount, user): (i64, i64) = sqlx::query_as(
+            "select expenses.amount, user_id from expenses join expense_users on expense_users.expense_id = expenses.id",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!((7196, 2), (amount, user));
+    }
+}
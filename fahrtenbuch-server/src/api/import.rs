@@ -0,0 +1,1044 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use axum::extract::Query;
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::api::add_expense::{self, query_add_expense};
+use crate::api::add_trip::{self, insert_trip, TripValidationConfig};
+use crate::api::trip::Classification;
+use crate::api::vehicle::{self, VehicleId};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult, FieldError};
+use crate::username::Username;
+use crate::utils;
+
+/// A user can be referenced by their id or by their username.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum UserRef {
+    Id(UserId),
+    Name(String),
+}
+
+impl FromStr for UserRef {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        Ok(match value.parse() {
+            Ok(id) => Self::Id(id),
+            Err(_) => Self::Name(value.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for UserRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{}", id),
+            Self::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A vehicle can be referenced by its id or by its name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(untagged)]
+pub enum VehicleRef {
+    Id(VehicleId),
+    Name(String),
+}
+
+/// A value of a row that is parsed when the row is imported, so an invalid value is
+/// reported for the row instead of rejecting the whole import.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ImportValue<T> {
+    Parsed(T),
+    Raw(String),
+}
+
+impl<T> ImportValue<T> {
+    fn parse(self, parse: fn(&str) -> Result<T, ApiError>) -> Result<T, ApiError> {
+        match self {
+            Self::Parsed(value) => Ok(value),
+            Self::Raw(value) => parse(&value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportTrip {
+    /// The vehicle that has been driven, can be omitted if there is only one.
+    #[serde(default)]
+    pub vehicle: Option<VehicleRef>,
+    #[serde(default)]
+    pub created_at: Option<ImportValue<DateTime<Utc>>>,
+    /// The start and end are required, but a missing one is reported for the row
+    /// instead of rejecting the whole import.
+    #[serde(default)]
+    pub start: Option<i64>,
+    #[serde(default)]
+    pub end: Option<i64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub classification: Classification,
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub purpose: Option<String>,
+    #[serde(default)]
+    pub business_partner: Option<String>,
+    pub users: Vec<UserRef>,
+    /// Allows the trip to start the odometer chain of its vehicle, even though there is
+    /// no trip ending at its start.
+    ///
+    /// This is not necessary for the first trip of a vehicle in the import, unless the
+    /// vehicle already has earlier trips.
+    #[serde(default)]
+    pub disable_start_check: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportExpense {
+    /// The vehicle the expense was made for, can be omitted if there is only one.
+    #[serde(default)]
+    pub vehicle: Option<VehicleRef>,
+    #[serde(default)]
+    pub created_at: Option<ImportValue<DateTime<Utc>>>,
+    /// The amount in cents, or in euros like `12.34` as a string. It is required, but
+    /// a missing amount is reported for the row like the start and end of a trip.
+    #[serde(default)]
+    pub amount: Option<ImportValue<u64>>,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub users: Vec<UserRef>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImportData {
+    /// Only validate the data, nothing will be stored.
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub trips: Vec<ImportTrip>,
+    #[serde(default)]
+    pub expenses: Vec<ImportExpense>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowKind {
+    Trip,
+    Expense,
+}
+
+/// Why a row of the import could not be imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowError {
+    pub kind: RowKind,
+    /// The index of the row in the provided data (starting at 0).
+    pub row: usize,
+    pub message: String,
+    pub details: Vec<FieldError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportResult {
+    pub dry_run: bool,
+    /// Whether the data has been stored. This only happens if there are no errors.
+    pub committed: bool,
+    pub trips: usize,
+    pub expenses: usize,
+    pub errors: Vec<RowError>,
+}
+
+async fn resolve_users(
+    db: &mut SqliteConnection,
+    users: &[UserRef],
+) -> Result<HashSet<UserId>, ApiError> {
+    let mut result = HashSet::new();
+
+    for user in users {
+        let value: Option<(UserId,)> = match user {
+            UserRef::Id(id) => {
+                sqlx::query_as("select id from users where id = ?")
+                    .bind(id)
+                    .fetch_optional(&mut *db)
+                    .await?
+            }
+            UserRef::Name(name) => {
+                sqlx::query_as("select id from users where username = ?")
+                    .bind(Username::from_str(name)?)
+                    .fetch_optional(&mut *db)
+                    .await?
+            }
+        };
+
+        match value {
+            Some((id,)) => result.insert(id),
+            None => {
+                return Err(ApiError::invalid_field(
+                    "users",
+                    format!("The user '{}' does not exist", user),
+                ))
+            }
+        };
+    }
+
+    Ok(result)
+}
+
+async fn resolve_vehicle(
+    db: &mut SqliteConnection,
+    vehicle: &Option<VehicleRef>,
+) -> Result<Option<VehicleId>, ApiError> {
+    match vehicle {
+        None => Ok(None),
+        Some(VehicleRef::Id(id)) => Ok(Some(*id)),
+        Some(VehicleRef::Name(name)) => {
+            let value: Option<(VehicleId,)> =
+                sqlx::query_as("select id from vehicles where name = ?")
+                    .bind(name.trim())
+                    .fetch_optional(&mut *db)
+                    .await?;
+
+            match value {
+                Some((id,)) => Ok(Some(id)),
+                None => Err(ApiError::invalid_field(
+                    "vehicle",
+                    format!("The vehicle '{}' does not exist", name),
+                )),
+            }
+        }
+    }
+}
+
+/// Resolves the vehicle of the trip, falling back to the only vehicle.
+async fn resolve_trip_vehicle(
+    db: &mut SqliteConnection,
+    trip: &ImportTrip,
+) -> Result<VehicleId, ApiError> {
+    let vehicle_id = resolve_vehicle(&mut *db, &trip.vehicle).await?;
+
+    vehicle::resolve_vehicle(db, vehicle_id).await
+}
+
+async fn import_trip(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    vehicle_id: VehicleId,
+    trip: ImportTrip,
+    starts_chain: bool,
+) -> Result<(), ApiError> {
+    let Some(start) = trip.start else {
+        return Err(ApiError::invalid_field("start", "The trip has no start"));
+    };
+    let Some(end) = trip.end else {
+        return Err(ApiError::invalid_field("end", "The trip has no end"));
+    };
+    let created_at = trip
+        .created_at
+        .map(|date| date.parse(parse_date))
+        .transpose()?;
+
+    let users = resolve_users(&mut *db, &trip.users).await?;
+    let disable_start_check = trip.disable_start_check || starts_chain;
+
+    insert_trip(
+        db,
+        actor,
+        add_trip::TripData {
+            vehicle_id: Some(vehicle_id),
+            created_at,
+            start,
+            end,
+            description: trip.description,
+            classification: trip.classification,
+            destination: trip.destination,
+            purpose: trip.purpose,
+            business_partner: trip.business_partner,
+            users,
+            disable_start_check,
+        },
+        TripValidationConfig {
+            disable_start_check,
+            // the history before the existing trips can be imported as well
+            only_reject_overlaps: true,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Whether the trip would be the first trip of its vehicle.
+async fn is_first_trip(
+    db: &mut SqliteConnection,
+    vehicle_id: VehicleId,
+    start: Option<i64>,
+) -> Result<bool, ApiError> {
+    let Some(start) = start else {
+        return Ok(false);
+    };
+
+    let (earlier,): (i64,) =
+        sqlx::query_as("select count(*) from trips where vehicle_id = ? and start < ?")
+            .bind(vehicle_id)
+            .bind(start)
+            .fetch_one(db)
+            .await?;
+
+    Ok(earlier == 0)
+}
+
+async fn import_expense(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    expense: ImportExpense,
+) -> Result<(), ApiError> {
+    let Some(amount) = expense.amount else {
+        return Err(ApiError::invalid_field(
+            "amount",
+            "The expense has no amount",
+        ));
+    };
+    let amount = amount.parse(parse_amount)?;
+    let created_at = expense
+        .created_at
+        .map(|date| date.parse(parse_date))
+        .transpose()?;
+
+    let vehicle_id = resolve_vehicle(&mut *db, &expense.vehicle).await?;
+    let users = resolve_users(&mut *db, &expense.users).await?;
+
+    query_add_expense(
+        db,
+        actor,
+        add_expense::ExpenseData {
+            vehicle_id,
+            created_at,
+            amount,
+            description: expense.description,
+            category_id: None,
+            users,
+            shares: Default::default(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Converts the error of a row into a report for the client.
+///
+/// Internal errors are not caused by the row, so they abort the whole import.
+fn row_error(kind: RowKind, row: usize, error: ApiError) -> Result<RowError, ApiError> {
+    let details = match &error {
+        ApiError::Internal(_) => return Err(error),
+        ApiError::Validation { details, .. } => details.clone(),
+        _ => Vec::new(),
+    };
+
+    Ok(RowError {
+        kind,
+        row,
+        message: error.to_string(),
+        details,
+    })
+}
+
+/// Releases the savepoint of a row, its changes are rolled back unless it is kept.
+///
+/// The savepoints are created manually, because the transaction of the import has
+/// been started with `begin immediate`, which sqlx does not know about.
+async fn end_savepoint(db: &mut SqliteConnection, keep: bool) -> Result<(), sqlx::Error> {
+    if !keep {
+        sqlx::query("rollback to import_row")
+            .execute(&mut *db)
+            .await?;
+    }
+
+    sqlx::query("release import_row").execute(db).await?;
+
+    Ok(())
+}
+
+/// Imports all rows in the given transaction.
+///
+/// Each row is imported in its own savepoint, so a failed row does not affect the
+/// other rows. The trips are imported in the order of the odometer chain, so each
+/// trip is validated against the trips that have been imported before it.
+async fn import_rows(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: ImportData,
+) -> Result<(usize, usize, Vec<RowError>), ApiError> {
+    let mut errors = Vec::new();
+
+    // the same vehicle can be referenced by its id and its name
+    let mut trips = Vec::new();
+    for (row, trip) in data.trips.into_iter().enumerate() {
+        match resolve_trip_vehicle(&mut *db, &trip).await {
+            Ok(vehicle_id) => trips.push((vehicle_id, row, trip)),
+            Err(error) => errors.push(row_error(RowKind::Trip, row, error)?),
+        }
+    }
+    trips.sort_by_key(|(vehicle_id, row, trip)| (*vehicle_id, trip.start, *row));
+
+    let mut imported_trips = 0;
+    let mut previous_vehicle = None;
+    for (vehicle_id, row, trip) in trips {
+        // the first imported trip of a vehicle starts its chain, e.g. at the odometer
+        // reading of the first page of a paper logbook
+        let starts_chain = previous_vehicle != Some(vehicle_id)
+            && is_first_trip(&mut *db, vehicle_id, trip.start).await?;
+        previous_vehicle = Some(vehicle_id);
+
+        sqlx::query("savepoint import_row")
+            .execute(&mut *db)
+            .await?;
+
+        let result = import_trip(&mut *db, actor, vehicle_id, trip, starts_chain).await;
+        end_savepoint(&mut *db, result.is_ok()).await?;
+
+        match result {
+            Ok(()) => imported_trips += 1,
+            Err(error) => errors.push(row_error(RowKind::Trip, row, error)?),
+        }
+    }
+
+    let mut imported_expenses = 0;
+    for (row, expense) in data.expenses.into_iter().enumerate() {
+        sqlx::query("savepoint import_row")
+            .execute(&mut *db)
+            .await?;
+
+        let result = import_expense(&mut *db, actor, expense).await;
+        end_savepoint(&mut *db, result.is_ok()).await?;
+
+        match result {
+            Ok(()) => imported_expenses += 1,
+            Err(error) => errors.push(row_error(RowKind::Expense, row, error)?),
+        }
+    }
+
+    errors.sort_by_key(|error| (error.kind == RowKind::Expense, error.row));
+
+    Ok((imported_trips, imported_expenses, errors))
+}
+
+/// Imports the trips and expenses, either all of them are stored or none.
+pub async fn query_import(
+    db: &SqlitePool,
+    actor: Option<UserId>,
+    data: ImportData,
+) -> Result<ImportResult, ApiError> {
+    let dry_run = data.dry_run;
+
+    // the odometer chains are read before the trips are added, so the import takes
+    // the write lock from the start like the other changes
+    let (trips, expenses, errors) = utils::conditional_transaction(
+        db,
+        |tx| Box::pin(import_rows(tx, actor, data)),
+        |(_, _, errors)| !dry_run && errors.is_empty(),
+    )
+    .await?;
+
+    let committed = !dry_run && errors.is_empty();
+
+    Ok(ImportResult {
+        dry_run,
+        committed,
+        trips,
+        expenses,
+        errors,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportCsvOptions {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// A row of a csv file, the columns match the ones of the export.
+#[derive(Debug, Clone, Deserialize)]
+struct CsvRow {
+    date: Option<String>,
+    vehicle: Option<String>,
+    start: Option<i64>,
+    end: Option<i64>,
+    amount: Option<String>,
+    users: String,
+    description: Option<String>,
+    classification: Option<Classification>,
+    destination: Option<String>,
+    purpose: Option<String>,
+    business_partner: Option<String>,
+}
+
+fn parse_date(date: &str) -> Result<DateTime<Utc>, ApiError> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| ApiError::invalid_field("date", format!("Invalid date '{}'", date)))
+}
+
+/// Parses an amount in euros like `12.34` into cents.
+fn parse_amount(amount: &str) -> Result<u64, ApiError> {
+    let invalid = || ApiError::invalid_field("amount", format!("Invalid amount '{}'", amount));
+
+    let (euros, cents) = match amount.trim().split_once(['.', ',']) {
+        Some((euros, cents)) if cents.len() == 2 => (euros, cents.parse().map_err(|_| invalid())?),
+        // a single digit means tenths, e.g. `12.5`
+        Some((euros, cents)) if cents.len() == 1 => {
+            (euros, cents.parse::<u64>().map_err(|_| invalid())? * 10)
+        }
+        Some(_) => return Err(invalid()),
+        None => (amount.trim(), 0),
+    };
+
+    let euros: u64 = euros.parse().map_err(|_| invalid())?;
+
+    Ok(euros * 100 + cents)
+}
+
+fn parse_csv_rows(data: &str) -> Result<Vec<CsvRow>, ApiError> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes())
+        .deserialize()
+        .collect::<Result<Vec<CsvRow>, _>>()
+        .map_err(|error| ApiError::invalid_field("body", format!("Invalid csv: {}", error)))
+}
+
+impl CsvRow {
+    fn created_at(&self) -> Option<ImportValue<DateTime<Utc>>> {
+        non_empty(&self.date).map(ImportValue::Raw)
+    }
+
+    fn vehicle(&self) -> Option<VehicleRef> {
+        self.vehicle.clone().map(VehicleRef::Name)
+    }
+
+    /// The users are separated by commas, e.g. `Alice, Bob`.
+    fn users(&self) -> Vec<UserRef> {
+        self.users
+            .split(',')
+            .filter(|user| !user.trim().is_empty())
+            .map(|user| UserRef::from_str(user).unwrap())
+            .collect()
+    }
+
+    fn description(&self) -> Option<String> {
+        non_empty(&self.description)
+    }
+}
+
+/// Empty columns are treated as missing values.
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|value| !value.is_empty())
+}
+
+pub fn parse_csv_trips(data: &str) -> Result<Vec<ImportTrip>, ApiError> {
+    let mut trips = Vec::new();
+
+    for row in parse_csv_rows(data)? {
+        trips.push(ImportTrip {
+            vehicle: row.vehicle(),
+            created_at: row.created_at(),
+            start: row.start,
+            end: row.end,
+            description: row.description(),
+            classification: row.classification.unwrap_or_default(),
+            destination: non_empty(&row.destination),
+            purpose: non_empty(&row.purpose),
+            business_partner: non_empty(&row.business_partner),
+            users: row.users(),
+            disable_start_check: false,
+        });
+    }
+
+    Ok(trips)
+}
+
+pub fn parse_csv_expenses(data: &str) -> Result<Vec<ImportExpense>, ApiError> {
+    let mut expenses = Vec::new();
+
+    for row in parse_csv_rows(data)? {
+        expenses.push(ImportExpense {
+            vehicle: row.vehicle(),
+            created_at: row.created_at(),
+            amount: non_empty(&row.amount).map(ImportValue::Raw),
+            description: row.description(),
+            users: row.users(),
+        });
+    }
+
+    Ok(expenses)
+}
+
+pub async fn import(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<ImportData>,
+) -> ApiResult<ImportResult> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    query_import(auth_session.backend.db().await, Some(user.id()), data)
+        .await
+        .into()
+}
+
+pub async fn import_trips_csv(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<ImportCsvOptions>,
+    body: String,
+) -> ApiResult<ImportResult> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let trips = match parse_csv_trips(&body) {
+        Ok(trips) => trips,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let data = ImportData {
+        dry_run: options.dry_run,
+        trips,
+        ..Default::default()
+    };
+
+    query_import(auth_session.backend.db().await, Some(user.id()), data)
+        .await
+        .into()
+}
+
+pub async fn import_expenses_csv(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<ImportCsvOptions>,
+    body: String,
+) -> ApiResult<ImportResult> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let expenses = match parse_csv_expenses(&body) {
+        Ok(expenses) => expenses,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let data = ImportData {
+        dry_run: options.dry_run,
+        expenses,
+        ..Default::default()
+    };
+
+    query_import(auth_session.backend.db().await, Some(user.id()), data)
+        .await
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::utils;
+
+    fn trip(start: i64, end: i64, users: Vec<UserRef>) -> ImportTrip {
+        ImportTrip {
+            vehicle: None,
+            created_at: None,
+            start: Some(start),
+            end: Some(end),
+            description: None,
+            classification: Classification::Private,
+            destination: None,
+            purpose: None,
+            business_partner: None,
+            users,
+            disable_start_check: false,
+        }
+    }
+
+    async fn count(db: &SqlitePool, table: &str) -> i64 {
+        let (count,): (i64,) = sqlx::query_as(&format!("select count(*) from {}", table))
+            .fetch_one(db)
+            .await
+            .unwrap();
+
+        count
+    }
+
+    #[tokio::test]
+    async fn test_import_in_chain_order() {
+        let db = utils::test_db().await;
+
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: false,
+                // the trips are not in order, but form a valid chain
+                trips: vec![
+                    trip(100, 150, vec![UserRef::Name("Bob".to_string())]),
+                    trip(0, 100, vec![UserRef::Id(1)]),
+                ],
+                expenses: vec![ImportExpense {
+                    vehicle: Some(VehicleRef::Name("Default".to_string())),
+                    created_at: None,
+                    amount: Some(ImportValue::Parsed(1000)),
+                    description: None,
+                    users: vec![UserRef::Name("alice".to_string())],
+                }],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            ImportResult {
+                dry_run: false,
+                committed: true,
+                trips: 2,
+                expenses: 1,
+                errors: vec![],
+            },
+            result
+        );
+        assert_eq!(2, count(&db, "trips").await);
+        assert_eq!(1, count(&db, "expenses").await);
+    }
+
+    #[tokio::test]
+    async fn test_import_is_all_or_nothing() {
+        let db = utils::test_db().await;
+
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: false,
+                trips: vec![
+                    trip(0, 100, vec![UserRef::Id(1)]),
+                    // there is a gap between 100 and 120
+                    trip(120, 150, vec![UserRef::Id(1)]),
+                    trip(150, 200, vec![UserRef::Name("charlie".to_string())]),
+                ],
+                expenses: vec![],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(!result.committed);
+        assert_eq!(1, result.trips);
+        assert_eq!(
+            vec![(RowKind::Trip, 1), (RowKind::Trip, 2)],
+            result
+                .errors
+                .iter()
+                .map(|error| (error.kind.clone(), error.row))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, count(&db, "trips").await);
+    }
+
+    async fn list_chain(db: &SqlitePool) -> Vec<(i64, i64)> {
+        utils::list_chain(db, 1).await
+    }
+
+    #[tokio::test]
+    async fn test_import_logbook_with_odometer_reading() {
+        let db = utils::test_db().await;
+
+        // the paper logbook starts at the odometer reading of the car when it was bought
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: false,
+                trips: vec![
+                    trip(48_120, 48_200, vec![UserRef::Id(1)]),
+                    trip(48_000, 48_120, vec![UserRef::Id(2)]),
+                ],
+                expenses: vec![],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(result.committed, "{:?}", result.errors);
+        assert_eq!(
+            vec![(48_000, 48_120), (48_120, 48_200)],
+            list_chain(&db).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_history_before_existing_trips() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 1000, 1100).await;
+
+        let import = |trips| {
+            query_import(
+                &db,
+                Some(1),
+                ImportData {
+                    dry_run: false,
+                    trips,
+                    expenses: vec![],
+                },
+            )
+        };
+
+        // trips that overlap the existing ones are still rejected
+        let result = import(vec![trip(1050, 1200, vec![UserRef::Id(1)])])
+            .await
+            .unwrap();
+        assert!(!result.committed);
+
+        // a trip after the existing ones must still continue the chain
+        let result = import(vec![trip(1200, 1300, vec![UserRef::Id(1)])])
+            .await
+            .unwrap();
+        assert!(!result.committed);
+
+        let result = import(vec![
+            trip(900, 1000, vec![UserRef::Id(1)]),
+            trip(800, 900, vec![UserRef::Id(1)]),
+        ])
+        .await
+        .unwrap();
+        assert!(result.committed, "{:?}", result.errors);
+        assert_eq!(
+            vec![(800, 900), (900, 1000), (1000, 1100)],
+            list_chain(&db).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_sorts_by_the_resolved_vehicle() {
+        let db = utils::test_db().await;
+
+        let with_vehicle = |vehicle, start, end| ImportTrip {
+            vehicle,
+            ..trip(start, end, vec![UserRef::Id(1)])
+        };
+
+        // all rows reference the same vehicle, so they form one chain
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: false,
+                trips: vec![
+                    with_vehicle(Some(VehicleRef::Id(1)), 100, 150),
+                    with_vehicle(Some(VehicleRef::Name("Default".to_string())), 50, 100),
+                    with_vehicle(None, 150, 200),
+                    with_vehicle(Some(VehicleRef::Name("Default".to_string())), 0, 50),
+                ],
+                expenses: vec![],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(result.committed, "{:?}", result.errors);
+        assert_eq!(
+            vec![(0, 50), (50, 100), (100, 150), (150, 200)],
+            list_chain(&db).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_csv_row_without_end_is_reported() {
+        let db = utils::test_db().await;
+
+        let trips = parse_csv_trips(
+            "date,vehicle,start,end,distance,users,price,description\n\
+             2024-03-01,Default,0,100,100,Alice,13.90,\n\
+             2024-03-02,Default,100,,,Alice,,\n",
+        )
+        .unwrap();
+
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: true,
+                trips,
+                expenses: vec![],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(1, result.trips);
+        assert_eq!(
+            vec![(1, "end".to_string())],
+            result
+                .errors
+                .iter()
+                .map(|error| (error.row, error.details[0].field.clone()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_csv_values_are_reported_per_row() {
+        let db = utils::test_db().await;
+
+        let trips = parse_csv_trips(
+            "date,vehicle,start,end,distance,users,price,description\n\
+             2024-03-01,Default,0,100,100,Alice,13.90,\n\
+             yesterday,Default,100,120,20,Alice,2.78,\n",
+        )
+        .unwrap();
+        let expenses = parse_csv_expenses(
+            "date,vehicle,amount,users,description\n\
+             2024-03-01,Default,12.34,Alice,Oil\n\
+             2024-03-02,Default,,Alice,Wipers\n\
+             2024-03-03,Default,12.345,Alice,Tyres\n\
+             03/04/2024,Default,10,Alice,Wash\n",
+        )
+        .unwrap();
+
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: true,
+                trips,
+                expenses,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((1, 1), (result.trips, result.expenses));
+        assert_eq!(
+            vec![
+                (RowKind::Trip, 1, "date".to_string()),
+                (RowKind::Expense, 1, "amount".to_string()),
+                (RowKind::Expense, 2, "amount".to_string()),
+                (RowKind::Expense, 3, "date".to_string()),
+            ],
+            result
+                .errors
+                .iter()
+                .map(|error| (
+                    error.kind.clone(),
+                    error.row,
+                    error.details[0].field.clone()
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_does_not_store() {
+        let db = utils::test_db().await;
+
+        let result = query_import(
+            &db,
+            Some(1),
+            ImportData {
+                dry_run: true,
+                trips: vec![trip(0, 100, vec![UserRef::Id(1)])],
+                expenses: vec![],
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(result.errors.is_empty());
+        assert!(!result.committed);
+        assert_eq!(0, count(&db, "trips").await);
+    }
+
+    #[test]
+    fn test_parse_csv_trips() {
+        let trips = parse_csv_trips(
+            "date,vehicle,start,end,distance,users,price,description\n\
+             2024-03-01,Default,0,100,100,\"Alice, 2\",13.90,\n",
+        )
+        .unwrap();
+
+        assert_eq!(1, trips.len());
+        assert_eq!(
+            vec![UserRef::Name("Alice".to_string()), UserRef::Id(2)],
+            trips[0].users
+        );
+        assert_eq!(
+            Some(ImportValue::Raw("2024-03-01".to_string())),
+            trips[0].created_at
+        );
+        assert_eq!(None, trips[0].description);
+        assert_eq!(Classification::Private, trips[0].classification);
+    }
+
+    #[test]
+    fn test_parse_csv_business_trips() {
+        let trips = parse_csv_trips(
+            "date,vehicle,start,end,distance,users,price,description,classification,destination,purpose,business_partner\n\
+             2024-03-01,Default,0,100,100,Alice,13.90,,business,Berlin,Meeting,ACME GmbH\n\
+             2024-03-02,Default,100,120,20,Alice,2.78,,,,,\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (
+                    Classification::Business,
+                    Some("Berlin".to_string()),
+                    Some("Meeting".to_string()),
+                    Some("ACME GmbH".to_string())
+                ),
+                (Classification::Private, None, None, None),
+            ],
+            trips
+                .into_iter()
+                .map(|trip| (
+                    trip.classification,
+                    trip.destination,
+                    trip.purpose,
+                    trip.business_partner
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(1234, parse_amount("12.34").unwrap());
+        assert_eq!(1234, parse_amount("12,34").unwrap());
+        assert_eq!(1250, parse_amount("12.5").unwrap());
+        assert_eq!(1200, parse_amount("12").unwrap());
+        assert!(parse_amount("12.345").is_err());
+        assert!(parse_amount("abc").is_err());
+    }
+}
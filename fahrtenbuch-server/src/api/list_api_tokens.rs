@@ -0,0 +1,26 @@
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use crate::auth::{ApiToken, AuthSession};
+use crate::response::{ApiError, ApiResult};
+
+async fn query_api_tokens(auth_session: &AuthSession) -> Result<Vec<ApiToken>, ApiError> {
+    let Some(user) = &auth_session.user else {
+        return Err(ApiError::unauthorized("You are not logged in"));
+    };
+
+    let tokens = sqlx::query_as("select * from api_tokens where user_id = ? order by created_at")
+        .bind(user.id())
+        .fetch_all(auth_session.backend.db().await)
+        .await?;
+
+    Ok(tokens)
+}
+
+/// Lists the api tokens of the logged in user.
+pub async fn list_api_tokens(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<ApiToken>> {
+    query_api_tokens(&auth_session).await.into()
+}
@@ -0,0 +1,17 @@
+use axum_messages::Messages;
+
+use crate::api::category::{self, Category};
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+pub async fn list_categories(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<Category>> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    category::query_categories(&mut db).await.into()
+}
@@ -0,0 +1,16 @@
+use axum_messages::Messages;
+
+use crate::api::period::ClosedPeriod;
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+/// Lists the closed periods including the reopened ones, the oldest first.
+pub async fn list_closed_periods(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<ClosedPeriod>> {
+    sqlx::query_as("select * from closed_periods order by start, id")
+        .fetch_all(auth_session.backend.db().await)
+        .await
+        .into()
+}
@@ -1,4 +1,3 @@
-use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
 
 use axum::extract::Query;
@@ -7,13 +6,16 @@ use axum_messages::Messages;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
-use sqlx::{QueryBuilder, SqlitePool};
+use sqlx::{QueryBuilder, SqliteConnection};
 
+use crate::api::category::CategoryId;
+use crate::api::page::{Cursor, Page, PageOptions, SortOrder};
+use crate::api::vehicle::VehicleId;
 use crate::auth::{AuthBackendError, AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::response::{ApiError, ApiResult};
 use crate::utils::{self, SqlBuilderExt};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct ListExpensesOptions {
     #[serde(default)]
     pub start: Option<DateTime<Utc>>,
@@ -22,111 +24,428 @@ pub struct ListExpensesOptions {
     /// Only list expenses for specific user(s).
     #[serde(default)]
     pub users: Vec<UserId>,
+    /// Only list expenses of a specific vehicle.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
+    /// Only list expenses with a description that contains the text.
+    #[serde(default)]
+    pub search: Option<String>,
+    /// The minimum amount in cents.
+    #[serde(default)]
+    pub min_amount: Option<i64>,
+    /// The maximum amount in cents.
+    #[serde(default)]
+    pub max_amount: Option<i64>,
+    /// The maximum number of expenses to return, all if omitted.
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// The `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl ListExpensesOptions {
+    fn page(&self) -> PageOptions {
+        PageOptions {
+            limit: self.limit,
+            cursor: self.cursor,
+            sort: self.sort,
+        }
+    }
 }
 
 #[derive(Debug, Clone, FromRow)]
 pub struct ExpenseEntry {
     id: i64,
+    vehicle_id: Option<VehicleId>,
     created_at: DateTime<Utc>,
     amount: i64,
     description: Option<String>,
+    category_id: Option<CategoryId>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Expense {
     pub id: i64,
+    pub vehicle_id: Option<VehicleId>,
     pub created_at: DateTime<Utc>,
     pub amount: i64,
     pub description: Option<String>,
+    /// Expenses without a category are split by the driven distance.
+    pub category_id: Option<CategoryId>,
     pub users: HashSet<UserId>,
+    /// How much each user has paid, empty if the amount is split equally.
+    pub shares: HashMap<UserId, i64>,
 }
 
 impl Expense {
-    /// Returns the amount of money the user has prepaid for the expense.
-    pub fn amount_for(&self, user_id: UserId) -> u64 {
-        if self.users.contains(&user_id) {
-            let iterator = utils::divide_equally(self.amount as u64, self.users.len() as u64);
-            let sorted_users = utils::sorted_vec(self.users.iter());
-
-            iterator
-                .zip(sorted_users)
-                .find_map(
-                    |(amount, uid)| {
-                        if uid == &user_id {
-                            Some(amount)
-                        } else {
-                            None
-                        }
-                    },
-                )
-                .unwrap_or_default()
-        } else {
-            0
+    /// Returns the amount of money each user has prepaid, sorted by the user ids.
+    pub fn amounts(&self) -> Vec<(UserId, u64)> {
+        let users = utils::sorted_vec(self.users.iter().copied());
+
+        if !self.shares.is_empty() {
+            return users
+                .into_iter()
+                .map(|user| {
+                    (
+                        user,
+                        self.shares.get(&user).copied().unwrap_or_default() as u64,
+                    )
+                })
+                .collect();
         }
+
+        utils::divide_equally(self.amount as u64, self.users.len() as u64)
+            .zip(users)
+            .map(|(amount, user)| (user, amount))
+            .collect()
+    }
+}
+
+/// Ensures that the shares of an expense belong to its users and add up to its amount.
+///
+/// No shares mean that the amount is split equally.
+pub fn validate_shares(
+    amount: u64,
+    users: &HashSet<UserId>,
+    shares: &HashMap<UserId, u64>,
+) -> Result<(), ApiError> {
+    if shares.is_empty() {
+        return Ok(());
+    }
+
+    if shares.keys().collect::<HashSet<_>>() != users.iter().collect() {
+        return Err(ApiError::invalid_field(
+            "shares",
+            "Every user of the expense must have exactly one share",
+        ));
+    }
+
+    if shares.values().any(|share| *share == 0) {
+        return Err(ApiError::invalid_field(
+            "shares",
+            "A share must be greater than 0",
+        ));
+    }
+
+    let total = shares.values().sum::<u64>();
+    if total != amount {
+        return Err(ApiError::invalid_field(
+            "shares",
+            format!(
+                "The shares add up to {}, but the amount is {}",
+                total, amount
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Replaces the users of the expense and how much each of them has paid.
+pub async fn store_expense_users(
+    db: &mut SqliteConnection,
+    expense_id: i64,
+    users: &HashSet<UserId>,
+    shares: &HashMap<UserId, u64>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("delete from expense_users where expense_id = ?")
+        .bind(expense_id)
+        .execute(&mut *db)
+        .await?;
+
+    for user_id in utils::sorted_vec(users.iter()) {
+        sqlx::query("insert into expense_users (expense_id, user_id, amount) values (?, ?, ?)")
+            .bind(expense_id)
+            .bind(user_id)
+            .bind(shares.get(user_id).map(|share| *share as i64))
+            .execute(&mut *db)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the users that share the expense.
+pub async fn query_expense_users(
+    db: &mut SqliteConnection,
+    id: i64,
+) -> Result<HashSet<UserId>, ApiError> {
+    let existing: Option<(i64,)> = sqlx::query_as("select id from expenses where id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    if existing.is_none() {
+        return Err(ApiError::not_found(format!(
+            "The expense {} does not exist",
+            id
+        )));
     }
+
+    let users: Vec<(UserId,)> =
+        sqlx::query_as("select user_id from expense_users where expense_id = ?")
+            .bind(id)
+            .fetch_all(&mut *db)
+            .await?;
+
+    Ok(users.into_iter().map(|(user,)| user).collect())
 }
 
-async fn query_options(
-    db: &SqlitePool,
+/// The expenses are sorted by the time they were made.
+const SORT_KEY: &str = "cast(strftime('%s', created_at) as integer)";
+
+pub async fn query_expenses(
+    db: &mut SqliteConnection,
     options: ListExpensesOptions,
-) -> Result<Vec<Expense>, AuthBackendError> {
+) -> Result<Page<Expense>, AuthBackendError> {
+    let page = options.page();
     let mut builder = QueryBuilder::new("select * from expenses");
+    let mut filter = builder.filter();
 
-    if let Some(start) = options.start {
-        builder
-            .push(" where datetime(created_at, 'utc') >= ")
-            .push_utc_bind(start);
-    }
+    filter
+        .eq("vehicle_id", options.vehicle_id)
+        .date_range("created_at", options.start, options.end)
+        .contains("description", options.search.as_deref())
+        .range("amount", options.min_amount, options.max_amount);
 
-    if let Some(end) = options.end {
-        builder
-            .push(" and datetime(created_at, 'utc') <= ")
-            .push_utc_bind(end);
+    if !options.users.is_empty() {
+        filter.in_subquery("id", "select expense_id from expense_users", |users| {
+            users.any_of("user_id", options.users);
+        });
     }
 
-    let trip_entries: Vec<ExpenseEntry> = builder.build_query_as().fetch_all(db).await?;
+    page.push_cursor(&mut filter, SORT_KEY);
+    page.push_order_and_limit(&mut builder, SORT_KEY);
 
-    let mut users_builder = QueryBuilder::new("select expense_id, user_id from expense_users");
+    let trip_entries: Vec<ExpenseEntry> = builder.build_query_as().fetch_all(&mut *db).await?;
 
+    let mut users_builder =
+        QueryBuilder::new("select expense_id, user_id, amount from expense_users");
+
+    // all users of the expenses are returned, even if only some of them were requested
     users_builder
-        .push_in("expense_id", trip_entries.iter().map(|entry| entry.id))
-        .push_in("user_id", options.users);
+        .filter()
+        .any_of("expense_id", trip_entries.iter().map(|entry| entry.id));
 
-    // expense_id, users
-    let mut expense_mapping: HashMap<i64, HashSet<i64>> = users_builder
-        .build_query_as::<'_, (i64, i64)>()
+    // expense_id, (users, shares)
+    let mut expense_mapping: HashMap<i64, (HashSet<UserId>, HashMap<UserId, i64>)> = users_builder
+        .build_query_as::<'_, (i64, i64, Option<i64>)>()
         .fetch_all(db)
         .await?
         .into_iter()
-        .fold(HashMap::new(), |mut map, (expense_id, user_id)| {
-            map.entry(expense_id).or_default().insert(user_id);
+        .fold(HashMap::new(), |mut map, (expense_id, user_id, amount)| {
+            let (users, shares) = map.entry(expense_id).or_default();
+            users.insert(user_id);
+            if let Some(amount) = amount {
+                shares.insert(user_id, amount);
+            }
             map
         });
 
     let mut result = Vec::new();
     for entry in trip_entries {
-        let users = expense_mapping.remove(&entry.id).unwrap_or_default();
+        let (users, shares) = expense_mapping.remove(&entry.id).unwrap_or_default();
         result.push(Expense {
             id: entry.id,
+            vehicle_id: entry.vehicle_id,
             created_at: entry.created_at,
             amount: entry.amount,
             description: entry.description,
+            category_id: entry.category_id,
             users,
+            shares,
         });
     }
 
-    result.sort_by_cached_key(|element| Reverse(element.created_at));
-
-    Ok(result)
+    Ok(Page::new(result, &page, |expense| Cursor {
+        key: expense.created_at.timestamp(),
+        id: expense.id,
+    }))
 }
 
 pub async fn list_expenses(
     auth_session: AuthSession,
     _messages: Messages,
     Query(options): Query<ListExpensesOptions>,
-) -> ApiResult<Vec<Expense>> {
-    match query_options(auth_session.backend.db().await, options).await {
-        Ok(data) => ApiResult::ok(data),
-        Err(e) => ApiResult::error(format!("Failed to list_expenses: {:?}", e)),
+) -> ApiResult<Page<Expense>> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    query_expenses(&mut db, options).await.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_paginate_expenses_with_equal_dates() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into expenses (id, vehicle_id, created_at, amount) values \
+             (1, 1, '2024-03-01T10:00:00+00:00', 100), \
+             (2, 1, '2024-03-02T10:00:00+00:00', 200), \
+             (3, 1, '2024-03-02T10:00:00+00:00', 300), \
+             (4, 1, '2024-03-03T10:00:00+00:00', 400)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let options = ListExpensesOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let first = query_expenses(&mut db.acquire().await.unwrap(), options.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![4, 3],
+            first.items.iter().map(|e| e.id).collect::<Vec<_>>()
+        );
+
+        let second = query_expenses(
+            &mut db.acquire().await.unwrap(),
+            ListExpensesOptions {
+                cursor: first.next_cursor,
+                ..options
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![2, 1],
+            second.items.iter().map(|e| e.id).collect::<Vec<_>>()
+        );
+        assert_eq!(None, second.next_cursor);
+    }
+
+    struct Fixture {
+        id: i64,
+        vehicle_id: VehicleId,
+        created_at: &'static str,
+        amount: i64,
+        description: Option<&'static str>,
+        users: &'static [UserId],
+    }
+
+    const FIXTURES: [Fixture; 4] = [
+        Fixture {
+            id: 1,
+            vehicle_id: 1,
+            created_at: "2024-01-10T10:00:00+00:00",
+            amount: 5000,
+            description: Some("Fuel"),
+            users: &[1],
+        },
+        Fixture {
+            id: 2,
+            vehicle_id: 1,
+            created_at: "2024-02-10T10:00:00+00:00",
+            amount: 1500,
+            description: Some("Car wash"),
+            users: &[2],
+        },
+        Fixture {
+            id: 3,
+            vehicle_id: 2,
+            created_at: "2024-02-20T10:00:00+00:00",
+            amount: 8000,
+            description: Some("fuel and oil"),
+            users: &[1, 2],
+        },
+        Fixture {
+            id: 4,
+            vehicle_id: 2,
+            created_at: "2024-03-20T10:00:00+00:00",
+            amount: 300,
+            description: None,
+            users: &[1],
+        },
+    ];
+
+    #[tokio::test]
+    async fn test_every_filter_combination() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into vehicles (id, name) values (2, 'Van')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        for expense in &FIXTURES {
+            sqlx::query(
+                "insert into expenses (id, vehicle_id, created_at, amount, description) values (?, ?, ?, ?, ?)",
+            )
+            .bind(expense.id)
+            .bind(expense.vehicle_id)
+            .bind(expense.created_at)
+            .bind(expense.amount)
+            .bind(expense.description)
+            .execute(&db)
+            .await
+            .unwrap();
+
+            for user in expense.users {
+                sqlx::query("insert into expense_users (expense_id, user_id) values (?, ?)")
+                    .bind(expense.id)
+                    .bind(user)
+                    .execute(&db)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let start: DateTime<Utc> = "2024-02-01T00:00:00Z".parse().unwrap();
+        let end: DateTime<Utc> = "2024-03-01T00:00:00Z".parse().unwrap();
+
+        // every bit of the mask enables one of the filters
+        for mask in 0..(1 << 7) {
+            let enabled = |bit: u32| mask & (1 << bit) != 0;
+
+            let options = ListExpensesOptions {
+                start: enabled(0).then_some(start),
+                end: enabled(1).then_some(end),
+                users: if enabled(2) { vec![2] } else { vec![] },
+                vehicle_id: enabled(3).then_some(2),
+                search: enabled(4).then(|| "fuel".to_string()),
+                min_amount: enabled(5).then_some(1000),
+                max_amount: enabled(6).then_some(6000),
+                ..Default::default()
+            };
+
+            let expected = FIXTURES
+                .iter()
+                .filter(|expense| {
+                    let created_at: DateTime<Utc> = expense.created_at.parse().unwrap();
+
+                    options.start.is_none_or(|start| created_at >= start)
+                        && options.end.is_none_or(|end| created_at <= end)
+                        && (options.users.is_empty() || expense.users.contains(&2))
+                        && options.vehicle_id.is_none_or(|id| expense.vehicle_id == id)
+                        && (options.search.is_none()
+                            || expense
+                                .description
+                                .is_some_and(|d| d.to_lowercase().contains("fuel")))
+                        && options.min_amount.is_none_or(|min| expense.amount >= min)
+                        && options.max_amount.is_none_or(|max| expense.amount <= max)
+                })
+                .map(|expense| expense.id)
+                .collect::<HashSet<_>>();
+
+            let page = query_expenses(&mut db.acquire().await.unwrap(), options)
+                .await
+                .unwrap();
+            let ids = page.items.iter().map(|e| e.id).collect::<HashSet<_>>();
+
+            assert_eq!(expected, ids, "filters enabled: {:07b}", mask);
+        }
     }
 }
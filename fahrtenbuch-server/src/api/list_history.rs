@@ -0,0 +1,41 @@
+use axum::extract::Query;
+use axum_messages::Messages;
+use serde::Deserialize;
+
+use crate::audit::{self, AuditEntry, Entity};
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::{ApiError, ApiResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryOptions {
+    entity: Entity,
+    id: i64,
+}
+
+async fn query_history(
+    auth_session: &AuthSession,
+    options: HistoryOptions,
+) -> Result<Vec<AuditEntry>, ApiError> {
+    // the history of users is only interesting for the admins
+    let permission = match options.entity {
+        Entity::Trip | Entity::Expense | Entity::Settlement => Permission::Read,
+        Entity::User => Permission::Manage,
+    };
+    auth::require_permission(auth_session, permission).await?;
+
+    let mut db = auth_session.backend.db().await.acquire().await?;
+    let entries = audit::history(&mut db, options.entity, options.id).await?;
+
+    Ok(entries)
+}
+
+/// Lists all recorded changes of a trip, expense, settlement or user, the oldest first.
+///
+/// The history is kept after the entry has been deleted.
+pub async fn list_history(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<HistoryOptions>,
+) -> ApiResult<Vec<AuditEntry>> {
+    query_history(&auth_session, options).await.into()
+}
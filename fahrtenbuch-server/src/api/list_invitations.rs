@@ -0,0 +1,21 @@
+use axum_messages::Messages;
+
+use crate::auth::{self, AuthSession, Invitation, Permission};
+use crate::response::{ApiError, ApiResult};
+
+async fn query_invitations(auth_session: &AuthSession) -> Result<Vec<Invitation>, ApiError> {
+    auth::require_permission(auth_session, Permission::Manage).await?;
+
+    let invitations = sqlx::query_as("select * from invitations order by created_at desc")
+        .fetch_all(auth_session.backend.db().await)
+        .await?;
+
+    Ok(invitations)
+}
+
+pub async fn list_invitations(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<Invitation>> {
+    query_invitations(&auth_session).await.into()
+}
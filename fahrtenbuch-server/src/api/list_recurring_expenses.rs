@@ -0,0 +1,17 @@
+use axum_messages::Messages;
+
+use crate::api::recurring_expense::{query_recurring_expenses, RecurringExpense};
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+pub async fn list_recurring_expenses(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<RecurringExpense>> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    query_recurring_expenses(&mut db).await.into()
+}
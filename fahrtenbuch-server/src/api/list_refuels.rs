@@ -0,0 +1,16 @@
+use axum::extract::Query;
+use axum_messages::Messages;
+
+use crate::api::refuel::{query_refuels, Refuel, RefuelOptions};
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+pub async fn list_refuels(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<RefuelOptions>,
+) -> ApiResult<Vec<Refuel>> {
+    query_refuels(auth_session.backend.db().await, options)
+        .await
+        .into()
+}
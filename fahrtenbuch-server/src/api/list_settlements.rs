@@ -0,0 +1,120 @@
+use axum::extract::Query;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{QueryBuilder, SqliteConnection};
+
+use crate::api::settlement::Settlement;
+use crate::auth::{AuthSession, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils::SqlBuilderExt;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ListSettlementsOptions {
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    /// Only list settlements that were paid or received by specific user(s).
+    #[serde(default)]
+    pub users: Vec<UserId>,
+}
+
+/// Returns the settlements that settle the time frame, the oldest first.
+pub async fn query_settlements(
+    db: &mut SqliteConnection,
+    options: ListSettlementsOptions,
+) -> Result<Vec<Settlement>, ApiError> {
+    let mut builder = QueryBuilder::new("select * from settlements");
+    let mut filter = builder.filter();
+
+    // a payment after the end of a period can still settle it
+    filter.date_range(
+        "coalesce(settles_at, created_at)",
+        options.start,
+        options.end,
+    );
+
+    if !options.users.is_empty() {
+        // the user can be on either side of the settlement
+        let condition = filter.condition().push("id in (select id from settlements");
+        condition
+            .filter()
+            .any_of("from_user_id", options.users.clone());
+        condition.push(" union select id from settlements");
+        condition.filter().any_of("to_user_id", options.users);
+        condition.push(")");
+    }
+
+    builder.push(" order by created_at, id");
+
+    Ok(builder.build_query_as().fetch_all(db).await?)
+}
+
+pub async fn list_settlements(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<ListSettlementsOptions>,
+) -> ApiResult<Vec<Settlement>> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    query_settlements(&mut db, options).await.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::utils;
+
+    #[tokio::test]
+    async fn test_filter_by_users() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into users (id, username, password) values (3, 'charlie', '')")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            "insert into settlements (id, from_user_id, to_user_id, amount, created_at) values
+                (1, 1, 2, 100, '2024-01-01T00:00:00Z'),
+                (2, 2, 3, 200, '2024-01-02T00:00:00Z'),
+                (3, 3, 1, 300, '2024-02-01T00:00:00Z')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let ids = |options| async {
+            query_settlements(&mut db.acquire().await.unwrap(), options)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|settlement| settlement.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![1, 3],
+            ids(ListSettlementsOptions {
+                users: vec![1],
+                ..Default::default()
+            })
+            .await
+        );
+        assert_eq!(
+            vec![1, 2],
+            ids(ListSettlementsOptions {
+                end: Some("2024-01-31T00:00:00Z".parse().unwrap()),
+                users: vec![1, 2],
+                ..Default::default()
+            })
+            .await
+        );
+    }
+}
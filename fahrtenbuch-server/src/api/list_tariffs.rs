@@ -0,0 +1,15 @@
+use axum_messages::Messages;
+
+use crate::api::tariff::Tariff;
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+pub async fn list_tariffs(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<Tariff>> {
+    sqlx::query_as("select * from tariffs order by valid_from")
+        .fetch_all(auth_session.backend.db().await)
+        .await
+        .into()
+}
@@ -1,4 +1,3 @@
-use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
 
 use axum::extract::Query;
@@ -7,14 +6,17 @@ use axum_messages::Messages;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::prelude::FromRow;
-use sqlx::{QueryBuilder, SqlitePool};
+use sqlx::{QueryBuilder, SqliteConnection};
 
-use crate::api::trip::Trip;
+use crate::api::page::{Cursor, Page, PageOptions, SortOrder};
+use crate::api::tariff::Tariffs;
+use crate::api::trip::{Classification, Trip};
+use crate::api::vehicle::VehicleId;
 use crate::auth::{AuthBackendError, AuthSession, UserId};
 use crate::response::ApiResult;
 use crate::utils::SqlBuilderExt;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct ListTripsOptions {
     #[serde(default)]
     pub start: Option<DateTime<Utc>>,
@@ -23,29 +25,75 @@ pub struct ListTripsOptions {
     /// Only list trips for specific user(s).
     #[serde(default)]
     pub users: Vec<UserId>,
+    /// Only list trips of a specific vehicle.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
+    /// Only list trips with a description that contains the text.
+    #[serde(default)]
+    pub search: Option<String>,
+    /// Only list business, commuting or private trips.
+    #[serde(default)]
+    pub classification: Option<Classification>,
+    /// Only list trips with a destination that contains the text.
+    #[serde(default)]
+    pub destination: Option<String>,
+    /// Only list trips with a purpose that contains the text.
+    #[serde(default)]
+    pub purpose: Option<String>,
+    /// Only list trips with a business partner that contains the text.
+    #[serde(default)]
+    pub business_partner: Option<String>,
+    #[serde(default)]
+    pub min_distance: Option<i64>,
+    #[serde(default)]
+    pub max_distance: Option<i64>,
+    /// The maximum number of trips to return, all if omitted.
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// The `next_cursor` of the previous page.
+    #[serde(default)]
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl ListTripsOptions {
+    fn page(&self) -> PageOptions {
+        PageOptions {
+            limit: self.limit,
+            cursor: self.cursor,
+            sort: self.sort,
+        }
+    }
 }
 
 #[derive(Debug, Clone, FromRow)]
 pub struct TripEntry {
     pub id: i64,
+    pub vehicle_id: VehicleId,
     pub created_at: DateTime<Utc>,
     pub start: i64,
     pub end: i64,
     pub description: Option<String>,
+    pub classification: Classification,
+    pub destination: Option<String>,
+    pub purpose: Option<String>,
+    pub business_partner: Option<String>,
+    pub version: i64,
+    pub hash: Option<String>,
 }
 
-const PRICE_PER_KM: f32 = 0.139;
-
 pub async fn list_trip_users(
-    db: &SqlitePool,
+    db: &mut SqliteConnection,
     trip_ids: impl Iterator<Item = i64>,
     users: Vec<UserId>,
 ) -> Result<Vec<(i64, UserId)>, AuthBackendError> {
     let mut users_builder = QueryBuilder::new("select trip_id, user_id from trip_users");
 
     users_builder
-        .push_in("trip_id", trip_ids)
-        .push_in("user_id", users);
+        .filter()
+        .any_of("trip_id", trip_ids)
+        .any_of("user_id", users);
 
     Ok(users_builder
         .build_query_as::<'_, (i64, i64)>()
@@ -53,62 +101,389 @@ pub async fn list_trip_users(
         .await?)
 }
 
-async fn query_options(
-    db: &SqlitePool,
+/// The trips of a vehicle are sorted by its odometer. The odometers of different
+/// vehicles can not be compared, so the trips of all vehicles are sorted by the time
+/// they were made.
+fn sort_key(vehicle_id: Option<VehicleId>) -> &'static str {
+    match vehicle_id {
+        Some(_) => "end",
+        None => "cast(strftime('%s', created_at) as integer)",
+    }
+}
+
+pub async fn query_trips(
+    db: &mut SqliteConnection,
     options: ListTripsOptions,
-) -> Result<Vec<Trip>, AuthBackendError> {
+) -> Result<Page<Trip>, AuthBackendError> {
+    let page = options.page();
+    let vehicle_id = options.vehicle_id;
     let mut builder = QueryBuilder::new("select * from trips");
+    let mut filter = builder.filter();
 
-    if let Some(start) = options.start {
-        builder
-            .push(" where datetime(created_at, 'utc') >= ")
-            .push_utc_bind(start);
-    }
+    filter
+        .eq("vehicle_id", options.vehicle_id)
+        .date_range("created_at", options.start, options.end)
+        .contains("description", options.search.as_deref())
+        .eq("classification", options.classification)
+        .contains("destination", options.destination.as_deref())
+        .contains("purpose", options.purpose.as_deref())
+        .contains("business_partner", options.business_partner.as_deref())
+        .range("end - start", options.min_distance, options.max_distance);
 
-    if let Some(end) = options.end {
-        builder
-            .push(" and datetime(created_at, 'utc') <= ")
-            .push_utc_bind(end);
+    if !options.users.is_empty() {
+        filter.in_subquery("id", "select trip_id from trip_users", |users| {
+            users.any_of("user_id", options.users);
+        });
     }
 
-    let trip_entries: Vec<TripEntry> = builder.build_query_as().fetch_all(db).await?;
+    page.push_cursor(&mut filter, sort_key(vehicle_id));
+    page.push_order_and_limit(&mut builder, sort_key(vehicle_id));
+
+    let trip_entries: Vec<TripEntry> = builder.build_query_as().fetch_all(&mut *db).await?;
 
     // trip_id, users
-    let mut trip_mapping: HashMap<i64, HashSet<i64>> =
-        list_trip_users(db, trip_entries.iter().map(|entry| entry.id), options.users)
-            .await?
-            .into_iter()
-            .fold(HashMap::new(), |mut map, (trip_id, user_id)| {
-                map.entry(trip_id).or_default().insert(user_id);
-                map
-            });
+    let mut trip_mapping: HashMap<i64, HashSet<i64>> = list_trip_users(
+        &mut *db,
+        trip_entries.iter().map(|entry| entry.id),
+        // all users of the trips are returned, even if only some of them were requested
+        vec![],
+    )
+    .await?
+    .into_iter()
+    .fold(HashMap::new(), |mut map, (trip_id, user_id)| {
+        map.entry(trip_id).or_default().insert(user_id);
+        map
+    });
+
+    let tariffs = Tariffs::load(db).await?;
 
     let mut result = Vec::new();
     for entry in trip_entries {
         let users = trip_mapping.remove(&entry.id).unwrap_or_default();
         result.push(Trip {
             id: entry.id,
+            vehicle_id: entry.vehicle_id,
             created_at: entry.created_at,
             start: entry.start as u64,
             end: entry.end as u64,
             description: entry.description,
+            classification: entry.classification,
+            destination: entry.destination,
+            purpose: entry.purpose,
+            business_partner: entry.business_partner,
             users,
-            price: (((entry.end as u64 - entry.start as u64) as f32 * PRICE_PER_KM) * 100.0) as u64,
+            price: tariffs.price(entry.created_at, (entry.end - entry.start) as u64),
+            version: entry.version,
+            hash: entry.hash,
         });
     }
 
-    result.sort_by_cached_key(|element| Reverse(element.end));
-
-    Ok(result)
+    Ok(Page::new(result, &page, |trip| Cursor {
+        key: match vehicle_id {
+            Some(_) => trip.end as i64,
+            None => trip.created_at.timestamp(),
+        },
+        id: trip.id,
+    }))
 }
 
 pub async fn list_trips(
     auth_session: AuthSession,
     _messages: Messages,
     Query(options): Query<ListTripsOptions>,
-) -> ApiResult<Vec<Trip>> {
-    match query_options(auth_session.backend.db().await, options).await {
-        Ok(data) => ApiResult::ok(data),
-        Err(e) => ApiResult::error(format!("Failed to list_trips: {:?}", e)),
+) -> ApiResult<Page<Trip>> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    query_trips(&mut db, options).await.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use sqlx::SqlitePool;
+
+    use crate::utils;
+
+    /// Requests all pages and returns the ids of the trips of each page.
+    async fn collect_pages(
+        db: &SqlitePool,
+        vehicle_id: Option<VehicleId>,
+        limit: u32,
+        sort: SortOrder,
+    ) -> Vec<Vec<i64>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let page = query_trips(
+                &mut db.acquire().await.unwrap(),
+                ListTripsOptions {
+                    vehicle_id,
+                    limit: Some(limit),
+                    cursor,
+                    sort,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+            pages.push(page.items.iter().map(|trip| trip.id).collect());
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        pages
+    }
+
+    #[tokio::test]
+    async fn test_paginate_trips() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into vehicles (id, name) values (2, 'Van')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+        // the same end as a trip of the other vehicle
+        utils::insert_trip(&db, 2, 50, 150).await;
+        utils::insert_trip(&db, 2, 150, 300).await;
+        // the trips of both vehicles are interleaved in time, the trips 2 and 4 are
+        // made at the same time
+        sqlx::query(
+            "update trips set created_at = case id \
+             when 1 then '2024-01-01T00:00:00+00:00' \
+             when 4 then '2024-01-02T00:00:00+00:00' \
+             when 5 then '2024-01-04T00:00:00+00:00' \
+             when 3 then '2024-01-05T00:00:00+00:00' \
+             else '2024-01-02T00:00:00+00:00' end",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // the trips of all vehicles are sorted by the time they were made
+        assert_eq!(
+            vec![vec![3, 5], vec![4, 2], vec![1]],
+            collect_pages(&db, None, 2, SortOrder::Desc).await
+        );
+        assert_eq!(
+            vec![vec![1, 2, 4], vec![5, 3]],
+            collect_pages(&db, None, 3, SortOrder::Asc).await
+        );
+
+        // the trips of a vehicle are sorted by its odometer
+        assert_eq!(
+            vec![vec![3, 2], vec![1]],
+            collect_pages(&db, Some(1), 2, SortOrder::Desc).await
+        );
+        assert_eq!(
+            vec![vec![4], vec![5]],
+            collect_pages(&db, Some(2), 1, SortOrder::Asc).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_without_limit_all_trips_are_listed() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+
+        let page = query_trips(
+            &mut db.acquire().await.unwrap(),
+            ListTripsOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![150, 100],
+            page.items.iter().map(|trip| trip.end).collect::<Vec<_>>()
+        );
+        assert_eq!(None, page.next_cursor);
+    }
+
+    struct Fixture {
+        id: i64,
+        vehicle_id: VehicleId,
+        created_at: &'static str,
+        start: i64,
+        end: i64,
+        description: Option<&'static str>,
+        classification: Classification,
+        users: &'static [UserId],
+    }
+
+    const FIXTURES: [Fixture; 5] = [
+        Fixture {
+            id: 1,
+            vehicle_id: 1,
+            created_at: "2024-01-10T10:00:00+00:00",
+            start: 0,
+            end: 100,
+            description: Some("Holiday"),
+            classification: Classification::Private,
+            users: &[1],
+        },
+        Fixture {
+            id: 2,
+            vehicle_id: 1,
+            created_at: "2024-02-10T10:00:00+00:00",
+            start: 100,
+            end: 120,
+            description: Some("Shopping"),
+            classification: Classification::Private,
+            users: &[2],
+        },
+        Fixture {
+            id: 3,
+            vehicle_id: 1,
+            created_at: "2024-03-10T10:00:00+00:00",
+            start: 120,
+            end: 300,
+            description: Some("holiday return"),
+            classification: Classification::Commuting,
+            users: &[1, 2],
+        },
+        Fixture {
+            id: 4,
+            vehicle_id: 2,
+            created_at: "2024-02-15T10:00:00+00:00",
+            start: 0,
+            end: 50,
+            description: None,
+            classification: Classification::Commuting,
+            users: &[1],
+        },
+        Fixture {
+            id: 5,
+            vehicle_id: 2,
+            created_at: "2024-03-20T10:00:00+00:00",
+            start: 50,
+            end: 60,
+            description: Some("shopping"),
+            classification: Classification::Private,
+            users: &[2],
+        },
+    ];
+
+    #[tokio::test]
+    async fn test_every_filter_combination() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into vehicles (id, name) values (2, 'Van')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        for trip in &FIXTURES {
+            sqlx::query(
+                "insert into trips (id, vehicle_id, created_at, start, end, description, classification) values (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(trip.id)
+            .bind(trip.vehicle_id)
+            .bind(trip.created_at)
+            .bind(trip.start)
+            .bind(trip.end)
+            .bind(trip.description)
+            .bind(trip.classification)
+            .execute(&db)
+            .await
+            .unwrap();
+
+            for user in trip.users {
+                sqlx::query("insert into trip_users (trip_id, user_id) values (?, ?)")
+                    .bind(trip.id)
+                    .bind(user)
+                    .execute(&db)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let start: DateTime<Utc> = "2024-02-01T00:00:00Z".parse().unwrap();
+        let end: DateTime<Utc> = "2024-03-15T00:00:00Z".parse().unwrap();
+
+        // every bit of the mask enables one of the filters
+        for mask in 0..(1 << 8) {
+            let enabled = |bit: u32| mask & (1 << bit) != 0;
+
+            let options = ListTripsOptions {
+                start: enabled(0).then_some(start),
+                end: enabled(1).then_some(end),
+                users: if enabled(2) { vec![1] } else { vec![] },
+                vehicle_id: enabled(3).then_some(1),
+                search: enabled(4).then(|| "HOLIDAY".to_string()),
+                min_distance: enabled(5).then_some(20),
+                max_distance: enabled(6).then_some(100),
+                classification: enabled(7).then_some(Classification::Commuting),
+                ..Default::default()
+            };
+
+            let expected = FIXTURES
+                .iter()
+                .filter(|trip| {
+                    let created_at: DateTime<Utc> = trip.created_at.parse().unwrap();
+                    let distance = trip.end - trip.start;
+
+                    options.start.is_none_or(|start| created_at >= start)
+                        && options.end.is_none_or(|end| created_at <= end)
+                        && (options.users.is_empty() || trip.users.contains(&1))
+                        && options.vehicle_id.is_none_or(|id| trip.vehicle_id == id)
+                        && (options.search.is_none()
+                            || trip
+                                .description
+                                .is_some_and(|d| d.to_lowercase().contains("holiday")))
+                        && options.min_distance.is_none_or(|min| distance >= min)
+                        && options.max_distance.is_none_or(|max| distance <= max)
+                        && options
+                            .classification
+                            .is_none_or(|classification| trip.classification == classification)
+                })
+                .map(|trip| trip.id)
+                .collect::<HashSet<_>>();
+
+            let page = query_trips(&mut db.acquire().await.unwrap(), options)
+                .await
+                .unwrap();
+            let ids = page
+                .items
+                .iter()
+                .map(|trip| trip.id)
+                .collect::<HashSet<_>>();
+
+            assert_eq!(expected, ids, "filters enabled: {:08b}", mask);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_filter_keeps_all_users_of_a_trip() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        sqlx::query("insert into trip_users (trip_id, user_id) values (1, 2)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let page = query_trips(
+            &mut db.acquire().await.unwrap(),
+            ListTripsOptions {
+                users: vec![2],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(HashSet::from([1, 2]), page.items[0].users);
     }
 }
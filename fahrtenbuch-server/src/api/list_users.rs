@@ -1,11 +1,15 @@
 use axum::extract::Query;
 use axum_messages::Messages;
 
+use std::collections::HashSet;
+
 use serde::Deserialize;
+use sqlx::SqliteConnection;
 
-use crate::auth::{AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::auth::{AuthSession, Role, UserId};
+use crate::response::{ApiError, ApiResult};
 use crate::username::Username;
+use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ListUsersOptions {}
@@ -14,15 +18,37 @@ pub async fn list_users(
     auth_session: AuthSession,
     messages: Messages,
     Query(_options): Query<ListUsersOptions>,
-) -> ApiResult<Vec<(UserId, Username)>> {
+) -> ApiResult<Vec<(UserId, Username, Role)>> {
     match auth_session.backend.list_users().await {
         Ok(data) => {
             messages.success("Found users");
             ApiResult::ok(data)
         }
         Err(e) => {
-            messages.error(format!("Failed to list users: {:?}", e));
-            ApiResult::error(format!("Failed to read list: {:?}", e))
+            messages.error("Failed to list users");
+            ApiResult::error(e)
         }
     }
 }
+
+/// Ensures that all users of an entry exist, before they are stored with it.
+pub async fn validate_users(
+    db: &mut SqliteConnection,
+    users: &HashSet<UserId>,
+) -> Result<(), ApiError> {
+    for user_id in utils::sorted_vec(users.iter()) {
+        let user: Option<(UserId,)> = sqlx::query_as("select id from users where id = ?")
+            .bind(user_id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+        if user.is_none() {
+            return Err(ApiError::invalid_field(
+                "users",
+                format!("The user {} does not exist", user_id),
+            ));
+        }
+    }
+
+    Ok(())
+}
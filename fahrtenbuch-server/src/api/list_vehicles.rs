@@ -0,0 +1,15 @@
+use axum_messages::Messages;
+
+use crate::api::vehicle::Vehicle;
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+pub async fn list_vehicles(
+    auth_session: AuthSession,
+    _messages: Messages,
+) -> ApiResult<Vec<Vehicle>> {
+    sqlx::query_as("select id, name from vehicles order by id")
+        .fetch_all(auth_session.backend.db().await)
+        .await
+        .into()
+}
@@ -3,24 +3,143 @@ use axum::{
     Router,
 };
 
+mod add_api_token;
+mod add_category;
 mod add_expense;
+mod add_invitation;
+mod add_password_reset;
+mod add_recurring_expense;
+mod add_refuel;
+mod add_settlement;
+mod add_tariff;
 mod add_trip;
+mod add_vehicle;
+pub(crate) mod category;
+mod close_period;
+mod delete_api_token;
+mod delete_category;
+mod delete_expense;
+mod delete_invitation;
+mod delete_recurring_expense;
+mod delete_settlement;
+mod delete_tariff;
+mod delete_trip;
+pub(crate) mod export;
+mod fuel_consumption;
+mod fuel_cost;
+pub(crate) mod import;
+mod list_api_tokens;
+mod list_categories;
+mod list_closed_periods;
 mod list_expenses;
+mod list_history;
+mod list_invitations;
+mod list_recurring_expenses;
+mod list_refuels;
+mod list_settlements;
+mod list_tariffs;
 mod list_trips;
 mod list_users;
+mod list_vehicles;
+mod page;
+mod period;
+pub(crate) mod recurring_expense;
+mod refuel;
+mod reopen_period;
+mod set_registration;
+pub(crate) mod set_role;
+pub(crate) mod settlement;
 mod summary;
-mod trip;
+mod tariff;
+pub(crate) mod trip;
+pub(crate) mod trip_chain;
+mod update_category;
 mod update_expense;
+mod update_settlement;
 mod update_trip;
+pub(crate) mod vehicle;
+mod verify_trips;
 
 pub fn router() -> Router<()> {
     Router::new()
         .route("/list_users", get(list_users::list_users))
+        .route("/set_role", post(set_role::set_role))
+        .route("/add_invitation", post(add_invitation::add_invitation))
+        .route("/list_invitations", get(list_invitations::list_invitations))
+        .route(
+            "/delete_invitation",
+            post(delete_invitation::delete_invitation),
+        )
+        .route(
+            "/set_registration",
+            post(set_registration::set_registration),
+        )
+        .route(
+            "/add_password_reset",
+            post(add_password_reset::add_password_reset),
+        )
+        .route("/add_api_token", post(add_api_token::add_api_token))
+        .route("/list_api_tokens", get(list_api_tokens::list_api_tokens))
+        .route(
+            "/delete_api_token",
+            post(delete_api_token::delete_api_token),
+        )
         .route("/add_trip", post(add_trip::add_trip))
         .route("/update_trip", post(update_trip::update_trip))
+        .route("/delete_trip", post(delete_trip::delete_trip))
         .route("/list_trips", get(list_trips::list_trips))
+        .route("/verify_trips", get(verify_trips::verify_trips))
         .route("/add_expense", post(add_expense::add_expense))
         .route("/update_expense", post(update_expense::update_expense))
+        .route("/delete_expense", post(delete_expense::delete_expense))
         .route("/list_expenses", get(list_expenses::list_expenses))
+        .route("/add_category", post(add_category::add_category))
+        .route("/update_category", post(update_category::update_category))
+        .route("/delete_category", post(delete_category::delete_category))
+        .route("/list_categories", get(list_categories::list_categories))
+        .route(
+            "/add_recurring_expense",
+            post(add_recurring_expense::add_recurring_expense),
+        )
+        .route(
+            "/delete_recurring_expense",
+            post(delete_recurring_expense::delete_recurring_expense),
+        )
+        .route(
+            "/list_recurring_expenses",
+            get(list_recurring_expenses::list_recurring_expenses),
+        )
+        .route("/add_refuel", post(add_refuel::add_refuel))
+        .route("/list_refuels", get(list_refuels::list_refuels))
+        .route("/fuel_consumption", get(fuel_consumption::fuel_consumption))
+        .route("/fuel_cost", get(fuel_cost::fuel_cost))
+        .route("/list_history", get(list_history::list_history))
+        .route("/add_settlement", post(add_settlement::add_settlement))
+        .route(
+            "/update_settlement",
+            post(update_settlement::update_settlement),
+        )
+        .route(
+            "/delete_settlement",
+            post(delete_settlement::delete_settlement),
+        )
+        .route("/list_settlements", get(list_settlements::list_settlements))
         .route("/summary", get(summary::summary))
+        .route("/close_period", post(close_period::close_period))
+        .route("/reopen_period", post(reopen_period::reopen_period))
+        .route(
+            "/list_closed_periods",
+            get(list_closed_periods::list_closed_periods),
+        )
+        .route("/add_vehicle", post(add_vehicle::add_vehicle))
+        .route("/list_vehicles", get(list_vehicles::list_vehicles))
+        .route("/add_tariff", post(add_tariff::add_tariff))
+        .route("/delete_tariff", post(delete_tariff::delete_tariff))
+        .route("/list_tariffs", get(list_tariffs::list_tariffs))
+        .route("/export_trips", get(export::export_trips))
+        .route("/export_expenses", get(export::export_expenses))
+        .route("/export_summary", get(export::export_summary))
+        .route("/import", post(import::import))
+        .route("/import_trips_csv", post(import::import_trips_csv))
+        .route("/import_expenses_csv", post(import::import_expenses_csv))
 }
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite};
+
+use crate::utils::Filter;
+
+/// The order in which the entries of a list are returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    /// The newest entries first, like before pagination existed.
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn keyword(self) -> &'static str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
+
+    fn comparison(self) -> &'static str {
+        match self {
+            Self::Asc => ">",
+            Self::Desc => "<",
+        }
+    }
+}
+
+/// Points to the last entry of a page, the next page starts after it.
+///
+/// The key is the value the list is sorted by, the id breaks ties between entries
+/// with the same key. It is sent to the client as an opaque string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub key: i64,
+    pub id: i64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.key, self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (key, id) = value
+            .split_once('_')
+            .ok_or_else(|| anyhow::anyhow!("invalid cursor '{}'", value))?;
+
+        Ok(Self {
+            key: key.parse()?,
+            id: id.parse()?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+/// The options shared by all paginated lists.
+///
+/// The fields are part of the options of each list, because query strings
+/// can not be deserialized into flattened structs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageOptions {
+    /// The maximum number of entries to return, all entries if omitted.
+    pub limit: Option<u32>,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<Cursor>,
+    pub sort: SortOrder,
+}
+
+impl PageOptions {
+    /// Only selects the entries after the cursor.
+    pub fn push_cursor(&self, filter: &mut Filter<'_, '_, Sqlite>, key: &str) {
+        let Some(cursor) = self.cursor else {
+            return;
+        };
+
+        filter
+            .condition()
+            .push(format!("({}, id) {} (", key, self.sort.comparison()))
+            .push_bind(cursor.key)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+
+    /// Pushes the order and limit, one more entry than requested is fetched
+    /// to know whether there is a next page.
+    pub fn push_order_and_limit(&self, builder: &mut QueryBuilder<'_, Sqlite>, key: &str) {
+        builder.push(format!(
+            " order by {} {keyword}, id {keyword}",
+            key,
+            keyword = self.sort.keyword()
+        ));
+
+        if let Some(limit) = self.limit {
+            builder.push(" limit ").push_bind(limit as i64 + 1);
+        }
+    }
+}
+
+/// A part of a list, the next part can be requested with the `next_cursor`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Creates a page from the entries fetched with [`PageOptions::push_order_and_limit`].
+    pub fn new(mut items: Vec<T>, options: &PageOptions, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let mut next_cursor = None;
+
+        if let Some(limit) = options.limit {
+            if items.len() > limit as usize {
+                items.truncate(limit as usize);
+                next_cursor = items.last().map(cursor);
+            }
+        }
+
+        Self { items, next_cursor }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let cursor = Cursor { key: -150, id: 3 };
+
+        assert_eq!("-150_3", cursor.to_string());
+        assert_eq!(cursor, "-150_3".parse().unwrap());
+        assert!("150".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn test_page_has_next_cursor_only_if_there_are_more_entries() {
+        let options = PageOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let cursor = |value: &i64| Cursor {
+            key: *value,
+            id: *value,
+        };
+
+        let page = Page::new(vec![3, 2, 1], &options, cursor);
+        assert_eq!(vec![3, 2], page.items);
+        assert_eq!(Some(Cursor { key: 2, id: 2 }), page.next_cursor);
+
+        let page = Page::new(vec![3, 2], &options, cursor);
+        assert_eq!(None, page.next_cursor);
+    }
+}
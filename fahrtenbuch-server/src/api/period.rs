@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::auth::UserId;
+use crate::response::ApiError;
+
+/// A period in which the trips, expenses and settlements can not be changed,
+/// because the balances have already been settled.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ClosedPeriod {
+    pub id: i64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+    pub closed_by: UserId,
+    /// The summary of each user at the time the period has been closed.
+    pub summary: Json<Value>,
+    /// The period is closed as long as it has not been reopened.
+    pub reopened_at: Option<DateTime<Utc>>,
+    pub reopened_by: Option<UserId>,
+    pub reopen_reason: Option<String>,
+}
+
+/// Returns the closed period that contains the date, if any.
+pub async fn closed_period_at(
+    db: &mut SqliteConnection,
+    date: DateTime<Utc>,
+) -> Result<Option<ClosedPeriod>, sqlx::Error> {
+    sqlx::query_as(
+        "select * from closed_periods where reopened_at is null \
+         and datetime(start, 'utc') <= datetime(?, 'utc') and datetime(?, 'utc') <= datetime(end, 'utc')",
+    )
+    .bind(date)
+    .bind(date)
+    .fetch_optional(&mut *db)
+    .await
+}
+
+/// Ensures that none of the dates is inside a closed period, so an entry with
+/// these dates can be added, changed or deleted.
+pub async fn ensure_open(
+    db: &mut SqliteConnection,
+    dates: impl IntoIterator<Item = DateTime<Utc>>,
+) -> Result<(), ApiError> {
+    for date in dates {
+        if let Some(period) = closed_period_at(&mut *db, date).await? {
+            return Err(ApiError::conflict(format!(
+                "The period from {} to {} has been closed, {} can not be changed",
+                period.start, period.end, date
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils;
+
+    #[tokio::test]
+    async fn test_ensure_open() {
+        let db = utils::test_db().await;
+        let mut conn = db.acquire().await.unwrap();
+
+        sqlx::query(
+            "insert into closed_periods (start, end, closed_at, closed_by, summary) values \
+             ('2024-01-01T00:00:00+00:00', '2024-01-31T23:59:59+00:00', '2024-02-01T00:00:00+00:00', 1, '{}'), \
+             ('2024-02-01T00:00:00+00:00', '2024-02-29T23:59:59+00:00', '2024-03-01T00:00:00+00:00', 1, '{}')",
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        sqlx::query("update closed_periods set reopened_at = '2024-03-02T00:00:00+00:00', reopened_by = 1 where id = 2")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        let date = |date: &str| date.parse::<DateTime<Utc>>().unwrap();
+
+        // the dates are compared in utc
+        assert!(ensure_open(&mut conn, [date("2024-02-01T00:30:00+01:00")])
+            .await
+            .is_err());
+        assert!(ensure_open(&mut conn, [date("2024-01-31T23:59:59Z")])
+            .await
+            .is_err());
+        // the second period has been reopened
+        ensure_open(
+            &mut conn,
+            [date("2023-12-31T23:59:59Z"), date("2024-02-01T00:00:00Z")],
+        )
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::{DateTime, Months, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+
+use crate::api::add_expense::{self, query_add_expense};
+use crate::api::category::CategoryId;
+use crate::api::period;
+use crate::api::vehicle::VehicleId;
+use crate::auth::UserId;
+use crate::response::ApiError;
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Interval {
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl Interval {
+    pub fn months(self) -> u32 {
+        match self {
+            Interval::Monthly => 1,
+            Interval::Quarterly => 3,
+            Interval::Yearly => 12,
+        }
+    }
+}
+
+/// An expense that is added automatically after each interval, e.g. the insurance.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow)]
+pub struct RecurringExpense {
+    pub id: i64,
+    pub vehicle_id: VehicleId,
+    /// The amount of each occurrence in cents.
+    pub amount: i64,
+    pub description: Option<String>,
+    pub category_id: Option<CategoryId>,
+    pub interval: Interval,
+    /// The date of the first occurrence.
+    pub start: DateTime<Utc>,
+    /// There are no occurrences after the end.
+    pub end: Option<DateTime<Utc>>,
+    /// The number of occurrences for which an expense has been added.
+    pub occurrences: i64,
+    /// The users who pay for each occurrence.
+    #[sqlx(skip)]
+    pub users: HashSet<UserId>,
+    /// The occurrences that have not been added, because their period has been closed.
+    #[sqlx(skip)]
+    pub skipped: Vec<SkippedOccurrence>,
+}
+
+/// An occurrence inside a closed period, its expense has to be added by hand
+/// if the period is reopened.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow)]
+pub struct SkippedOccurrence {
+    pub date: DateTime<Utc>,
+    pub closed_period_id: i64,
+}
+
+impl RecurringExpense {
+    /// Returns the date of the `n`th occurrence (starting at 0), or `None` if it is after the end.
+    ///
+    /// The dates are calculated from the start, so an occurrence at the end of a
+    /// short month does not move the following ones.
+    pub fn occurrence(&self, n: u32) -> Option<DateTime<Utc>> {
+        let date = self
+            .start
+            .checked_add_months(Months::new(n.checked_mul(self.interval.months())?))?;
+
+        if self.end.is_some_and(|end| date > end) {
+            return None;
+        }
+
+        Some(date)
+    }
+
+    /// Returns the date of the next occurrence that has not been added yet.
+    pub fn next_occurrence(&self) -> Option<DateTime<Utc>> {
+        self.occurrence(u32::try_from(self.occurrences).ok()?)
+    }
+}
+
+/// Returns all recurring expenses, sorted by their ids.
+pub async fn query_recurring_expenses(
+    db: &mut SqliteConnection,
+) -> Result<Vec<RecurringExpense>, sqlx::Error> {
+    let mut recurring_expenses: Vec<RecurringExpense> =
+        sqlx::query_as("select * from recurring_expenses order by id")
+            .fetch_all(&mut *db)
+            .await?;
+
+    let users: Vec<(i64, UserId)> =
+        sqlx::query_as("select recurring_expense_id, user_id from recurring_expense_users")
+            .fetch_all(&mut *db)
+            .await?;
+
+    for (id, user_id) in users {
+        if let Some(recurring_expense) = recurring_expenses
+            .iter_mut()
+            .find(|recurring_expense| recurring_expense.id == id)
+        {
+            recurring_expense.users.insert(user_id);
+        }
+    }
+
+    let skipped: Vec<(i64, DateTime<Utc>, i64)> = sqlx::query_as(
+        "select recurring_expense_id, date, closed_period_id from skipped_occurrences order by date",
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    for (id, date, closed_period_id) in skipped {
+        if let Some(recurring_expense) = recurring_expenses
+            .iter_mut()
+            .find(|recurring_expense| recurring_expense.id == id)
+        {
+            recurring_expense.skipped.push(SkippedOccurrence {
+                date,
+                closed_period_id,
+            });
+        }
+    }
+
+    Ok(recurring_expenses)
+}
+
+/// Returns the recurring expense with its users, if it exists.
+///
+/// The skipped occurrences are not loaded.
+pub async fn query_recurring_expense(
+    db: &mut SqliteConnection,
+    id: i64,
+) -> Result<Option<RecurringExpense>, sqlx::Error> {
+    let recurring_expense: Option<RecurringExpense> =
+        sqlx::query_as("select * from recurring_expenses where id = ?")
+            .bind(id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+    let Some(mut recurring_expense) = recurring_expense else {
+        return Ok(None);
+    };
+
+    let users: Vec<(UserId,)> = sqlx::query_as(
+        "select user_id from recurring_expense_users where recurring_expense_id = ?",
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+    recurring_expense.users = users.into_iter().map(|(user_id,)| user_id).collect();
+
+    Ok(Some(recurring_expense))
+}
+
+/// Adds the expenses of the occurrences up to `now` that have not been added yet.
+/// Returns the number of added expenses.
+async fn add_due_occurrences(
+    db: &mut SqliteConnection,
+    id: i64,
+    now: DateTime<Utc>,
+) -> Result<usize, ApiError> {
+    // the recurring expense is loaded inside the transaction, so no other run adds the same occurrences
+    let Some(mut recurring_expense) = query_recurring_expense(&mut *db, id).await? else {
+        return Ok(0);
+    };
+
+    let occurrences = recurring_expense.occurrences;
+    let mut count = 0;
+
+    while let Some(date) = recurring_expense
+        .next_occurrence()
+        .filter(|date| *date <= now)
+    {
+        // a settled period can not be changed, so the occurrence is recorded as skipped
+        if let Some(closed) = period::closed_period_at(&mut *db, date).await? {
+            warn!(
+                "Skipped the recurring expense {} at {}, because the period {} has been closed",
+                recurring_expense.id, date, closed.id
+            );
+            sqlx::query(
+                "insert into skipped_occurrences (recurring_expense_id, date, closed_period_id) values (?, ?, ?)",
+            )
+            .bind(recurring_expense.id)
+            .bind(date)
+            .bind(closed.id)
+            .execute(&mut *db)
+            .await?;
+
+            recurring_expense.occurrences += 1;
+            continue;
+        }
+
+        query_add_expense(
+            &mut *db,
+            None,
+            add_expense::ExpenseData {
+                vehicle_id: Some(recurring_expense.vehicle_id),
+                created_at: Some(date),
+                amount: recurring_expense.amount as u64,
+                description: recurring_expense.description.clone(),
+                category_id: recurring_expense.category_id,
+                users: recurring_expense.users.clone(),
+                shares: Default::default(),
+            },
+        )
+        .await?;
+
+        recurring_expense.occurrences += 1;
+        count += 1;
+    }
+
+    if recurring_expense.occurrences != occurrences {
+        sqlx::query("update recurring_expenses set occurrences = ? where id = ?")
+            .bind(recurring_expense.occurrences)
+            .bind(recurring_expense.id)
+            .execute(&mut *db)
+            .await?;
+    }
+
+    Ok(count)
+}
+
+/// Adds the expenses of all occurrences up to `now` that have not been added yet,
+/// so missed occurrences are caught up. Returns the number of added expenses.
+///
+/// Each recurring expense is added in its own transaction, if one of them fails
+/// it is logged and the others are still added.
+pub async fn add_due_expenses(db: &SqlitePool, now: DateTime<Utc>) -> Result<usize, ApiError> {
+    let ids: Vec<(i64,)> = sqlx::query_as("select id from recurring_expenses order by id")
+        .fetch_all(db)
+        .await?;
+
+    let mut count = 0;
+    for (id,) in ids {
+        match utils::transaction(db, |tx| Box::pin(add_due_occurrences(tx, id, now))).await {
+            Ok(added) => count += added,
+            Err(error) => error!("Failed to add the recurring expense {}: {:?}", id, error),
+        }
+    }
+
+    Ok(count)
+}
+
+/// Adds the due expenses right away and then after every `period`, until the task is aborted.
+pub async fn continuously_add_due_expenses(db: SqlitePool, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        match add_due_expenses(&db, Utc::now()).await {
+            Ok(0) => {}
+            Ok(count) => info!("Added {} recurring expenses", count),
+            Err(error) => error!("Failed to add the recurring expenses: {:?}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn recurring_expense(interval: Interval, start: &str, end: Option<&str>) -> RecurringExpense {
+        RecurringExpense {
+            id: 1,
+            vehicle_id: 1,
+            amount: 1000,
+            description: None,
+            category_id: None,
+            interval,
+            start: start.parse().unwrap(),
+            end: end.map(|end| end.parse().unwrap()),
+            occurrences: 0,
+            users: HashSet::from([1]),
+            skipped: vec![],
+        }
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let monthly = recurring_expense(
+            Interval::Monthly,
+            "2024-01-31T00:00:00Z",
+            Some("2024-04-30T00:00:00Z"),
+        );
+
+        // the day is clamped to the end of short months, but the following months are not affected
+        assert_eq!(
+            vec![
+                Some("2024-01-31T00:00:00Z".parse().unwrap()),
+                Some("2024-02-29T00:00:00Z".parse().unwrap()),
+                Some("2024-03-31T00:00:00Z".parse().unwrap()),
+                Some("2024-04-30T00:00:00Z".parse().unwrap()),
+                None,
+            ],
+            (0..5).map(|n| monthly.occurrence(n)).collect::<Vec<_>>()
+        );
+
+        let yearly = recurring_expense(Interval::Yearly, "2024-03-01T00:00:00Z", None);
+        assert_eq!(
+            Some("2027-03-01T00:00:00Z".parse().unwrap()),
+            yearly.occurrence(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_due_expenses_are_added_once() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into recurring_expenses (id, vehicle_id, amount, interval, start) values (1, 1, 5000, 'quarterly', '2024-01-01T00:00:00Z'); \
+             insert into recurring_expense_users (recurring_expense_id, user_id) values (1, 1), (1, 2)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let now: DateTime<Utc> = "2024-07-15T00:00:00Z".parse().unwrap();
+
+        // the occurrences in january, april and july are caught up
+        assert_eq!(3, add_due_expenses(&db, now).await.unwrap());
+        assert_eq!(0, add_due_expenses(&db, now).await.unwrap());
+
+        // a deleted occurrence is not added again
+        sqlx::query(
+            "delete from expense_users where expense_id = 1; delete from expenses where id = 1",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(0, add_due_expenses(&db, now).await.unwrap());
+
+        let next: DateTime<Utc> = "2024-10-01T00:00:00Z".parse().unwrap();
+        assert_eq!(1, add_due_expenses(&db, next).await.unwrap());
+
+        let expenses: Vec<(String, i64)> = sqlx::query_as(
+            "select strftime('%Y-%m-%d', created_at), count(user_id) from expenses \
+             join expense_users on expense_users.expense_id = expenses.id group by expenses.id order by created_at",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ("2024-04-01".to_string(), 2),
+                ("2024-07-01".to_string(), 2),
+                ("2024-10-01".to_string(), 2),
+            ],
+            expenses
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_recurring_expense_does_not_block_the_others() {
+        let db = utils::test_db().await;
+        // the first recurring expense has no users, so its expenses can not be added
+        sqlx::query(
+            "insert into recurring_expenses (id, vehicle_id, amount, interval, start) values \
+             (1, 1, 5000, 'monthly', '2024-01-01T00:00:00Z'), (2, 1, 3000, 'monthly', '2024-01-01T00:00:00Z'); \
+             insert into recurring_expense_users (recurring_expense_id, user_id) values (2, 1)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let now: DateTime<Utc> = "2024-02-15T00:00:00Z".parse().unwrap();
+        assert_eq!(2, add_due_expenses(&db, now).await.unwrap());
+
+        let occurrences: Vec<(i64, i64)> =
+            sqlx::query_as("select id, occurrences from recurring_expenses order by id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(vec![(1, 0), (2, 2)], occurrences);
+    }
+
+    #[tokio::test]
+    async fn test_occurrences_in_closed_periods_are_recorded() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into recurring_expenses (id, vehicle_id, amount, interval, start) values (1, 1, 5000, 'monthly', '2024-01-01T00:00:00Z'); \
+             insert into recurring_expense_users (recurring_expense_id, user_id) values (1, 1); \
+             insert into closed_periods (start, end, closed_at, closed_by, summary) values \
+             ('2024-01-01T00:00:00+00:00', '2024-01-31T23:59:59+00:00', '2024-02-01T00:00:00+00:00', 1, '{}')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let now: DateTime<Utc> = "2024-02-15T00:00:00Z".parse().unwrap();
+        assert_eq!(1, add_due_expenses(&db, now).await.unwrap());
+
+        let recurring_expenses = query_recurring_expenses(&mut db.acquire().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(2, recurring_expenses[0].occurrences);
+        assert_eq!(
+            vec![SkippedOccurrence {
+                date: "2024-01-01T00:00:00Z".parse().unwrap(),
+                closed_period_id: 1,
+            }],
+            recurring_expenses[0].skipped
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, QueryBuilder, SqlitePool};
+
+use crate::api::vehicle::VehicleId;
+use crate::response::ApiError;
+use crate::utils::SqlBuilderExt;
+
+/// Fuel that has been bought for a vehicle, what has been paid is recorded in an expense.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow)]
+pub struct Refuel {
+    pub id: i64,
+    /// The expense that records who has paid for the fuel.
+    pub expense_id: i64,
+    pub vehicle_id: VehicleId,
+    pub created_at: DateTime<Utc>,
+    /// The value of the odometer when refuelling.
+    pub odometer: i64,
+    /// The amount of fuel in millilitres.
+    pub millilitres: i64,
+    /// The price per litre in tenths of a cent (1.799 €/l = 1799).
+    pub price_per_litre: i64,
+    /// Whether the tank has been filled up, the consumption is only known between full tanks.
+    pub full_tank: bool,
+    pub station: Option<String>,
+}
+
+/// Returns the price in cents for the fuel, rounded to the nearest cent.
+pub fn price_for(millilitres: u64, price_per_litre: u64) -> u64 {
+    // millilitres * tenths of a cent per litre = 10000 * cents
+    (millilitres * price_per_litre + 5000) / 10000
+}
+
+/// The fuel that has been used between two refuels that filled up the tank.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Consumption {
+    /// The odometer at the first full tank.
+    pub start: i64,
+    /// The odometer at the next full tank.
+    pub end: i64,
+    /// The fuel that has been refilled after the first full tank, up to the next one.
+    pub millilitres: i64,
+    pub litres_per_100km: f64,
+}
+
+/// Calculates the consumption between each two full tanks.
+///
+/// The refuels in between that did not fill up the tank count towards the next
+/// full tank, refuels before the first full tank are ignored.
+pub fn consumption(refuels: &[Refuel]) -> Vec<Consumption> {
+    let mut refuels = refuels.iter().collect::<Vec<_>>();
+    refuels.sort_by_key(|refuel| (refuel.odometer, refuel.created_at, refuel.id));
+
+    let mut result = Vec::new();
+    let mut last_full: Option<i64> = None;
+    let mut millilitres = 0;
+
+    for refuel in refuels {
+        millilitres += refuel.millilitres;
+
+        if !refuel.full_tank {
+            continue;
+        }
+
+        if let Some(start) = last_full.filter(|start| *start < refuel.odometer) {
+            let distance = refuel.odometer - start;
+            result.push(Consumption {
+                start,
+                end: refuel.odometer,
+                millilitres,
+                // ml / km * 100 / 1000 = l / 100 km
+                litres_per_100km: millilitres as f64 / distance as f64 / 10.0,
+            });
+        }
+
+        last_full = Some(refuel.odometer);
+        millilitres = 0;
+    }
+
+    result
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RefuelOptions {
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    /// Only the refuels of a specific vehicle.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
+}
+
+/// Returns the refuels in the time frame, sorted by the odometer.
+pub async fn query_refuels(
+    db: &SqlitePool,
+    options: RefuelOptions,
+) -> Result<Vec<Refuel>, ApiError> {
+    let mut builder = QueryBuilder::new(
+        "select refuels.*, expenses.vehicle_id, expenses.created_at from refuels \
+         join expenses on expenses.id = refuels.expense_id",
+    );
+
+    builder
+        .filter()
+        .eq("expenses.vehicle_id", options.vehicle_id)
+        .date_range("expenses.created_at", options.start, options.end);
+
+    builder.push(" order by expenses.vehicle_id, refuels.odometer, refuels.id");
+
+    Ok(builder.build_query_as().fetch_all(db).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn refuel(id: i64, odometer: i64, millilitres: i64, full_tank: bool) -> Refuel {
+        Refuel {
+            id,
+            expense_id: id,
+            vehicle_id: 1,
+            created_at: Utc::now(),
+            odometer,
+            millilitres,
+            price_per_litre: 1799,
+            full_tank,
+            station: None,
+        }
+    }
+
+    #[test]
+    fn test_price_is_rounded_to_cents() {
+        // 40 l at 1.799 €/l = 71.96 €
+        assert_eq!(7196, price_for(40_000, 1799));
+        // 0.5 l at 1.799 €/l = 0.8995 €
+        assert_eq!(90, price_for(500, 1799));
+    }
+
+    #[test]
+    fn test_consumption_between_full_tanks() {
+        let refuels = [
+            // the first refuel did not fill up the tank, so nothing is known before it
+            refuel(1, 0, 20_000, false),
+            refuel(2, 100, 10_000, true),
+            refuel(4, 700, 30_000, true),
+            refuel(3, 400, 12_000, false),
+            refuel(5, 1200, 40_000, true),
+        ];
+
+        assert_eq!(
+            vec![
+                Consumption {
+                    start: 100,
+                    end: 700,
+                    millilitres: 42_000,
+                    litres_per_100km: 7.0,
+                },
+                Consumption {
+                    start: 700,
+                    end: 1200,
+                    millilitres: 40_000,
+                    litres_per_100km: 8.0,
+                },
+            ],
+            consumption(&refuels)
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::period::ClosedPeriod;
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReopenPeriodData {
+    pub id: i64,
+    /// Why the period has to be changed again.
+    pub reason: String,
+}
+
+pub async fn query_reopen_period(
+    db: &mut SqliteConnection,
+    actor: UserId,
+    data: ReopenPeriodData,
+) -> Result<(), ApiError> {
+    let period: Option<ClosedPeriod> = sqlx::query_as("select * from closed_periods where id = ?")
+        .bind(data.id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    let Some(period) = period else {
+        return Err(ApiError::not_found(format!(
+            "The closed period {} does not exist",
+            data.id
+        )));
+    };
+
+    if period.reopened_at.is_some() {
+        return Err(ApiError::conflict(format!(
+            "The period {} has already been reopened",
+            data.id
+        )));
+    }
+
+    let reason = data.reason.trim();
+    if reason.is_empty() {
+        return Err(ApiError::invalid_field(
+            "reason",
+            "The reason for reopening the period must not be empty",
+        ));
+    }
+
+    sqlx::query(
+        "update closed_periods set reopened_at = ?, reopened_by = ?, reopen_reason = ? where id = ?",
+    )
+    .bind(Utc::now())
+    .bind(actor)
+    .bind(reason)
+    .bind(period.id)
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Reopens a closed period, the closed period is kept to show that it has been reopened.
+pub async fn reopen_period(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<ReopenPeriodData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_reopen_period(tx, user.id(), data)))
+        .await
+        .into()
+}
@@ -0,0 +1,32 @@
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+
+use crate::auth::{self, AuthSession, Permission};
+use crate::response::ApiResult;
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationSettings {
+    /// Whether anyone can register without an invitation.
+    open: bool,
+}
+
+pub async fn set_registration(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<RegistrationSettings>,
+) -> ApiResult<()> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(auth::set_registration_open(tx, data.open))
+    })
+    .await
+    .into()
+}
@@ -0,0 +1,120 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, Role, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetRoleData {
+    pub user_id: UserId,
+    pub role: Role,
+}
+
+pub async fn query_set_role(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: SetRoleData,
+) -> Result<(), ApiError> {
+    let snapshot = audit::snapshot(&mut *db, Entity::User, data.user_id).await?;
+
+    let Some((current,)): Option<(Role,)> = sqlx::query_as("select role from users where id = ?")
+        .bind(data.user_id)
+        .fetch_optional(&mut *db)
+        .await?
+    else {
+        return Err(ApiError::not_found(format!(
+            "The user {} does not exist",
+            data.user_id
+        )));
+    };
+
+    if current == Role::Admin && data.role != Role::Admin {
+        let (admins,): (i64,) = sqlx::query_as("select count(*) from users where role = ?")
+            .bind(Role::Admin)
+            .fetch_one(&mut *db)
+            .await?;
+
+        // otherwise nobody could manage the fahrtenbuch anymore
+        if admins <= 1 {
+            return Err(ApiError::conflict("The last admin can not be demoted"));
+        }
+    }
+
+    sqlx::query("update users set role = ? where id = ?")
+        .bind(data.role)
+        .bind(data.user_id)
+        .execute(&mut *db)
+        .await?;
+
+    audit::record_change(db, actor, Entity::User, data.user_id, snapshot).await?;
+
+    Ok(())
+}
+
+pub async fn set_role(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<SetRoleData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Manage).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_set_role(tx, Some(user.id()), data)))
+        .await
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use sqlx::SqlitePool;
+
+    async fn set(db: &SqlitePool, user_id: UserId, role: Role) -> Result<(), ApiError> {
+        utils::transaction(db, |tx| {
+            Box::pin(query_set_role(tx, Some(1), SetRoleData { user_id, role }))
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_last_admin_can_not_be_demoted() {
+        let db = utils::test_db().await;
+
+        set(&db, 1, Role::Admin).await.unwrap();
+        assert_eq!(
+            "conflict",
+            set(&db, 1, Role::Viewer).await.unwrap_err().code()
+        );
+
+        set(&db, 2, Role::Admin).await.unwrap();
+        set(&db, 1, Role::Viewer).await.unwrap();
+
+        let roles: Vec<(UserId, Role)> = sqlx::query_as("select id, role from users order by id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(vec![(1, Role::Viewer), (2, Role::Admin)], roles);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_user() {
+        let db = utils::test_db().await;
+
+        assert_eq!(
+            "not_found",
+            set(&db, 3, Role::Member).await.unwrap_err().code()
+        );
+    }
+}
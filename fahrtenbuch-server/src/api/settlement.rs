@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::auth::UserId;
+use crate::response::ApiError;
+
+/// A payment from one user to another that offsets their balances.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow)]
+pub struct Settlement {
+    pub id: i64,
+    /// The user who paid.
+    pub from_user_id: UserId,
+    /// The user who received the money.
+    pub to_user_id: UserId,
+    /// The amount in cents.
+    pub amount: i64,
+    /// The date of the payment.
+    pub created_at: DateTime<Utc>,
+    /// The date of the period that is settled, if it differs from the date of the payment.
+    pub settles_at: Option<DateTime<Utc>>,
+    pub note: Option<String>,
+}
+
+impl Settlement {
+    /// The users who are part of the settlement.
+    pub fn users(&self) -> HashSet<UserId> {
+        HashSet::from([self.from_user_id, self.to_user_id])
+    }
+
+    /// The dates that must not be inside a closed period to change the settlement.
+    pub fn dates(&self) -> Vec<DateTime<Utc>> {
+        [Some(self.created_at), self.settles_at]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+pub async fn query_settlement(db: &mut SqliteConnection, id: i64) -> Result<Settlement, ApiError> {
+    let settlement: Option<Settlement> = sqlx::query_as("select * from settlements where id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+    settlement.ok_or_else(|| ApiError::not_found(format!("The settlement {} does not exist", id)))
+}
+
+/// Ensures that the settlement will be valid in the database.
+pub async fn validate_settlement(
+    db: &mut SqliteConnection,
+    settlement: &Settlement,
+) -> Result<(), ApiError> {
+    if settlement.amount <= 0 {
+        return Err(ApiError::invalid_field(
+            "amount",
+            "The amount must be greater than 0",
+        ));
+    }
+
+    if settlement.from_user_id == settlement.to_user_id {
+        return Err(ApiError::invalid_field(
+            "to_user_id",
+            "A user can not pay themselves",
+        ));
+    }
+
+    for (field, user_id) in [
+        ("from_user_id", settlement.from_user_id),
+        ("to_user_id", settlement.to_user_id),
+    ] {
+        let user: Option<(UserId,)> = sqlx::query_as("select id from users where id = ?")
+            .bind(user_id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+        if user.is_none() {
+            return Err(ApiError::invalid_field(
+                field,
+                format!("The user {} does not exist", user_id),
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -5,132 +5,292 @@ use axum_messages::Messages;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-
-use crate::api::list_expenses::{list_expenses, ListExpensesOptions};
-use crate::api::list_trips::{list_trips, ListTripsOptions};
-use crate::api::list_users::{list_users, ListUsersOptions};
+use sqlx::SqliteConnection;
+
+use crate::api::category::{query_categories, Allocation, CategoryId};
+use crate::api::list_expenses::{query_expenses, ListExpensesOptions};
+use crate::api::list_settlements::{query_settlements, ListSettlementsOptions};
+use crate::api::list_trips::{query_trips, ListTripsOptions};
+use crate::api::trip::{Classification, Trip};
+use crate::api::vehicle::VehicleId;
 use crate::auth::{AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::response::{ApiError, ApiResult};
 use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SummaryOptions {
     #[serde(default)]
-    start: Option<DateTime<Utc>>,
+    pub start: Option<DateTime<Utc>>,
     #[serde(default)]
-    end: Option<DateTime<Utc>>,
-    user: UserId,
+    pub end: Option<DateTime<Utc>>,
+    pub user: UserId,
+    /// Only consider trips and expenses of a specific vehicle.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct SummaryResult {
     /// How much the user has driven in the given time frame.
-    distance: u64,
+    pub distance: u64,
     /// Amount of money the user prepaid for expenses.
-    prepaid: u64,
+    pub prepaid: u64,
     /// Total amount of money spent on expenses in the given time frame.
-    total_amount: u64,
+    pub total_amount: u64,
     /// The distance driven by all users in the given time frame.
-    total_distance: u64,
+    pub total_distance: u64,
+    /// How the expenses of each category have been split, sorted by the category.
+    pub categories: Vec<CategorySummary>,
+    /// The trips of each classification, e.g. for the tax return.
+    pub classifications: Vec<ClassificationSummary>,
     /// How much each user has paid/must pay.
-    balances: HashMap<UserId, i64>,
+    pub balances: HashMap<UserId, i64>,
     /// How much the user gets or must pay to whom to balance the expenses.
-    payments: HashMap<UserId, HashMap<UserId, i64>>,
+    pub payments: HashMap<UserId, HashMap<UserId, i64>>,
 }
 
-macro_rules! min {
-    ($e:expr) => {
-        $e
-    };
-    ( $first:expr $(, $e:expr)+ ) => {
-        ::core::cmp::min($first, min!($($e),*))
-    };
+#[derive(Debug, Clone, Serialize)]
+pub struct CategorySummary {
+    /// `None` for the expenses without a category.
+    pub category_id: Option<CategoryId>,
+    pub name: Option<String>,
+    pub allocation: Allocation,
+    /// Total amount of money spent on expenses of the category in the given time frame.
+    pub total_amount: u64,
+    /// How much each user must pay for the expenses of the category.
+    pub amounts: HashMap<UserId, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClassificationSummary {
+    pub classification: Classification,
+    /// The number of trips with the classification in the given time frame.
+    pub trips: u64,
+    /// How much the user has driven on these trips.
+    pub distance: u64,
+    /// The distance driven by all users on these trips.
+    pub total_distance: u64,
+}
+
+/// Splits the amount proportionally to the weights, the user who pays the most
+/// also pays the remainder.
+///
+/// If all weights are 0, nobody pays anything.
+fn split(amount: u64, mut weights: Vec<u64>) -> Vec<u64> {
+    let remainder = utils::divide_proportionally(amount, weights.as_mut());
+
+    if let Some(max) = weights.iter().max().copied() {
+        if let Some(weight) = weights.iter_mut().find(|weight| **weight == max) {
+            *weight += remainder;
+        }
+    }
+
+    weights
 }
 
+/// Above this many users with an open balance the payments are only calculated greedily,
+/// because finding the fewest payments takes `2^n` steps.
+const MAX_EXACT_USERS: usize = 16;
+
+/// Calculates how much each user has to pay to whom, with as few payments as possible.
+///
+/// The users are split into as many groups as possible whose balances add up to 0,
+/// a group of `n` users can then be balanced with `n - 1` payments. Ties are broken
+/// by the user ids, so the same balances always result in the same payments.
 fn calculate_payments(balances: HashMap<UserId, i64>) -> HashMap<UserId, HashMap<UserId, i64>> {
-    // calculate the amount each user has to pay to whom to balance the expenses
     let mut payments = HashMap::new();
 
-    let sorted_balances: BTreeMap<UserId, i64> = BTreeMap::from_iter(balances.clone().into_iter());
-    // we need to keep track of the updated balances separately,
-    // because we can't modify the balances while iterating over them
-    let mut updated_balances = sorted_balances.clone();
+    // sort the balances by the user ids to ensure that we always get the same result
+    let balances = BTreeMap::from_iter(balances)
+        .into_iter()
+        .filter(|(_, amount)| *amount != 0)
+        .collect::<Vec<_>>();
+
+    if balances.len() > MAX_EXACT_USERS {
+        settle_greedily(balances, &mut payments);
+        return payments;
+    }
 
-    for (user_id, mut amount) in sorted_balances {
-        // skip users who are already balanced or have a positive balance
-        if amount >= 0 {
-            continue;
+    for group in zero_sum_groups(&balances) {
+        settle_greedily(group, &mut payments);
+    }
+
+    payments
+}
+
+/// Splits the balances into the largest number of groups that add up to 0.
+///
+/// If the balances do not add up to 0, one group contains the remainder.
+fn zero_sum_groups(balances: &[(UserId, i64)]) -> Vec<Vec<(UserId, i64)>> {
+    let all = (1usize << balances.len()) - 1;
+
+    // the sum of the balances of each subset, the bits of the index are the users
+    let mut sums = vec![0i64; all + 1];
+    for subset in 1..=all {
+        let first = subset.trailing_zeros() as usize;
+        sums[subset] = sums[subset & (subset - 1)] + balances[first].1;
+    }
+
+    // the largest number of zero-sum groups each subset can be split into
+    let mut groups = vec![0u32; all + 1];
+    for subset in 1..=all {
+        let best = (0..balances.len())
+            .filter(|i| subset & (1 << i) != 0)
+            .map(|i| groups[subset ^ (1 << i)])
+            .max()
+            .unwrap_or_default();
+        groups[subset] = best + u32::from(sums[subset] == 0);
+    }
+
+    // remove one user at a time, the lowest user that keeps the number of groups wins,
+    // every time the remaining users add up to 0, the removed ones form a group
+    let mut result = Vec::new();
+    let mut group = Vec::new();
+    let mut subset = all;
+    while subset != 0 {
+        if sums[subset] == 0 && !group.is_empty() {
+            result.push(std::mem::take(&mut group));
         }
 
-        // now find all users who get paid
-        let people_with_positive_balance = updated_balances
-            .clone()
-            .into_iter()
-            .filter(|(_, amount)| *amount > 0);
+        let expected = groups[subset] - u32::from(sums[subset] == 0);
+        let user = (0..balances.len())
+            .find(|i| subset & (1 << i) != 0 && groups[subset ^ (1 << i)] == expected)
+            .unwrap();
+
+        group.push(balances[user]);
+        subset ^= 1 << user;
+    }
 
-        let payments_of_user: &mut HashMap<UserId, i64> = payments.entry(user_id).or_default();
+    if !group.is_empty() {
+        result.push(group);
+    }
 
-        // iterate over the users and pay each one as much as possible until the amount is 0
-        for (user_to_pay, their_balance) in people_with_positive_balance {
-            let payment = payments_of_user.entry(user_to_pay).or_default();
+    result
+}
 
-            // either pay their full balance or the amount the user owes
-            *payment = min!(amount.abs(), their_balance);
-            amount += *payment;
-            updated_balances.insert(user_to_pay, their_balance - *payment);
+/// Balances the group by letting the largest debtor pay the largest creditor until
+/// everyone is balanced, every payment balances at least one of the two.
+fn settle_greedily(
+    mut balances: Vec<(UserId, i64)>,
+    payments: &mut HashMap<UserId, HashMap<UserId, i64>>,
+) {
+    loop {
+        // on ties, the user with the lower id pays or gets paid first
+        let debtor = (0..balances.len())
+            .filter(|&i| balances[i].1 < 0)
+            .min_by_key(|&i| (balances[i].1, balances[i].0));
+        let creditor = (0..balances.len())
+            .filter(|&i| balances[i].1 > 0)
+            .min_by_key(|&i| (-balances[i].1, balances[i].0));
+
+        let (Some(debtor), Some(creditor)) = (debtor, creditor) else {
+            break;
+        };
 
-            // if we have paid the full amount, we can stop
-            if amount == 0 {
-                break;
+        let amount = (-balances[debtor].1).min(balances[creditor].1);
+        *payments
+            .entry(balances[debtor].0)
+            .or_default()
+            .entry(balances[creditor].0)
+            .or_default() += amount;
+
+        balances[debtor].1 += amount;
+        balances[creditor].1 -= amount;
+    }
+}
+
+/// The summaries of all users in a time frame, they only differ in how much the
+/// user has driven and prepaid, so everything else is calculated once.
+#[derive(Debug, Clone)]
+pub struct Summaries {
+    trips: Vec<Trip>,
+    prepaid: HashMap<UserId, u64>,
+    total_amount: u64,
+    total_distance: u64,
+    categories: Vec<CategorySummary>,
+    balances: HashMap<UserId, i64>,
+    payments: HashMap<UserId, HashMap<UserId, i64>>,
+}
+
+impl Summaries {
+    /// Returns the summary from the point of view of the user.
+    pub fn for_user(&self, user: UserId) -> SummaryResult {
+        let classifications = [
+            Classification::Business,
+            Classification::Commuting,
+            Classification::Private,
+        ]
+        .into_iter()
+        .map(|classification| {
+            let trips = self
+                .trips
+                .iter()
+                .filter(|trip| trip.classification == classification);
+
+            ClassificationSummary {
+                classification,
+                trips: trips.clone().count() as u64,
+                distance: trips.clone().map(|trip| trip.distance_for(user)).sum(),
+                total_distance: trips.map(|trip| trip.distance()).sum(),
             }
+        })
+        .collect();
+
+        SummaryResult {
+            distance: self
+                .trips
+                .iter()
+                .map(|trip| trip.distance_for(user))
+                .sum::<u64>(),
+            prepaid: self.prepaid.get(&user).copied().unwrap_or_default(),
+            total_distance: self.total_distance,
+            total_amount: self.total_amount,
+            categories: self.categories.clone(),
+            classifications,
+            balances: self.balances.clone(),
+            payments: self.payments.clone(),
         }
     }
-
-    payments
 }
 
-pub async fn summary(
-    auth_session: AuthSession,
-    messages: Messages,
-    Query(SummaryOptions { start, end, user }): Query<SummaryOptions>,
-) -> ApiResult<SummaryResult> {
-    let trips = match list_trips(
-        auth_session.clone(),
-        messages.clone(),
-        Query(ListTripsOptions {
+pub async fn query_summaries(
+    db: &mut SqliteConnection,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    vehicle_id: Option<VehicleId>,
+) -> Result<Summaries, ApiError> {
+    let trips = query_trips(
+        &mut *db,
+        ListTripsOptions {
             start,
             end,
             users: vec![],
-        }),
+            vehicle_id,
+            ..Default::default()
+        },
     )
-    .await
-    {
-        ApiResult::Ok(data) => data,
-        ApiResult::Err(e) => return ApiResult::error(e.to_string()),
-    };
+    .await?
+    .items;
 
-    let expenses = match list_expenses(
-        auth_session.clone(),
-        messages.clone(),
-        Query(ListExpensesOptions {
+    let expenses = query_expenses(
+        &mut *db,
+        ListExpensesOptions {
             start,
             end,
             users: vec![],
-        }),
+            vehicle_id,
+            ..Default::default()
+        },
     )
-    .await
-    {
-        ApiResult::Ok(data) => data,
-        ApiResult::Err(e) => return ApiResult::error(e.to_string()),
-    };
+    .await?
+    .items;
 
-    let users = match list_users(auth_session, messages, Query(ListUsersOptions {})).await {
-        ApiResult::Ok(data) => data,
-        ApiResult::Err(e) => return ApiResult::error(e.to_string()),
-    };
+    let users: Vec<(UserId,)> = sqlx::query_as("select id from users")
+        .fetch_all(&mut *db)
+        .await?;
 
     // sort the user ids to ensure that we always get the same result
-    let mut user_ids = users.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+    let mut user_ids = users.into_iter().map(|(id,)| id).collect::<Vec<_>>();
     user_ids.sort();
 
     // the total amount of money spent on expenses in the given time frame
@@ -140,49 +300,82 @@ pub async fn summary(
         .sum::<u64>();
 
     // calculate the distance driven by each user:
-    let distances = user_ids.iter().map(|id| {
-        trips
+    let distances = user_ids
+        .iter()
+        .map(|id| trips.iter().map(|trip| trip.distance_for(*id)).sum::<u64>())
+        .collect::<Vec<_>>();
+    // calculate the total distance driven by all users
+    let total_distance = distances.iter().sum::<u64>();
+
+    // the expenses of each category are split by the allocation rule of the category
+    let mut totals: BTreeMap<Option<CategoryId>, u64> = BTreeMap::new();
+    for expense in &expenses {
+        *totals.entry(expense.category_id).or_default() += expense.amount as u64;
+    }
+
+    let categories = query_categories(&mut *db).await?;
+    let mut amount_to_pay = vec![0; user_ids.len()];
+    let mut category_summaries = Vec::new();
+
+    for (category_id, total) in totals {
+        let category = categories
             .iter()
-            .map(|trip| trip.distance_for(*id) as u64)
-            .sum::<u64>()
-    });
+            .find(|category| Some(category.id) == category_id);
 
-    // the vec will be overwritten, the distances serve as weights for how much each user should pay
-    let mut amount_to_pay = distances.collect::<Vec<_>>();
-    // calculate the total distance driven by all users
-    let total_distance = amount_to_pay.iter().sum::<u64>();
-    let remainder = utils::divide_proportionally(total_amount, amount_to_pay.as_mut());
-
-    // the user who drove the most should pay the remainder:
-    let max_expense = *amount_to_pay.iter().max().unwrap();
-    for expense in amount_to_pay.iter_mut() {
-        if *expense == max_expense {
-            *expense += remainder;
-            break;
+        // expenses without a category are split by distance
+        let weights = category.map_or_else(
+            || distances.clone(),
+            |category| category.weights(&user_ids, &distances),
+        );
+        let amounts = split(total, weights);
+
+        for (amount_to_pay, amount) in amount_to_pay.iter_mut().zip(&amounts) {
+            *amount_to_pay += amount;
         }
+
+        category_summaries.push(CategorySummary {
+            category_id,
+            name: category.map(|category| category.name.clone()),
+            allocation: category.map_or(Allocation::Distance, |category| category.allocation),
+            total_amount: total,
+            amounts: user_ids.iter().copied().zip(amounts).collect(),
+        });
     }
 
     let mut balances: HashMap<UserId, i64> = HashMap::new();
 
     // register the amount each user has to pay:
-    for (id, amount) in user_ids.iter().zip(amount_to_pay.into_iter()) {
+    for (id, amount) in user_ids.iter().zip(amount_to_pay) {
         *balances.entry(*id).or_default() -= amount as i64;
     }
 
-    let prepaid = expenses
-        .iter()
-        .map(|expense| expense.amount_for(user) as u64)
-        .sum();
+    let mut prepaid: HashMap<UserId, u64> = HashMap::new();
 
     // for each expense, add the amount to the balance of the users who prepaid them
     for expense in expenses {
         // add the amount to the balance of the users who prepaid the expense
-        for (amount, user_id) in
-            utils::divide_equally(expense.amount as u64, expense.users.len() as u64)
-                // we need to sort the users to ensure that the balance is deterministic
-                .zip(utils::sorted_vec(expense.users.iter()).into_iter())
-        {
-            *balances.entry(*user_id).or_default() += amount as i64;
+        for (user_id, amount) in expense.amounts() {
+            *balances.entry(user_id).or_default() += amount as i64;
+            *prepaid.entry(user_id).or_default() += amount;
+        }
+    }
+
+    // settlements are not tied to a vehicle, so they only offset the summary of all vehicles
+    if vehicle_id.is_none() {
+        let settlements = query_settlements(
+            &mut *db,
+            ListSettlementsOptions {
+                start,
+                end,
+                users: vec![],
+            },
+        )
+        .await?;
+
+        // the payer has paid off (part of) their debt, so the receiver gets less
+        for settlement in settlements {
+            *balances.entry(settlement.from_user_id).or_default() += settlement.amount;
+            *balances.entry(settlement.to_user_id).or_default() -= settlement.amount;
         }
     }
 
@@ -195,25 +388,243 @@ pub async fn summary(
     //
     // Then A has to pay 5 to B and 5 to C
 
-    ApiResult::ok(SummaryResult {
-        distance: trips
-            .into_iter()
-            .map(|trip| trip.distance_for(user))
-            .sum::<u64>(),
+    Ok(Summaries {
+        trips,
         prepaid,
-        total_distance,
         total_amount,
+        total_distance,
+        categories: category_summaries,
         balances: balances.clone(),
         payments: calculate_payments(balances),
     })
 }
 
+pub async fn query_summary(
+    db: &mut SqliteConnection,
+    SummaryOptions {
+        start,
+        end,
+        user,
+        vehicle_id,
+    }: SummaryOptions,
+) -> Result<SummaryResult, ApiError> {
+    Ok(query_summaries(db, start, end, vehicle_id)
+        .await?
+        .for_user(user))
+}
+
+pub async fn summary(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<SummaryOptions>,
+) -> ApiResult<SummaryResult> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    query_summary(&mut db, options).await.into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use map_macro::hash_map;
     use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    #[tokio::test]
+    async fn test_settlements_offset_balances() {
+        let db = utils::test_db().await;
+
+        // alice drove, bob paid for the fuel
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end) values (1, 1, '2024-01-01T00:00:00Z', 0, 100)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "insert into expenses (id, vehicle_id, created_at, amount) values (1, 1, '2024-01-01T00:00:00Z', 5000)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "insert into trip_users (trip_id, user_id) values (1, 1); insert into expense_users (expense_id, user_id) values (1, 2)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // alice has paid back half of it
+        sqlx::query(
+            "insert into settlements (from_user_id, to_user_id, amount, created_at) values (1, 2, 2000, '2024-01-15T00:00:00Z')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let summary = |vehicle_id| {
+            let options = SummaryOptions {
+                start: None,
+                end: None,
+                user: 1,
+                vehicle_id,
+            };
+            let db = &db;
+            async move { query_summary(&mut db.acquire().await.unwrap(), options).await }
+        };
+
+        let result = summary(None).await.unwrap();
+        assert_eq!(hash_map! { 1 => -3000, 2 => 3000 }, result.balances);
+        assert_eq!(3000, result.payments[&1][&2]);
+
+        // the settlement is not part of the summary of a single vehicle
+        let result = summary(Some(1)).await.unwrap();
+        assert_eq!(hash_map! { 1 => -5000, 2 => 5000 }, result.balances);
+    }
+
+    #[tokio::test]
+    async fn test_settlements_offset_the_period_they_settle() {
+        let db = utils::test_db().await;
+
+        // the balance of january is paid at the beginning of february
+        sqlx::query(
+            "insert into settlements (from_user_id, to_user_id, amount, created_at, settles_at) values \
+             (1, 2, 2000, '2024-02-03T00:00:00Z', '2024-01-31T00:00:00Z'), \
+             (2, 1, 500, '2024-02-10T00:00:00Z', null)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let balances = |start: &str, end: &str| {
+            let options = SummaryOptions {
+                start: Some(start.parse().unwrap()),
+                end: Some(end.parse().unwrap()),
+                user: 1,
+                vehicle_id: None,
+            };
+            let db = &db;
+            async move { query_summary(&mut db.acquire().await.unwrap(), options).await }
+        };
+
+        let january = balances("2024-01-01T00:00:00Z", "2024-01-31T23:59:59Z")
+            .await
+            .unwrap();
+        assert_eq!(hash_map! { 1 => 2000, 2 => -2000 }, january.balances);
+
+        let february = balances("2024-02-01T00:00:00Z", "2024-02-29T23:59:59Z")
+            .await
+            .unwrap();
+        assert_eq!(hash_map! { 1 => -500, 2 => 500 }, february.balances);
+    }
+
+    #[tokio::test]
+    async fn test_categories_are_split_by_their_allocation() {
+        let db = utils::test_db().await;
+
+        // alice drove 300 km and bob 100 km, alice paid for the fuel and bob for the insurance
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end) values \
+             (1, 1, '2024-01-01T00:00:00Z', 0, 300), (2, 1, '2024-01-02T00:00:00Z', 300, 400); \
+             insert into trip_users (trip_id, user_id) values (1, 1), (2, 2); \
+             insert into expenses (id, vehicle_id, created_at, amount, category_id) values \
+             (1, 1, '2024-01-03T00:00:00Z', 4000, 1), (2, 1, '2024-01-04T00:00:00Z', 6000, 2); \
+             insert into expense_users (expense_id, user_id) values (1, 1), (2, 2)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let result = query_summary(
+            &mut db.acquire().await.unwrap(),
+            SummaryOptions {
+                start: None,
+                end: None,
+                user: 1,
+                vehicle_id: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (Some(1), hash_map! { 1 => 3000, 2 => 1000 }),
+                (Some(2), hash_map! { 1 => 3000, 2 => 3000 }),
+            ],
+            result
+                .categories
+                .into_iter()
+                .map(|category| (category.category_id, category.amounts))
+                .collect::<Vec<_>>()
+        );
+        // alice pays 6000 and has prepaid 4000, bob pays 4000 and has prepaid 6000
+        assert_eq!(hash_map! { 1 => -2000, 2 => 2000 }, result.balances);
+    }
+
+    #[tokio::test]
+    async fn test_totals_per_classification() {
+        let db = utils::test_db().await;
+
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end, classification, destination, purpose) values \
+             (1, 1, '2024-01-01T00:00:00Z', 0, 100, 'business', 'Berlin', 'Meeting'), \
+             (2, 1, '2024-01-02T00:00:00Z', 100, 130, 'commuting', null, null), \
+             (3, 1, '2024-01-03T00:00:00Z', 130, 150, 'commuting', null, null), \
+             (4, 1, '2024-01-04T00:00:00Z', 150, 200, 'business', 'Hamburg', 'Fair'); \
+             insert into trip_users (trip_id, user_id) values (1, 1), (2, 1), (3, 2), (4, 1), (4, 2)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let result = query_summary(
+            &mut db.acquire().await.unwrap(),
+            SummaryOptions {
+                start: None,
+                end: None,
+                user: 1,
+                vehicle_id: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ClassificationSummary {
+                    classification: Classification::Business,
+                    trips: 2,
+                    distance: 125,
+                    total_distance: 150,
+                },
+                ClassificationSummary {
+                    classification: Classification::Commuting,
+                    trips: 2,
+                    distance: 30,
+                    total_distance: 50,
+                },
+                ClassificationSummary {
+                    classification: Classification::Private,
+                    trips: 0,
+                    distance: 0,
+                    total_distance: 0,
+                },
+            ],
+            result.classifications
+        );
+    }
+
+    #[test]
+    fn test_split_gives_the_remainder_to_the_largest_part() {
+        assert_eq!(vec![34, 33, 33], split(100, vec![1, 1, 1]));
+        assert_eq!(vec![10, 91], split(101, vec![10, 90]));
+        assert_eq!(vec![0, 0], split(100, vec![0, 0]));
+    }
 
     #[test]
     fn test_calculate_payments_two_negative() {
@@ -282,4 +693,87 @@ mod tests {
             calculate_payments(balances)
         );
     }
+
+    #[test]
+    fn test_calculate_payments_splits_groups() {
+        // 0 and 3 as well as 1 and 2 can be balanced on their own
+        let balances = hash_map! { 0 => -5, 1 => -3, 2 => 3, 3 => 5 };
+
+        assert_eq!(
+            hash_map! {
+                0 => hash_map! {
+                    3 => 5,
+                },
+                1 => hash_map! {
+                    2 => 3,
+                },
+            },
+            calculate_payments(balances)
+        );
+    }
+
+    /// Balances of up to 8 users that add up to 0, two of them are still settled exactly.
+    fn balanced() -> impl Strategy<Value = Vec<i64>> {
+        proptest::collection::vec(-1000i64..1000, 0..8).prop_map(|mut balances| {
+            balances.push(-balances.iter().sum::<i64>());
+            balances
+        })
+    }
+
+    fn count(payments: &HashMap<UserId, HashMap<UserId, i64>>) -> usize {
+        payments.values().map(HashMap::len).sum()
+    }
+
+    proptest! {
+        #[test]
+        fn test_payments_balance_everyone(balances in balanced()) {
+            let payments = calculate_payments(
+                balances.iter().copied().enumerate().map(|(i, amount)| (i as i64, amount)).collect(),
+            );
+
+            // every debtor pays exactly its debt and every creditor receives its balance
+            let mut remaining = balances.clone();
+            for (from, to) in &payments {
+                for (to, amount) in to {
+                    prop_assert!(*amount > 0);
+                    remaining[*from as usize] += amount;
+                    remaining[*to as usize] -= amount;
+                }
+            }
+            prop_assert!(remaining.iter().all(|amount| *amount == 0));
+
+            let open = balances.iter().filter(|amount| **amount != 0).count();
+            prop_assert!(count(&payments) <= open.saturating_sub(1));
+        }
+
+        #[test]
+        fn test_payments_are_deterministic(balances in balanced()) {
+            let forward = balances.iter().copied().enumerate().map(|(i, amount)| (i as i64, amount));
+            let backward = forward.clone().rev();
+
+            prop_assert_eq!(
+                calculate_payments(forward.collect()),
+                calculate_payments(backward.collect())
+            );
+        }
+
+        #[test]
+        fn test_independent_groups_are_settled_separately(first in balanced(), second in balanced()) {
+            let open = first.iter().chain(&second).filter(|amount| **amount != 0).count();
+            let groups = [&first, &second]
+                .iter()
+                .filter(|group| group.iter().any(|amount| *amount != 0))
+                .count();
+
+            let balances = first
+                .iter()
+                .chain(&second)
+                .copied()
+                .enumerate()
+                .map(|(i, amount)| (i as i64, amount))
+                .collect();
+
+            prop_assert!(count(&calculate_payments(balances)) <= open - groups);
+        }
+    }
 }
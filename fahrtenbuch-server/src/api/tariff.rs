@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use sqlx::SqliteConnection;
+
+use crate::response::ApiError;
+
+/// The price per kilometre that has to be paid from a point in time on.
+#[derive(Debug, Clone, Deserialize, Serialize, FromRow)]
+pub struct Tariff {
+    /// The unique identifier for the tariff.
+    pub id: i64,
+    /// The date from which on the tariff is used, until the next tariff becomes valid.
+    pub valid_from: DateTime<Utc>,
+    /// The price per kilometre in tenths of a cent (0.139 €/km = 139).
+    pub price_per_km: i64,
+}
+
+impl Tariff {
+    /// Returns the price in cents for driving the given distance.
+    ///
+    /// Fractions of a cent are truncated, like the price has always been calculated.
+    pub fn price_for(&self, distance: u64) -> u64 {
+        distance * self.price_per_km as u64 / 10
+    }
+}
+
+/// All tariffs, sorted by the date from which on they are valid.
+#[derive(Debug, Clone, Default)]
+pub struct Tariffs(Vec<Tariff>);
+
+impl Tariffs {
+    pub async fn load(db: &mut SqliteConnection) -> Result<Self, sqlx::Error> {
+        let tariffs = sqlx::query_as("select * from tariffs order by valid_from")
+            .fetch_all(db)
+            .await?;
+
+        Ok(Self::new(tariffs))
+    }
+
+    pub fn new(mut tariffs: Vec<Tariff>) -> Self {
+        tariffs.sort_by_key(|tariff| tariff.valid_from);
+        Self(tariffs)
+    }
+
+    /// Returns the tariff that was in effect at the given date.
+    pub fn at(&self, date: DateTime<Utc>) -> Option<&Tariff> {
+        self.0
+            .iter()
+            .take_while(|tariff| tariff.valid_from <= date)
+            .last()
+    }
+
+    /// Returns the price in cents for a trip of the given distance made at the given date.
+    ///
+    /// If there is no tariff for the date, the trip is free.
+    pub fn price(&self, date: DateTime<Utc>, distance: u64) -> u64 {
+        self.at(date)
+            .map(|tariff| tariff.price_for(distance))
+            .unwrap_or_default()
+    }
+}
+
+/// Ensures that changing the tariffs from the given date on does not change the
+/// price of trips that have already been recorded.
+pub async fn ensure_no_trips_after(
+    db: &mut SqliteConnection,
+    date: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    let (count,): (i64,) = sqlx::query_as(
+        "select count(*) from trips where datetime(created_at, 'utc') >= datetime(?, 'utc')",
+    )
+    .bind(date)
+    .fetch_one(db)
+    .await?;
+
+    if count > 0 {
+        return Err(ApiError::conflict(format!(
+            "There are already {} trips after {}, their price would change",
+            count, date
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    fn tariff(id: i64, month: u32, price_per_km: i64) -> Tariff {
+        Tariff {
+            id,
+            valid_from: Utc.with_ymd_and_hms(2024, month, 1, 0, 0, 0).unwrap(),
+            price_per_km,
+        }
+    }
+
+    #[test]
+    fn test_price_is_truncated_to_cents() {
+        let tariff = tariff(1, 1, 139);
+
+        assert_eq!(0, tariff.price_for(0));
+        // 13.9 cents
+        assert_eq!(13, tariff.price_for(1));
+        // 139 cents
+        assert_eq!(139, tariff.price_for(10));
+        // 208.5 cents
+        assert_eq!(208, tariff.price_for(15));
+    }
+
+    #[test]
+    fn test_price_uses_tariff_in_effect() {
+        let tariffs = Tariffs::new(vec![tariff(2, 3, 160), tariff(1, 1, 139)]);
+
+        let january = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let march = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        let before = Utc.with_ymd_and_hms(2023, 12, 31, 0, 0, 0).unwrap();
+
+        assert_eq!(1390, tariffs.price(january, 100));
+        assert_eq!(1600, tariffs.price(march, 100));
+        assert_eq!(0, tariffs.price(before, 100));
+    }
+
+    #[tokio::test]
+    async fn test_default_tariff() {
+        let db = crate::utils::test_db().await;
+        let tariffs = Tariffs::load(&mut db.acquire().await.unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(1390, tariffs.price(Utc::now(), 100));
+    }
+}
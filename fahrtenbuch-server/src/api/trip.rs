@@ -1,17 +1,49 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use chrono::DateTime;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, SqliteConnection};
 
-use crate::auth::UserId;
-use crate::utils;
+use crate::api::list_trips::list_trip_users;
+use crate::api::vehicle::{self, VehicleId};
+use crate::auth::{self, User, UserId};
+use crate::response::ApiError;
+use crate::utils::{self, SqlBuilderExt};
+
+/// Whether a trip has been made for business, commuting or private reasons.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Classification {
+    /// A business trip, it must have a destination and a purpose.
+    Business,
+    /// A trip between home and the regular place of work.
+    Commuting,
+    #[default]
+    Private,
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Classification::Business => write!(f, "business"),
+            Classification::Commuting => write!(f, "commuting"),
+            Classification::Private => write!(f, "private"),
+        }
+    }
+}
 
 /// This represents an entry in the fahrtenbuch with all the relevant data.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Trip {
     /// The unique identifier for the trip.
     pub id: i64,
+    /// The vehicle that has been driven.
+    pub vehicle_id: VehicleId,
     /// The date when the entry was made.
     pub created_at: DateTime<Utc>,
     /// The start value of the odometer.
@@ -20,10 +52,23 @@ pub struct Trip {
     pub end: u64,
     /// The reason for the trip.
     pub description: Option<String>,
+    pub classification: Classification,
+    /// Where the trip went to, e.g. the address of a customer.
+    pub destination: Option<String>,
+    /// Why the trip has been made, required for business trips.
+    pub purpose: Option<String>,
+    /// Who has been visited on a business trip.
+    pub business_partner: Option<String>,
     /// The associated users for the trip (who pays for the trip?)
     pub users: HashSet<UserId>,
     /// The price of the trip.
     pub price: u64,
+    /// Increased by every correction of the trip, the versions are kept.
+    pub version: i64,
+    /// The hash of the latest version of the trip. The versions of a vehicle are chained
+    /// in the order they have been recorded, not in the order of the odometer, so a
+    /// correction is appended to the chain, see `trip_chain`.
+    pub hash: Option<String>,
 }
 
 impl Trip {
@@ -39,3 +84,156 @@ impl Trip {
             .unwrap_or(0)
     }
 }
+
+/// Returns the users of the trip of the vehicle that ends at `end`.
+pub async fn query_trip_users(
+    db: &mut SqliteConnection,
+    vehicle_id: Option<VehicleId>,
+    end: i64,
+) -> Result<HashSet<UserId>, ApiError> {
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, vehicle_id).await?;
+
+    let Some((id,)): Option<(i64,)> =
+        sqlx::query_as("select id from trips where vehicle_id = ? and end = ?")
+            .bind(vehicle_id)
+            .bind(end)
+            .fetch_optional(&mut *db)
+            .await?
+    else {
+        return Err(ApiError::not_found(format!(
+            "the trip with the end {} does not exist",
+            end
+        )));
+    };
+
+    Ok(list_trip_users(db, [id].into_iter(), vec![])
+        .await?
+        .into_iter()
+        .map(|(_, user)| user)
+        .collect())
+}
+
+/// Ensures that the user is part of all the trips, see `auth::require_participation`.
+pub async fn require_participation(
+    db: &mut SqliteConnection,
+    user: &User,
+    trip_ids: Vec<i64>,
+) -> Result<(), ApiError> {
+    // without any ids, the filter would not restrict the users at all
+    if trip_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut users: HashMap<i64, HashSet<UserId>> =
+        trip_ids.iter().map(|id| (*id, HashSet::new())).collect();
+
+    for (trip_id, user_id) in list_trip_users(db, trip_ids.into_iter(), vec![]).await? {
+        users.entry(trip_id).or_default().insert(user_id);
+    }
+
+    users
+        .values()
+        .try_for_each(|users| auth::require_participation(user, users))
+}
+
+/// A trip that does not start where the previous trip of the vehicle ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OdometerBreak {
+    pub vehicle_id: VehicleId,
+    pub trip_id: i64,
+    /// The end of the previous trip of the vehicle.
+    pub previous_end: i64,
+    pub start: i64,
+}
+
+impl OdometerBreak {
+    /// Whether trips are missing in between, otherwise the trips overlap.
+    pub fn is_gap(&self) -> bool {
+        self.start > self.previous_end
+    }
+}
+
+/// Checks that the trips of each vehicle form a chain without gaps or overlaps.
+///
+/// The first trip of a vehicle can start at any value.
+pub async fn check_odometer(
+    db: &mut SqliteConnection,
+    vehicle_id: Option<VehicleId>,
+) -> Result<Vec<OdometerBreak>, ApiError> {
+    let mut builder = QueryBuilder::new("select id, vehicle_id, start, end from trips");
+    builder.filter().eq("vehicle_id", vehicle_id);
+    builder.push(" order by vehicle_id, start, end, id");
+
+    let trips: Vec<(i64, VehicleId, i64, i64)> =
+        builder.build_query_as().fetch_all(&mut *db).await?;
+
+    Ok(trips
+        .windows(2)
+        .filter_map(|pair| {
+            let (_, previous_vehicle, _, previous_end) = pair[0];
+            let (trip_id, vehicle_id, start, _) = pair[1];
+
+            (previous_vehicle == vehicle_id && previous_end != start).then_some(OdometerBreak {
+                vehicle_id,
+                trip_id,
+                previous_end,
+                start,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::utils;
+
+    #[tokio::test]
+    async fn test_check_odometer() {
+        let db = utils::test_db().await;
+        let mut conn = db.acquire().await.unwrap();
+
+        sqlx::query("insert into vehicles (id, name) values (2, 'van')")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        // the van starts at a different odometer value, which is not a break
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end) values
+                (1, 1, '2024-01-01T00:00:00Z', 0, 100),
+                (2, 1, '2024-01-02T00:00:00Z', 120, 150),
+                (3, 1, '2024-01-03T00:00:00Z', 140, 200),
+                (4, 2, '2024-01-01T00:00:00Z', 500, 600),
+                (5, 2, '2024-01-02T00:00:00Z', 600, 700)",
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        let breaks = check_odometer(&mut conn, None).await.unwrap();
+        assert_eq!(
+            vec![
+                OdometerBreak {
+                    vehicle_id: 1,
+                    trip_id: 2,
+                    previous_end: 100,
+                    start: 120,
+                },
+                OdometerBreak {
+                    vehicle_id: 1,
+                    trip_id: 3,
+                    previous_end: 150,
+                    start: 140,
+                },
+            ],
+            breaks
+        );
+        assert!(breaks[0].is_gap());
+        assert!(!breaks[1].is_gap());
+
+        assert!(check_odometer(&mut conn, Some(2)).await.unwrap().is_empty());
+    }
+}
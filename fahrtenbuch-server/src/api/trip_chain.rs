@@ -0,0 +1,612 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, QueryBuilder, SqliteConnection, SqlitePool};
+
+use crate::api::trip::Classification;
+use crate::api::vehicle::VehicleId;
+use crate::auth::UserId;
+use crate::response::ApiError;
+use crate::utils::{self, SqlBuilderExt};
+
+/// The content of a trip that is recorded in its versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+struct TripContent {
+    id: i64,
+    vehicle_id: VehicleId,
+    created_at: DateTime<Utc>,
+    start: i64,
+    end: i64,
+    description: Option<String>,
+    classification: Classification,
+    destination: Option<String>,
+    purpose: Option<String>,
+    business_partner: Option<String>,
+    /// The users of the trip, sorted by their ids.
+    #[sqlx(skip)]
+    users: Vec<UserId>,
+    version: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct CurrentTrip {
+    #[sqlx(flatten)]
+    content: TripContent,
+    hash: Option<String>,
+}
+
+/// The fields of a version that are covered by its hash, the fields are always
+/// serialized in the same order.
+#[derive(Serialize)]
+struct HashedFields<'a> {
+    previous_hash: &'a str,
+    trip: &'a TripContent,
+    deleted: bool,
+    recorded_at: DateTime<Utc>,
+    recorded_by: Option<UserId>,
+}
+
+/// A recorded version of a trip, the versions can not be changed.
+///
+/// The versions of a vehicle form a chain in the order they have been recorded, each
+/// version links to the previously recorded version of the vehicle and its hash covers
+/// the hash of that version.
+#[derive(Debug, FromRow)]
+struct TripVersion {
+    id: i64,
+    trip_id: i64,
+    version: i64,
+    vehicle_id: VehicleId,
+    created_at: DateTime<Utc>,
+    start: i64,
+    end: i64,
+    description: Option<String>,
+    classification: Classification,
+    destination: Option<String>,
+    purpose: Option<String>,
+    business_partner: Option<String>,
+    users: String,
+    deleted: bool,
+    recorded_at: DateTime<Utc>,
+    recorded_by: Option<UserId>,
+    previous_id: Option<i64>,
+    previous_hash: String,
+    hash: String,
+}
+
+impl TripVersion {
+    fn content(&self) -> TripContent {
+        TripContent {
+            id: self.trip_id,
+            vehicle_id: self.vehicle_id,
+            created_at: self.created_at,
+            start: self.start,
+            end: self.end,
+            description: self.description.clone(),
+            classification: self.classification,
+            destination: self.destination.clone(),
+            purpose: self.purpose.clone(),
+            business_partner: self.business_partner.clone(),
+            users: self
+                .users
+                .split(',')
+                .filter_map(|user| user.parse().ok())
+                .collect(),
+            version: self.version,
+        }
+    }
+
+    /// Hashes the version together with the hash of the previous version of the vehicle.
+    fn calculate_hash(&self) -> String {
+        let fields = HashedFields {
+            previous_hash: &self.previous_hash,
+            trip: &self.content(),
+            deleted: self.deleted,
+            recorded_at: self.recorded_at,
+            recorded_by: self.recorded_by,
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(
+            serde_json::to_string(&fields)
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Returns the current trips of the vehicle, or only the given trip, in the order of
+/// the odometer.
+async fn query_current_trips(
+    db: &mut SqliteConnection,
+    vehicle_id: Option<VehicleId>,
+    trip_id: Option<i64>,
+) -> Result<Vec<CurrentTrip>, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "select id, vehicle_id, created_at, start, end, description, classification, destination, purpose, \
+         business_partner, version, hash from trips",
+    );
+    builder
+        .filter()
+        .eq("vehicle_id", vehicle_id)
+        .eq("id", trip_id);
+    builder.push(" order by vehicle_id, start, end, id");
+
+    let mut trips: Vec<CurrentTrip> = builder.build_query_as().fetch_all(&mut *db).await?;
+
+    let mut builder = QueryBuilder::new("select trip_id, user_id from trip_users");
+    builder
+        .filter()
+        .in_subquery("trip_id", "select id from trips", |trips| {
+            trips.eq("vehicle_id", vehicle_id).eq("id", trip_id);
+        });
+    builder.push(" order by trip_id, user_id");
+
+    let users: Vec<(i64, UserId)> = builder.build_query_as().fetch_all(&mut *db).await?;
+    let mut users = users.into_iter().fold(
+        HashMap::<i64, Vec<UserId>>::new(),
+        |mut map, (trip, user)| {
+            map.entry(trip).or_default().push(user);
+            map
+        },
+    );
+
+    for trip in &mut trips {
+        trip.content.users = users.remove(&trip.content.id).unwrap_or_default();
+    }
+
+    Ok(trips)
+}
+
+/// Records the current state of the trip as a new version at the end of the chain of
+/// its vehicle, the trip keeps the hash of its latest version.
+///
+/// This must be called after every change to a trip, including its deletion.
+pub async fn record_version(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    trip_id: i64,
+) -> Result<(), sqlx::Error> {
+    let latest: Option<TripVersion> = sqlx::query_as(
+        "select * from trip_versions where trip_id = ? order by version desc limit 1",
+    )
+    .bind(trip_id)
+    .fetch_optional(&mut *db)
+    .await?;
+    let version = latest.as_ref().map_or(0, |latest| latest.version) + 1;
+
+    let trip = query_current_trips(&mut *db, None, Some(trip_id))
+        .await?
+        .pop();
+
+    let (content, deleted) = match (trip, latest) {
+        (Some(trip), _) => (
+            TripContent {
+                version,
+                ..trip.content
+            },
+            false,
+        ),
+        // the deletion repeats the latest version of the trip
+        (None, Some(latest)) => (
+            TripContent {
+                version,
+                ..latest.content()
+            },
+            true,
+        ),
+        (None, None) => return Ok(()),
+    };
+
+    let previous: Option<(i64, String)> = sqlx::query_as(
+        "select id, hash from trip_versions where vehicle_id = ? order by id desc limit 1",
+    )
+    .bind(content.vehicle_id)
+    .fetch_optional(&mut *db)
+    .await?;
+    let (previous_id, previous_hash) = previous.unzip();
+
+    let mut row = TripVersion {
+        id: 0,
+        trip_id: content.id,
+        version: content.version,
+        vehicle_id: content.vehicle_id,
+        created_at: content.created_at,
+        start: content.start,
+        end: content.end,
+        description: content.description,
+        classification: content.classification,
+        destination: content.destination,
+        purpose: content.purpose,
+        business_partner: content.business_partner,
+        users: content
+            .users
+            .iter()
+            .map(|user| user.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+        deleted,
+        recorded_at: Utc::now(),
+        recorded_by: actor,
+        previous_id,
+        previous_hash: previous_hash.unwrap_or_default(),
+        hash: String::new(),
+    };
+    row.hash = row.calculate_hash();
+
+    sqlx::query(
+        "insert into trip_versions (trip_id, version, vehicle_id, created_at, start, end, description, classification, \
+         destination, purpose, business_partner, users, deleted, recorded_at, recorded_by, previous_id, previous_hash, hash) \
+         values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(row.trip_id)
+    .bind(row.version)
+    .bind(row.vehicle_id)
+    .bind(row.created_at)
+    .bind(row.start)
+    .bind(row.end)
+    .bind(row.description)
+    .bind(row.classification)
+    .bind(row.destination)
+    .bind(row.purpose)
+    .bind(row.business_partner)
+    .bind(row.users)
+    .bind(row.deleted)
+    .bind(row.recorded_at)
+    .bind(row.recorded_by)
+    .bind(row.previous_id)
+    .bind(row.previous_hash)
+    .bind(&row.hash)
+    .execute(&mut *db)
+    .await?;
+
+    sqlx::query("update trips set version = ?, hash = ? where id = ?")
+        .bind(row.version)
+        .bind(&row.hash)
+        .bind(trip_id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+/// Records the first version of the trips that have been added before the versions
+/// were introduced. Returns the number of recorded trips.
+pub async fn record_unversioned_trips(db: &SqlitePool) -> Result<usize, sqlx::Error> {
+    utils::transaction(db, |tx| {
+        Box::pin(async move {
+            let trips: Vec<(i64,)> = sqlx::query_as(
+                "select id from trips where version = 0 order by vehicle_id, start, end, id",
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            for (trip_id,) in &trips {
+                record_version(&mut *tx, None, *trip_id).await?;
+            }
+
+            Ok(trips.len())
+        })
+    })
+    .await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainProblem {
+    /// A version does not match its hash or does not link to the previous version
+    /// of the vehicle.
+    BrokenLink,
+    /// There is no version of the trip.
+    NotRecorded,
+    /// The trip differs from its latest version.
+    Modified,
+    /// The trip has been removed without recording its deletion.
+    Removed,
+}
+
+/// A trip that has been changed without a trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChainBreak {
+    pub vehicle_id: VehicleId,
+    pub trip_id: i64,
+    pub problem: ChainProblem,
+}
+
+/// Walks the chain of the versions of each vehicle and compares each trip to its
+/// latest version, an empty result means that no trip has been changed without a trace.
+pub async fn verify_chain(
+    db: &mut SqliteConnection,
+    vehicle_id: Option<VehicleId>,
+) -> Result<Vec<ChainBreak>, ApiError> {
+    let mut builder = QueryBuilder::new("select * from trip_versions");
+    builder.filter().eq("vehicle_id", vehicle_id);
+    builder.push(" order by id");
+
+    let versions: Vec<TripVersion> = builder.build_query_as().fetch_all(&mut *db).await?;
+
+    let mut breaks = Vec::new();
+    // the last version of each vehicle and the latest version of each trip
+    let mut previous = HashMap::<VehicleId, (i64, String)>::new();
+    let mut latest = BTreeMap::<i64, TripVersion>::new();
+
+    for version in versions {
+        let (previous_id, previous_hash) = previous
+            .get(&version.vehicle_id)
+            .map_or((None, ""), |(id, hash)| (Some(*id), hash.as_str()));
+
+        if version.previous_id != previous_id
+            || version.previous_hash != previous_hash
+            || version.hash != version.calculate_hash()
+        {
+            breaks.push(ChainBreak {
+                vehicle_id: version.vehicle_id,
+                trip_id: version.trip_id,
+                problem: ChainProblem::BrokenLink,
+            });
+        }
+
+        previous.insert(version.vehicle_id, (version.id, version.hash.clone()));
+        latest.insert(version.trip_id, version);
+    }
+
+    for trip in query_current_trips(&mut *db, vehicle_id, None).await? {
+        let problem = match latest.remove(&trip.content.id) {
+            None => Some(ChainProblem::NotRecorded),
+            Some(version)
+                if version.deleted
+                    || version.content() != trip.content
+                    || trip.hash.as_ref() != Some(&version.hash) =>
+            {
+                Some(ChainProblem::Modified)
+            }
+            Some(_) => None,
+        };
+
+        if let Some(problem) = problem {
+            breaks.push(ChainBreak {
+                vehicle_id: trip.content.vehicle_id,
+                trip_id: trip.content.id,
+                problem,
+            });
+        }
+    }
+
+    breaks.extend(
+        latest
+            .into_values()
+            .filter(|version| !version.deleted)
+            .map(|version| ChainBreak {
+                vehicle_id: version.vehicle_id,
+                trip_id: version.trip_id,
+                problem: ChainProblem::Removed,
+            }),
+    );
+
+    Ok(breaks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::api::add_trip::{self, query_add_trip};
+    use crate::api::update_trip::{self, query_update_trip};
+    use crate::utils;
+
+    async fn add(db: &SqlitePool, start: i64, end: i64) {
+        let data = add_trip::TripData {
+            vehicle_id: None,
+            created_at: None,
+            start,
+            end,
+            description: None,
+            classification: Classification::Private,
+            destination: None,
+            purpose: None,
+            business_partner: None,
+            users: HashSet::from([1]),
+            disable_start_check: false,
+        };
+
+        utils::transaction(db, |tx| Box::pin(query_add_trip(tx, Some(1), data)))
+            .await
+            .unwrap();
+    }
+
+    async fn update(db: &SqlitePool, original_end: i64, end: i64) {
+        let data = update_trip::TripData {
+            vehicle_id: None,
+            original_end,
+            start: None,
+            end: Some(end),
+            description: Some("corrected".to_string()),
+            classification: None,
+            destination: None,
+            purpose: None,
+            business_partner: None,
+            users: HashSet::new(),
+        };
+
+        utils::transaction(db, |tx| Box::pin(query_update_trip(tx, Some(2), data)))
+            .await
+            .unwrap();
+    }
+
+    async fn verify(db: &SqlitePool) -> Vec<(i64, ChainProblem)> {
+        verify_chain(&mut db.acquire().await.unwrap(), None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|chain_break| (chain_break.trip_id, chain_break.problem))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_corrections_are_recorded_as_versions() {
+        let db = utils::test_db().await;
+        add(&db, 0, 100).await;
+        add(&db, 100, 150).await;
+        add(&db, 150, 200).await;
+
+        // the following trip is shifted, so both trips get a new version
+        update(&db, 150, 160).await;
+        assert_eq!(Vec::<(i64, ChainProblem)>::new(), verify(&db).await);
+
+        let versions: Vec<(i64, i64, i64, Option<UserId>)> = sqlx::query_as(
+            "select trip_id, version, end, recorded_by from trip_versions order by id",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![
+                (1, 1, 100, Some(1)),
+                (2, 1, 150, Some(1)),
+                (3, 1, 200, Some(1)),
+                (3, 2, 200, Some(2)),
+                (2, 2, 160, Some(2)),
+            ],
+            versions
+        );
+
+        // each version links to the previous version of the vehicle and the trips
+        // keep the hash of their latest version
+        let links: Vec<(i64, Option<i64>, bool)> = sqlx::query_as(
+            "select id, previous_id, previous_hash = coalesce((select hash from trip_versions as previous \
+             where previous.id = trip_versions.previous_id), '') from trip_versions order by id",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![
+                (1, None, true),
+                (2, Some(1), true),
+                (3, Some(2), true),
+                (4, Some(3), true),
+                (5, Some(4), true),
+            ],
+            links
+        );
+
+        let (hash,): (Option<String>,) = sqlx::query_as("select hash from trips where id = 2")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let (latest,): (String,) = sqlx::query_as("select hash from trip_versions where id = 5")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(Some(latest), hash);
+
+        // the versions can not be changed
+        assert!(
+            sqlx::query("update trip_versions set end = 150 where trip_id = 2")
+                .execute(&db)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes_without_a_version_are_found() {
+        let db = utils::test_db().await;
+        add(&db, 0, 100).await;
+        add(&db, 100, 150).await;
+        add(&db, 150, 200).await;
+
+        sqlx::query("update trips set description = 'changed' where id = 2")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(vec![(2, ChainProblem::Modified)], verify(&db).await);
+
+        // a correction of another trip does not hide the change
+        update(&db, 100, 100).await;
+        assert_eq!(vec![(2, ChainProblem::Modified)], verify(&db).await);
+
+        sqlx::query("delete from trip_users where trip_id = 3; delete from trips where id = 3")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![(2, ChainProblem::Modified), (3, ChainProblem::Removed)],
+            verify(&db).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changed_versions_break_the_chain() {
+        let db = utils::test_db().await;
+        add(&db, 0, 100).await;
+        add(&db, 100, 150).await;
+        add(&db, 150, 200).await;
+
+        sqlx::query(
+            "drop trigger trip_versions_no_update; \
+             update trip_versions set end = 140 where trip_id = 2",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![(2, ChainProblem::BrokenLink), (2, ChainProblem::Modified)],
+            verify(&db).await
+        );
+
+        // a version with a new hash does not match the link of the next version
+        sqlx::query(
+            "update trip_versions set end = 150, hash = 'forged' where trip_id = 2; \
+             update trips set hash = 'forged' where id = 2",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![(2, ChainProblem::BrokenLink), (3, ChainProblem::BrokenLink)],
+            verify(&db).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_unversioned_trips() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into trips (id, vehicle_id, created_at, start, end) values \
+             (1, 1, '2024-01-01T00:00:00Z', 0, 100), (2, 1, '2024-01-02T00:00:00Z', 100, 150); \
+             insert into trip_users (trip_id, user_id) values (1, 1), (2, 2)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (1, ChainProblem::NotRecorded),
+                (2, ChainProblem::NotRecorded)
+            ],
+            verify(&db).await
+        );
+
+        assert_eq!(2, record_unversioned_trips(&db).await.unwrap());
+        assert_eq!(Vec::<(i64, ChainProblem)>::new(), verify(&db).await);
+        // the trips are only recorded once
+        assert_eq!(0, record_unversioned_trips(&db).await.unwrap());
+
+        let versions: Vec<(i64, i64, Option<UserId>)> =
+            sqlx::query_as("select trip_id, version, recorded_by from trip_versions order by id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(vec![(1, 1, None), (2, 1, None)], versions);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::category::{self, Allocation, CategoryId};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+/// Changes a category, this also changes how the past expenses of the category are split.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CategoryData {
+    id: CategoryId,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    allocation: Option<Allocation>,
+    /// Replaces the percentages, they are dropped if the allocation is changed without them.
+    #[serde(default)]
+    percentages: Option<HashMap<UserId, u64>>,
+}
+
+async fn query_update_category(
+    db: &mut SqliteConnection,
+    data: CategoryData,
+) -> Result<(), ApiError> {
+    let current = category::query_category(&mut *db, data.id).await?;
+    let mut category = current.clone();
+
+    if let Some(name) = data.name {
+        category.name = name.trim().to_string();
+    }
+
+    if let Some(allocation) = data.allocation {
+        if allocation != category.allocation {
+            category.percentages.clear();
+        }
+        category.allocation = allocation;
+    }
+
+    if let Some(percentages) = data.percentages {
+        category.percentages = percentages;
+    }
+
+    category::validate_category(&mut *db, &category).await?;
+
+    // the allocation decides how the past expenses are split, so it must not change a settled period
+    if category.allocation != current.allocation || category.percentages != current.percentages {
+        let closed: Option<(i64,)> = sqlx::query_as(
+            "select closed_periods.id from expenses join closed_periods \
+             on closed_periods.reopened_at is null \
+             and datetime(closed_periods.start, 'utc') <= datetime(expenses.created_at, 'utc') \
+             and datetime(expenses.created_at, 'utc') <= datetime(closed_periods.end, 'utc') \
+             where expenses.category_id = ?",
+        )
+        .bind(category.id)
+        .fetch_optional(&mut *db)
+        .await?;
+
+        if let Some((id,)) = closed {
+            return Err(ApiError::conflict(format!(
+                "The category has expenses in the closed period {}, its allocation can not be changed",
+                id
+            )));
+        }
+    }
+
+    sqlx::query("update categories set name = ?, allocation = ? where id = ?")
+        .bind(&category.name)
+        .bind(category.allocation)
+        .bind(category.id)
+        .execute(&mut *db)
+        .await?;
+
+    category::store_percentages(&mut *db, &category).await?;
+
+    Ok(())
+}
+
+pub async fn update_category(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<CategoryData>,
+) -> ApiResult<()> {
+    if let Err(error) = auth::require_permission(&auth_session, Permission::Manage).await {
+        return ApiResult::error(error);
+    }
+
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| Box::pin(query_update_category(tx, data)))
+        .await
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_allocation_of_closed_expenses_can_not_change() {
+        let db = utils::test_db().await;
+        sqlx::query(
+            "insert into expenses (id, vehicle_id, created_at, amount, category_id) values (1, 1, '2024-01-15T00:00:00Z', 1000, 1); \
+             insert into expense_users (expense_id, user_id) values (1, 1); \
+             insert into closed_periods (start, end, closed_at, closed_by, summary) values \
+             ('2024-01-01T00:00:00+00:00', '2024-01-31T23:59:59+00:00', '2024-02-01T00:00:00+00:00', 1, '{}')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let update = |name: Option<&str>, allocation: Option<Allocation>| {
+            let data = CategoryData {
+                id: 1,
+                name: name.map(str::to_string),
+                allocation,
+                percentages: None,
+            };
+            utils::transaction(&db, |tx| Box::pin(query_update_category(tx, data)))
+        };
+
+        let error = update(None, Some(Allocation::Equal)).await.unwrap_err();
+        assert_eq!("conflict", error.code());
+
+        // the name does not change the balances
+        update(Some("petrol"), Some(Allocation::Distance))
+            .await
+            .unwrap();
+
+        // the other categories have no expenses in the closed period
+        let data = CategoryData {
+            id: 2,
+            name: None,
+            allocation: Some(Allocation::Distance),
+            percentages: None,
+        };
+        utils::transaction(&db, |tx| Box::pin(query_update_category(tx, data)))
+            .await
+            .unwrap();
+    }
+}
@@ -1,71 +1,328 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use axum::Json;
+use axum_login::AuthUser;
 use axum_messages::Messages;
 
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::SqlitePool;
+use sqlx::SqliteConnection;
 
-use crate::auth::{AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::api::category::{self, CategoryId};
+use crate::api::list_expenses::{query_expense_users, store_expense_users, validate_shares};
+use crate::api::list_users;
+use crate::api::period;
+use crate::api::vehicle::{self, VehicleId};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, User, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExpenseData {
     id: i64,
+    /// Moves the expense to another vehicle.
+    #[serde(default)]
+    vehicle_id: Option<VehicleId>,
     #[serde(default)]
     amount: Option<u64>,
     #[serde(default)]
     description: Option<String>,
+    /// Moves the expense to another category.
+    #[serde(default)]
+    category_id: Option<CategoryId>,
+    /// Replaces the users, the amount is split equally between them unless `shares` is set.
     #[serde(default)]
     users: HashSet<UserId>,
+    /// Replaces how much each user has paid, `users` can be omitted.
+    #[serde(default)]
+    shares: HashMap<UserId, u64>,
+}
+
+impl ExpenseData {
+    /// The new users of the expense, if they are changed.
+    fn users(&self) -> Option<HashSet<UserId>> {
+        if !self.users.is_empty() {
+            Some(self.users.clone())
+        } else if !self.shares.is_empty() {
+            Some(self.shares.keys().copied().collect())
+        } else {
+            None
+        }
+    }
 }
 
-async fn query_update_expense(db: &SqlitePool, data: ExpenseData) -> anyhow::Result<()> {
+async fn query_update_expense(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: ExpenseData,
+) -> Result<(), ApiError> {
+    let snapshot = audit::snapshot(&mut *db, Entity::Expense, data.id).await?;
+
+    if snapshot.is_none() {
+        return Err(ApiError::not_found(format!(
+            "The expense {} does not exist",
+            data.id
+        )));
+    }
+
+    let (created_at,): (DateTime<Utc>,) =
+        sqlx::query_as("select created_at from expenses where id = ?")
+            .bind(data.id)
+            .fetch_one(&mut *db)
+            .await?;
+    period::ensure_open(&mut *db, [created_at]).await?;
+
+    if let Some(vehicle_id) = data.vehicle_id {
+        let vehicle_id = vehicle::resolve_vehicle(&mut *db, Some(vehicle_id)).await?;
+
+        sqlx::query("update expenses set vehicle_id = ? where id = ?")
+            .bind(vehicle_id)
+            .bind(data.id)
+            .execute(&mut *db)
+            .await?;
+    }
+
     if let Some(amount) = data.amount {
+        let refuel: Option<(i64,)> = sqlx::query_as("select id from refuels where expense_id = ?")
+            .bind(data.id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+        if refuel.is_some() {
+            return Err(ApiError::conflict(
+                "The amount of a refuel is calculated from the fuel and its price",
+            ));
+        }
+
         if amount == 0 {
-            return Err(anyhow::anyhow!("The amount must be greater than 0"));
+            return Err(ApiError::invalid_field(
+                "amount",
+                "The amount must be greater than 0",
+            ));
         }
 
         sqlx::query("update expenses set amount = ? where id = ?")
             .bind(amount as i64)
             .bind(data.id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
     }
 
-    if let Some(description) = data.description {
+    if let Some(description) = &data.description {
         sqlx::query("update expenses set description = ? where id = ?")
             .bind(description)
             .bind(data.id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
     }
 
-    if !data.users.is_empty() {
-        sqlx::query("delete from expense_users where expense_id = ?")
+    if let Some(category_id) = data.category_id {
+        category::ensure_category_exists(&mut *db, category_id).await?;
+
+        sqlx::query("update expenses set category_id = ? where id = ?")
+            .bind(category_id)
             .bind(data.id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
+    }
 
-        for user_id in data.users {
-            sqlx::query("insert into expense_users (expense_id, user_id) values (?, ?)")
-                .bind(data.id)
-                .bind(user_id)
-                .execute(db)
-                .await?;
-        }
+    if let Some(users) = data.users() {
+        list_users::validate_users(&mut *db, &users).await?;
+        store_expense_users(&mut *db, data.id, &users, &data.shares).await?;
     }
 
+    // the shares must still add up to the amount, if only one of them has changed
+    let (amount,): (i64,) = sqlx::query_as("select amount from expenses where id = ?")
+        .bind(data.id)
+        .fetch_one(&mut *db)
+        .await?;
+    let rows: Vec<(UserId, Option<i64>)> =
+        sqlx::query_as("select user_id, amount from expense_users where expense_id = ?")
+            .bind(data.id)
+            .fetch_all(&mut *db)
+            .await?;
+
+    validate_shares(
+        amount as u64,
+        &rows.iter().map(|(user, _)| *user).collect(),
+        &rows
+            .iter()
+            .filter_map(|(user, share)| Some((*user, (*share)? as u64)))
+            .collect(),
+    )?;
+
+    audit::record_change(db, actor, Entity::Expense, data.id, snapshot).await?;
+
     Ok(())
 }
 
+/// Updates the expense as the user, who must be part of it before and after the update.
+async fn update_expense_as(
+    db: &mut SqliteConnection,
+    user: &User,
+    data: ExpenseData,
+) -> Result<(), ApiError> {
+    let users = query_expense_users(&mut *db, data.id).await?;
+    auth::require_participation(user, &users)?;
+    if let Some(users) = data.users() {
+        auth::require_participation(user, &users)?;
+    }
+
+    query_update_expense(db, Some(user.id()), data).await
+}
+
 pub async fn update_expense(
     auth_session: AuthSession,
     _messages: Messages,
     Json(data): Json<ExpenseData>,
-) -> ApiResult<Option<()>> {
-    match query_update_expense(auth_session.backend.db().await, data).await {
-        Ok(_) => ApiResult::empty(),
-        Err(e) => ApiResult::error(format!("Failed to update expense: {:?}", e)),
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move { update_expense_as(tx, &user, data).await })
+    })
+    .await
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_failed_update_expense_is_rolled_back() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into expenses (id, vehicle_id, created_at, amount) values (1, 1, datetime('now'), 1000)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("insert into expense_users (expense_id, user_id) values (1, 1)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        // the amount is updated before the users are validated, which fails because the user 3 does not exist
+        let data = ExpenseData {
+            id: 1,
+            vehicle_id: None,
+            amount: Some(2000),
+            description: Some("changed".to_string()),
+            category_id: None,
+            users: HashSet::from([3]),
+            shares: HashMap::new(),
+        };
+        let result =
+            utils::transaction(&db, |tx| Box::pin(query_update_expense(tx, Some(1), data))).await;
+        assert!(matches!(result, Err(ApiError::Validation { .. })));
+
+        let (amount, description): (i64, Option<String>) =
+            sqlx::query_as("select amount, description from expenses where id = 1")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        let users: Vec<(UserId,)> = sqlx::query_as("select user_id from expense_users")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+
+        assert_eq!((1000, None), (amount, description));
+        assert_eq!(vec![(1,)], users);
+    }
+
+    #[tokio::test]
+    async fn test_shares_follow_the_amount() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into expenses (id, vehicle_id, created_at, amount) values (1, 1, datetime('now'), 6000)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("insert into expense_users (expense_id, user_id, amount) values (1, 1, 4000), (1, 2, 2000)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let update = |amount: Option<u64>, users: &[UserId], shares: &[(UserId, u64)]| {
+            let data = ExpenseData {
+                id: 1,
+                vehicle_id: None,
+                amount,
+                description: None,
+                category_id: None,
+                users: users.iter().copied().collect(),
+                shares: shares.iter().copied().collect(),
+            };
+
+            utils::transaction(&db, |tx| Box::pin(query_update_expense(tx, Some(1), data)))
+        };
+
+        // the shares no longer match the amount
+        assert!(update(Some(3000), &[], &[]).await.is_err());
+        update(Some(3000), &[], &[(1, 1000), (2, 2000)])
+            .await
+            .unwrap();
+        // changing the users drops the shares
+        update(Some(5000), &[1, 2], &[]).await.unwrap();
+
+        let shares: Vec<(UserId, Option<i64>)> =
+            sqlx::query_as("select user_id, amount from expense_users order by user_id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+
+        assert_eq!(vec![(1, None), (2, None)], shares);
+    }
+
+    #[tokio::test]
+    async fn test_members_can_not_reassign_their_expenses() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into expenses (id, vehicle_id, created_at, amount) values (1, 1, datetime('now'), 1000)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("insert into expense_users (expense_id, user_id) values (1, 2)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let bob = utils::test_user(&db, 2).await;
+        let update_as = |users: HashSet<UserId>, shares: HashMap<UserId, u64>| {
+            let bob = bob.clone();
+            let data = ExpenseData {
+                id: 1,
+                vehicle_id: None,
+                amount: None,
+                description: None,
+                category_id: None,
+                users,
+                shares,
+            };
+            utils::transaction(&db, move |tx| {
+                Box::pin(async move { update_expense_as(tx, &bob, data).await })
+            })
+        };
+
+        // bob would no longer be part of the expense
+        let result = update_as(HashSet::from([1]), HashMap::new()).await;
+        assert_eq!("forbidden", result.unwrap_err().code());
+        let result = update_as(HashSet::new(), HashMap::from([(1, 1000)])).await;
+        assert_eq!("forbidden", result.unwrap_err().code());
+
+        update_as(HashSet::from([1, 2]), HashMap::new())
+            .await
+            .unwrap();
+
+        let users: Vec<(UserId,)> =
+            sqlx::query_as("select user_id from expense_users order by user_id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(vec![(1,), (2,)], users);
     }
 }
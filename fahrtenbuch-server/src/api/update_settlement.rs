@@ -0,0 +1,105 @@
+use axum::Json;
+use axum_login::AuthUser;
+use axum_messages::Messages;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+use crate::api::period;
+use crate::api::settlement::{self, Settlement};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SettlementData {
+    id: i64,
+    #[serde(default)]
+    from_user_id: Option<UserId>,
+    #[serde(default)]
+    to_user_id: Option<UserId>,
+    #[serde(default)]
+    amount: Option<u64>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    settles_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    note: Option<String>,
+}
+
+impl SettlementData {
+    /// Applies the changes to the current settlement.
+    fn apply(&self, current: Settlement) -> Settlement {
+        Settlement {
+            from_user_id: self.from_user_id.unwrap_or(current.from_user_id),
+            to_user_id: self.to_user_id.unwrap_or(current.to_user_id),
+            amount: self.amount.map_or(current.amount, |amount| amount as i64),
+            created_at: self.created_at.unwrap_or(current.created_at),
+            settles_at: self.settles_at.or(current.settles_at),
+            note: self.note.clone().or(current.note),
+            ..current
+        }
+    }
+}
+
+async fn query_update_settlement(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: SettlementData,
+) -> Result<(), ApiError> {
+    let current = settlement::query_settlement(&mut *db, data.id).await?;
+    let mut dates = current.dates();
+
+    let updated = data.apply(current);
+    settlement::validate_settlement(&mut *db, &updated).await?;
+    // neither the old nor the new dates may be inside a closed period
+    dates.extend(updated.dates());
+    period::ensure_open(&mut *db, dates).await?;
+
+    let snapshot = audit::snapshot(&mut *db, Entity::Settlement, data.id).await?;
+
+    sqlx::query(
+        "update settlements set from_user_id = ?, to_user_id = ?, amount = ?, created_at = ?, settles_at = ?, note = ? where id = ?",
+    )
+    .bind(updated.from_user_id)
+    .bind(updated.to_user_id)
+    .bind(updated.amount)
+    .bind(updated.created_at)
+    .bind(updated.settles_at)
+    .bind(updated.note)
+    .bind(data.id)
+    .execute(&mut *db)
+    .await?;
+
+    audit::record_change(db, actor, Entity::Settlement, data.id, snapshot).await?;
+
+    Ok(())
+}
+
+pub async fn update_settlement(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Json(data): Json<SettlementData>,
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move {
+            // members must be part of the settlement before and after the update
+            let current = settlement::query_settlement(&mut *tx, data.id).await?;
+            auth::require_participation(&user, &current.users())?;
+            auth::require_participation(&user, &data.apply(current).users())?;
+
+            query_update_settlement(tx, Some(user.id()), data).await
+        })
+    })
+    .await
+    .into()
+}
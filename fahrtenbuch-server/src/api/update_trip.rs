@@ -1,56 +1,90 @@
 use std::collections::HashSet;
 
 use axum::Json;
+use axum_login::AuthUser;
 use axum_messages::Messages;
 
 use serde::Deserialize;
-use sqlx::SqlitePool;
+use sqlx::SqliteConnection;
 
 use crate::api::add_trip::{validate_trip, TripValidationConfig};
 use crate::api::list_trips::{list_trip_users, TripEntry};
-use crate::api::trip::Trip;
-use crate::auth::{AuthSession, UserId};
-use crate::response::ApiResult;
+use crate::api::period;
+use crate::api::trip::{self, Classification, Trip};
+use crate::api::trip_chain;
+use crate::api::vehicle::{self, VehicleId};
+use crate::audit::{self, Entity};
+use crate::auth::{self, AuthSession, Permission, User, UserId};
+use crate::response::{ApiError, ApiResult};
+use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct TripData {
+    /// The vehicle of the trip, can be omitted if there is only one.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
     /// The end of the trip before the update.
-    /// This is required to uniquely identify the trip.
-    original_end: i64,
+    /// Together with the vehicle, this is required to uniquely identify the trip.
+    pub original_end: i64,
+    #[serde(default)]
+    pub start: Option<i64>,
+    #[serde(default)]
+    pub end: Option<i64>,
+    #[serde(default)]
+    pub description: Option<String>,
     #[serde(default)]
-    start: Option<i64>,
+    pub classification: Option<Classification>,
     #[serde(default)]
-    end: Option<i64>,
+    pub destination: Option<String>,
     #[serde(default)]
-    description: Option<String>,
+    pub purpose: Option<String>,
     #[serde(default)]
-    users: HashSet<UserId>,
+    pub business_partner: Option<String>,
+    #[serde(default)]
+    pub users: HashSet<UserId>,
 }
 
-async fn query_update_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()> {
+/// Updates the trip and returns the ids of the neighbouring trips that have been shifted.
+pub async fn query_update_trip(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    data: TripData,
+) -> Result<Vec<i64>, ApiError> {
+    let vehicle_id = vehicle::resolve_vehicle(&mut *db, data.vehicle_id).await?;
+
     let Some(current_trip_entry): Option<TripEntry> =
-        sqlx::query_as("select * from trips where end = ?")
+        sqlx::query_as("select * from trips where vehicle_id = ? and end = ?")
+            .bind(vehicle_id)
             .bind(data.original_end)
-            .fetch_optional(db)
+            .fetch_optional(&mut *db)
             .await?
     else {
-        return Err(anyhow::anyhow!(
+        return Err(ApiError::not_found(format!(
             "the trip with the end {} does not exist",
             data.original_end
-        ));
+        )));
     };
 
+    let snapshot = audit::snapshot(&mut *db, Entity::Trip, current_trip_entry.id).await?;
+
     let mut current_trip = Trip {
         id: current_trip_entry.id,
+        vehicle_id: current_trip_entry.vehicle_id,
         created_at: current_trip_entry.created_at,
         start: current_trip_entry.start as u64,
         end: current_trip_entry.end as u64,
         description: current_trip_entry.description,
+        classification: current_trip_entry.classification,
+        destination: current_trip_entry.destination,
+        purpose: current_trip_entry.purpose,
+        business_partner: current_trip_entry.business_partner,
         users: HashSet::new(),
         price: 0,
+        version: current_trip_entry.version,
+        hash: current_trip_entry.hash,
     };
 
-    current_trip.users = list_trip_users(db, [current_trip.id].into_iter(), vec![])
+    current_trip.users = list_trip_users(&mut *db, [current_trip.id].into_iter(), vec![])
         .await?
         .into_iter()
         .map(|(_, user)| user)
@@ -61,9 +95,10 @@ async fn query_update_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()
         let original_start = current_trip.start as i64;
         current_trip.start = start as u64;
 
-        trip_before = sqlx::query_as("select * from trips where end = ?")
+        trip_before = sqlx::query_as("select * from trips where vehicle_id = ? and end = ?")
+            .bind(vehicle_id)
             .bind(original_start)
-            .fetch_optional(db)
+            .fetch_optional(&mut *db)
             .await?;
     }
 
@@ -73,11 +108,10 @@ async fn query_update_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()
         before.end = current_trip.start as i64;
 
         if before.end <= before.start {
-            return Err(anyhow::anyhow!(
+            return Err(ApiError::conflict(format!(
                 "The trip before the current trip would become invalid: start = {} end = {}",
-                before.start,
-                before.end
-            ));
+                before.start, before.end
+            )));
         }
     }
 
@@ -86,9 +120,10 @@ async fn query_update_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()
         let original_end = current_trip.end as i64;
         current_trip.end = end as u64;
 
-        trip_after = sqlx::query_as("select * from trips where start = ?")
+        trip_after = sqlx::query_as("select * from trips where vehicle_id = ? and start = ?")
+            .bind(vehicle_id)
             .bind(original_end)
-            .fetch_optional(db)
+            .fetch_optional(&mut *db)
             .await?;
     }
 
@@ -98,11 +133,10 @@ async fn query_update_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()
         after.start = current_trip.end as i64;
 
         if after.end <= after.start {
-            return Err(anyhow::anyhow!(
+            return Err(ApiError::conflict(format!(
                 "The trip after the current trip would become invalid: start = {} end = {}",
-                after.start,
-                after.end
-            ));
+                after.start, after.end
+            )));
         }
     }
 
@@ -110,71 +144,298 @@ async fn query_update_trip(db: &SqlitePool, data: TripData) -> anyhow::Result<()
         current_trip.description = Some(description);
     }
 
+    if let Some(classification) = data.classification {
+        current_trip.classification = classification;
+    }
+
+    if let Some(destination) = data.destination {
+        current_trip.destination = Some(destination);
+    }
+
+    if let Some(purpose) = data.purpose {
+        current_trip.purpose = Some(purpose);
+    }
+
+    if let Some(business_partner) = data.business_partner {
+        current_trip.business_partner = Some(business_partner);
+    }
+
     if !data.users.is_empty() {
         current_trip.users = data.users.clone();
     }
 
     validate_trip(
-        db,
+        &mut *db,
         current_trip.clone(),
         TripValidationConfig {
             disable_start_check: false,
             ignore_id: Some(current_trip.id),
             ignore_gaps: true,
+            only_reject_overlaps: false,
         },
     )
     .await?;
 
+    // shifting a neighbouring trip changes its price, so it must be open as well
+    let mut dates = vec![current_trip.created_at];
+    dates.extend(
+        trip_before
+            .iter()
+            .chain(&trip_after)
+            .map(|trip| trip.created_at),
+    );
+    period::ensure_open(&mut *db, dates).await?;
+
+    // the shifts of the neighbouring trips are recorded as changes of their own
+    let mut shifted = Vec::new();
     if let Some(TripEntry { id, end, .. }) = trip_before {
+        let snapshot = audit::snapshot(&mut *db, Entity::Trip, id).await?;
         sqlx::query("update trips set end = ? where id = ?")
             .bind(end)
             .bind(id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
+        audit::record_change(&mut *db, actor, Entity::Trip, id, snapshot).await?;
+        trip_chain::record_version(&mut *db, actor, id).await?;
+        shifted.push(id);
     }
 
     if let Some(TripEntry { id, start, .. }) = trip_after {
+        let snapshot = audit::snapshot(&mut *db, Entity::Trip, id).await?;
         sqlx::query("update trips set start = ? where id = ?")
             .bind(start)
             .bind(id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
+        audit::record_change(&mut *db, actor, Entity::Trip, id, snapshot).await?;
+        trip_chain::record_version(&mut *db, actor, id).await?;
+        shifted.push(id);
     }
 
-    sqlx::query("update trips set start = ?, end = ?, description = ? where id = ?")
-        .bind(current_trip.start as i64)
-        .bind(current_trip.end as i64)
-        .bind(current_trip.description)
-        .bind(current_trip.id)
-        .execute(db)
-        .await?;
+    sqlx::query(
+        "update trips set start = ?, end = ?, description = ?, classification = ?, destination = ?, purpose = ?, \
+         business_partner = ? where id = ?",
+    )
+    .bind(current_trip.start as i64)
+    .bind(current_trip.end as i64)
+    .bind(current_trip.description)
+    .bind(current_trip.classification)
+    .bind(current_trip.destination)
+    .bind(current_trip.purpose)
+    .bind(current_trip.business_partner)
+    .bind(current_trip.id)
+    .execute(&mut *db)
+    .await?;
 
     // update the users associated with the trip:
     if !data.users.is_empty() {
         sqlx::query("delete from trip_users where trip_id = ?")
             .bind(current_trip.id)
-            .execute(db)
+            .execute(&mut *db)
             .await?;
 
         for user_id in current_trip.users {
             sqlx::query("insert into trip_users (trip_id, user_id) values (?, ?)")
                 .bind(current_trip.id)
                 .bind(user_id)
-                .execute(db)
+                .execute(&mut *db)
                 .await?;
         }
     }
 
-    Ok(())
+    audit::record_change(&mut *db, actor, Entity::Trip, current_trip.id, snapshot).await?;
+    trip_chain::record_version(db, actor, current_trip.id).await?;
+
+    Ok(shifted)
+}
+
+/// Updates the trip as the user, who must be part of every trip that is changed.
+async fn update_trip_as(
+    db: &mut SqliteConnection,
+    user: &User,
+    data: TripData,
+) -> Result<(), ApiError> {
+    // members must be part of the trip before and after the update
+    let users = trip::query_trip_users(&mut *db, data.vehicle_id, data.original_end).await?;
+    auth::require_participation(user, &users)?;
+    if !data.users.is_empty() {
+        auth::require_participation(user, &data.users)?;
+    }
+
+    // shifting a neighbouring trip changes its price, so they must be part of it as well
+    let shifted = query_update_trip(&mut *db, Some(user.id()), data).await?;
+    trip::require_participation(db, user, shifted).await
 }
 
 pub async fn update_trip(
     auth_session: AuthSession,
     _messages: Messages,
     Json(data): Json<TripData>,
-) -> ApiResult<Option<()>> {
-    match query_update_trip(auth_session.backend.db().await, data).await {
-        Ok(_) => ApiResult::empty(),
-        Err(e) => ApiResult::error(format!("Failed to update trip: {:?}", e)),
+) -> ApiResult<()> {
+    let user = match auth::require_permission(&auth_session, Permission::Write).await {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+    let db = auth_session.backend.db().await;
+
+    utils::transaction(db, |tx| {
+        Box::pin(async move { update_trip_as(tx, &user, data).await })
+    })
+    .await
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use sqlx::SqlitePool;
+
+    async fn update(db: &SqlitePool, data: TripData) -> Result<Vec<i64>, ApiError> {
+        utils::transaction(db, |tx| Box::pin(query_update_trip(tx, Some(1), data))).await
+    }
+
+    fn update_data(original_end: i64, start: i64, end: i64, users: &[UserId]) -> TripData {
+        TripData {
+            vehicle_id: None,
+            original_end,
+            start: Some(start),
+            end: Some(end),
+            description: None,
+            classification: None,
+            destination: None,
+            purpose: None,
+            business_partner: None,
+            users: users.iter().copied().collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_shifts_neighbours() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+
+        update(&db, update_data(150, 90, 160, &[2])).await.unwrap();
+
+        assert_eq!(
+            vec![(0, 90), (90, 160), (160, 200)],
+            utils::list_chain(&db, 1).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_only_shifts_neighbours_of_the_same_vehicle() {
+        let db = utils::test_db().await;
+        sqlx::query("insert into vehicles (id, name) values (2, 'Van')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        for vehicle_id in [1, 2] {
+            utils::insert_trip(&db, vehicle_id, 0, 100).await;
+            utils::insert_trip(&db, vehicle_id, 100, 150).await;
+            utils::insert_trip(&db, vehicle_id, 150, 200).await;
+        }
+
+        update(
+            &db,
+            TripData {
+                vehicle_id: Some(1),
+                ..update_data(150, 90, 160, &[])
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![(0, 90), (90, 160), (160, 200)],
+            utils::list_chain(&db, 1).await
+        );
+        assert_eq!(
+            vec![(0, 100), (100, 150), (150, 200)],
+            utils::list_chain(&db, 2).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_update_is_rolled_back() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+        sqlx::query(
+            "create trigger fail_trip_users before insert on trip_users when new.user_id = 2 \
+             begin select raise(abort, 'failed'); end",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // the neighbours are shifted before the users are replaced,
+        // which fails because of the trigger
+        let result = update(&db, update_data(150, 90, 160, &[1, 2])).await;
+        assert!(result.is_err());
+
+        assert_eq!(
+            vec![(0, 100), (100, 150), (150, 200)],
+            utils::list_chain(&db, 1).await
+        );
+
+        let trip_users: Vec<(i64, UserId)> =
+            sqlx::query_as("select trip_id, user_id from trip_users order by trip_id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(vec![(1, 1), (2, 1), (3, 1)], trip_users);
+    }
+
+    #[tokio::test]
+    async fn test_members_only_change_their_trips() {
+        let db = utils::test_db().await;
+        utils::insert_trip(&db, 1, 0, 100).await;
+        utils::insert_trip(&db, 1, 100, 150).await;
+        utils::insert_trip(&db, 1, 150, 200).await;
+        sqlx::query("update trip_users set user_id = 2 where trip_id = 2")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let bob = utils::test_user(&db, 2).await;
+        let update_as = |data| {
+            let bob = bob.clone();
+            utils::transaction(&db, move |tx| {
+                Box::pin(async move { update_trip_as(tx, &bob, data).await })
+            })
+        };
+
+        // bob is not part of the trip of alice
+        let result = update_as(TripData {
+            description: Some("changed".to_string()),
+            ..update_data(200, 150, 200, &[])
+        })
+        .await;
+        assert_eq!("forbidden", result.unwrap_err().code());
+
+        // bob can not reassign his trip away from himself
+        let result = update_as(update_data(150, 100, 150, &[1])).await;
+        assert_eq!("forbidden", result.unwrap_err().code());
+
+        // shifting his trip would shift the trips of alice as well
+        let result = update_as(update_data(150, 90, 160, &[])).await;
+        assert_eq!("forbidden", result.unwrap_err().code());
+        assert_eq!(
+            vec![(0, 100), (100, 150), (150, 200)],
+            utils::list_chain(&db, 1).await
+        );
+
+        update_as(TripData {
+            description: Some("changed".to_string()),
+            ..update_data(150, 100, 150, &[1, 2])
+        })
+        .await
+        .unwrap();
     }
 }
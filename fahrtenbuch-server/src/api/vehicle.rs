@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use sqlx::SqliteConnection;
+
+use crate::response::ApiError;
+
+pub type VehicleId = i64;
+
+/// A vehicle that is shared by the users. Each vehicle has its own odometer,
+/// so the trips of a vehicle form their own chain.
+#[derive(Debug, Clone, Deserialize, Serialize, FromRow)]
+pub struct Vehicle {
+    /// The unique identifier for the vehicle.
+    pub id: VehicleId,
+    /// The name of the vehicle (e.g. the license plate).
+    pub name: String,
+}
+
+/// Returns the vehicle that should be used for an entry.
+///
+/// If no vehicle has been provided, this falls back to the only vehicle in the
+/// database, so clients that do not know about vehicles keep working as long as
+/// there is just one.
+pub async fn resolve_vehicle(
+    db: &mut SqliteConnection,
+    vehicle_id: Option<VehicleId>,
+) -> Result<VehicleId, ApiError> {
+    if let Some(vehicle_id) = vehicle_id {
+        let value: Option<(VehicleId,)> = sqlx::query_as("select id from vehicles where id = ?")
+            .bind(vehicle_id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+        return match value {
+            Some((id,)) => Ok(id),
+            None => Err(ApiError::not_found(format!(
+                "The vehicle {} does not exist",
+                vehicle_id
+            ))),
+        };
+    }
+
+    let vehicles: Vec<(VehicleId,)> = sqlx::query_as("select id from vehicles")
+        .fetch_all(db)
+        .await?;
+
+    match vehicles.as_slice() {
+        [(id,)] => Ok(*id),
+        [] => Err(ApiError::conflict(
+            "There is no vehicle, please add one first",
+        )),
+        _ => Err(ApiError::invalid_field(
+            "vehicle_id",
+            "There are multiple vehicles, please specify which one to use",
+        )),
+    }
+}
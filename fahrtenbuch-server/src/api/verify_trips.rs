@@ -0,0 +1,32 @@
+use axum::extract::Query;
+use axum_messages::Messages;
+
+use serde::Deserialize;
+
+use crate::api::trip_chain::{self, ChainBreak};
+use crate::api::vehicle::VehicleId;
+use crate::auth::AuthSession;
+use crate::response::ApiResult;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VerifyTripsOptions {
+    /// Only verify the trips of a specific vehicle.
+    #[serde(default)]
+    pub vehicle_id: Option<VehicleId>,
+}
+
+/// Lists the trips that have been changed without a trace, none if the logbook is intact.
+pub async fn verify_trips(
+    auth_session: AuthSession,
+    _messages: Messages,
+    Query(options): Query<VerifyTripsOptions>,
+) -> ApiResult<Vec<ChainBreak>> {
+    let mut db = match auth_session.backend.db().await.acquire().await {
+        Ok(db) => db,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    trip_chain::verify_chain(&mut db, options.vehicle_id)
+        .await
+        .into()
+}
@@ -1,74 +1,85 @@
 use std::str::FromStr;
 
-use axum::Router;
+use axum::http::{header, Method};
+use axum::{middleware, Router};
 use axum_login::{
     login_required,
     tower_sessions::{ExpiredDeletion, Expiry, SessionManagerLayer},
     AuthManagerLayerBuilder,
 };
 use axum_messages::MessagesManagerLayer;
-use log::debug;
+use log::{debug, info};
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::SqlitePool;
 use time::Duration;
-use tokio::net::ToSocketAddrs;
 use tokio::{signal, task::AbortHandle};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 use tower_sessions::cookie::Key;
-use tower_sessions::cookie::SameSite;
 use tower_sessions_sqlx_store::SqliteStore;
 
-use crate::api;
+use crate::api::{self, recurring_expense, trip_chain};
 use crate::auth::{self, AuthBackend};
+use crate::config::Config;
+
+/// How often the recurring expenses are checked for due occurrences.
+const RECURRING_EXPENSES_PERIOD: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
 
 pub struct App {
     db: SqlitePool,
+    config: Config,
 }
 
 impl App {
     /// Connect to the database and run migrations if necessary.
-    pub async fn connect(database: &str) -> anyhow::Result<Self> {
+    pub async fn connect(config: Config) -> anyhow::Result<Self> {
         debug!(
             "Opening database {} with App running in {}",
-            database,
+            config.database_url,
             std::env::current_dir().unwrap().display()
         );
-        let options = SqliteConnectOptions::from_str(database)?.create_if_missing(true);
+        let options = SqliteConnectOptions::from_str(&config.database_url)?
+            .create_if_missing(true)
+            .journal_mode(config.journal_mode.into());
 
         let db = SqlitePoolOptions::new()
-            .max_connections(5)
+            .max_connections(config.max_connections)
             .connect_with(options)
             .await?;
         sqlx::migrate!().run(&db).await?;
 
-        Ok(Self { db })
+        let recorded = trip_chain::record_unversioned_trips(&db).await?;
+        if recorded > 0 {
+            info!("Recorded the first version of {} trips", recorded);
+        }
+
+        Ok(Self { db, config })
     }
 
-    /// Serve the application.
-    pub async fn serve(self, addr: impl ToSocketAddrs) -> anyhow::Result<()> {
+    pub fn db(&self) -> &SqlitePool {
+        &self.db
+    }
+
+    /// Builds the router with the session and auth layers.
+    fn router(self, session_store: SqliteStore, key: Key) -> Router {
         // Session layer.
         //
         // This uses `tower-sessions` to establish a layer that will provide the session
         // as a request extension.
-        let session_store = SqliteStore::new(self.db.clone());
-        session_store.migrate().await?;
-
-        let deletion_task = tokio::task::spawn(
-            session_store
-                .clone()
-                .continuously_delete_expired(tokio::time::Duration::from_secs(60)),
-        );
-
-        // Generate a cryptographic key to sign the session cookie.
-        let key = Key::generate();
-
         let session_layer = SessionManagerLayer::new(session_store)
-            .with_secure(true)
-            .with_same_site(SameSite::Strict)
-            .with_expiry(Expiry::OnInactivity(Duration::days(1)))
+            .with_secure(self.config.cookie_secure)
+            .with_same_site(self.config.cookie_same_site.into())
+            .with_expiry(Expiry::OnInactivity(Duration::hours(
+                self.config.session_lifetime_hours.into(),
+            )))
             .with_signed(key);
 
+        let cors_layer = CorsLayer::new()
+            .allow_origin(self.config.cors_origins())
+            .allow_credentials(true)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]);
+
         // Auth service.
         //
         // This combines the session layer with our backend to establish the auth
@@ -76,29 +87,64 @@ impl App {
         let backend = AuthBackend::new(self.db);
         let auth_layer = AuthManagerLayerBuilder::new(backend, session_layer).build();
 
-        let app = Router::new()
+        Router::new()
             .merge(api::router())
             .route_layer(login_required!(AuthBackend, login_url = "/login"))
+            // runs before the login is required, so api tokens can be used instead of a session
+            .route_layer(middleware::from_fn(auth::bearer_auth))
             .merge(auth::router())
             .layer(MessagesManagerLayer)
             .layer(auth_layer)
-            .layer(CorsLayer::new().allow_credentials(true))
-            .layer(TraceLayer::new_for_http());
+            .layer(cors_layer)
+            .layer(TraceLayer::new_for_http())
+    }
+
+    /// Serve the application.
+    pub async fn serve(self) -> anyhow::Result<()> {
+        let session_store = SqliteStore::new(self.db.clone());
+        session_store.migrate().await?;
+
+        let deletion_task = tokio::task::spawn(
+            session_store
+                .clone()
+                .continuously_delete_expired(tokio::time::Duration::from_secs(60)),
+        );
+
+        // The first run catches up on the occurrences that were missed while the server was down.
+        let recurring_task = tokio::task::spawn(recurring_expense::continuously_add_due_expenses(
+            self.db.clone(),
+            RECURRING_EXPENSES_PERIOD,
+        ));
+
+        // The key to sign the session cookie is kept, so sessions survive a restart.
+        let key = self.config.load_key()?;
+        let addr = self.config.addr.clone();
+
+        let app = self.router(session_store, key);
 
         let listener = tokio::net::TcpListener::bind(addr).await?;
 
-        // Ensure we use a shutdown signal to abort the deletion task.
+        // Ensure we use a shutdown signal to abort the background tasks.
         axum::serve(listener, app.into_make_service())
-            .with_graceful_shutdown(shutdown_signal(deletion_task.abort_handle()))
+            .with_graceful_shutdown(shutdown_signal(vec![
+                deletion_task.abort_handle(),
+                recurring_task.abort_handle(),
+            ]))
             .await?;
 
         deletion_task.await??;
+        if let Err(error) = recurring_task.await {
+            // the task only stops when it is aborted
+            if !error.is_cancelled() {
+                return Err(error.into());
+            }
+        }
 
         Ok(())
     }
 }
 
-async fn shutdown_signal(deletion_task_abort_handle: AbortHandle) {
+async fn shutdown_signal(abort_handles: Vec<AbortHandle>) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -117,7 +163,378 @@ async fn shutdown_signal(deletion_task_abort_handle: AbortHandle) {
     let terminate = std::future::pending::<()>();
 
     tokio::select! {
-        _ = ctrl_c => { deletion_task_abort_handle.abort() },
-        _ = terminate => { deletion_task_abort_handle.abort() },
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    for handle in abort_handles {
+        handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+
+    use crate::utils;
+
+    struct TestClient {
+        router: Router,
+    }
+
+    struct TestResponse {
+        status: StatusCode,
+        /// The session cookie, if the server has set one.
+        cookie: Option<String>,
+        body: serde_json::Value,
+    }
+
+    impl TestClient {
+        /// Creates the app for a test database, where alice (an admin) and bob
+        /// both have the password `secret`.
+        async fn new() -> Self {
+            let db = utils::test_db().await;
+
+            let hash = password_auth::generate_hash("secret");
+            sqlx::query("update users set password = ?")
+                .bind(hash)
+                .execute(&db)
+                .await
+                .unwrap();
+            sqlx::query("update users set role = 'admin' where id = 1")
+                .execute(&db)
+                .await
+                .unwrap();
+
+            let session_store = SqliteStore::new(db.clone());
+            session_store.migrate().await.unwrap();
+
+            Self {
+                router: App {
+                    db,
+                    config: Config::default(),
+                }
+                .router(session_store, Key::generate()),
+            }
+        }
+
+        /// Sends a request, authenticated with either a session cookie or
+        /// an api token (`Bearer ...`).
+        async fn request(
+            &self,
+            method: &str,
+            path: &str,
+            credentials: Option<&str>,
+            body: serde_json::Value,
+        ) -> TestResponse {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::CONTENT_TYPE, "application/json");
+
+            match credentials {
+                Some(token) if token.starts_with("Bearer ") => {
+                    request = request.header(header::AUTHORIZATION, token);
+                }
+                Some(cookie) => request = request.header(header::COOKIE, cookie),
+                None => {}
+            }
+
+            let response = self
+                .router
+                .clone()
+                .oneshot(request.body(Body::from(body.to_string())).unwrap())
+                .await
+                .unwrap();
+
+            let status = response.status();
+            let cookie = response
+                .headers()
+                .get(header::SET_COOKIE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(';').next())
+                .map(str::to_string);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+
+            TestResponse {
+                status,
+                cookie,
+                body: serde_json::from_slice(&body).unwrap_or_default(),
+            }
+        }
+
+        async fn login(&self, username: &str, password: &str) -> TestResponse {
+            self.request(
+                "POST",
+                "/login",
+                None,
+                serde_json::json!({ "username": username, "password": password }),
+            )
+            .await
+        }
+
+        /// Logs in and returns the session cookie.
+        async fn session(&self, username: &str) -> String {
+            let response = self.login(username, "secret").await;
+            assert_eq!(StatusCode::OK, response.status);
+
+            response.cookie.unwrap()
+        }
+
+        /// Whether the session can access the api.
+        async fn is_logged_in(&self, cookie: &str) -> bool {
+            self.request("GET", "/list_users", Some(cookie), serde_json::Value::Null)
+                .await
+                .status
+                == StatusCode::OK
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_password_invalidates_other_sessions() {
+        let client = TestClient::new().await;
+        let first = client.session("alice").await;
+        let second = client.session("alice").await;
+        assert!(client.is_logged_in(&first).await);
+        assert!(client.is_logged_in(&second).await);
+
+        let response = client
+            .request(
+                "POST",
+                "/change_password",
+                Some(&first),
+                serde_json::json!({ "old_password": "secret", "new_password": "changed" }),
+            )
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        // the session that changed the password has been renewed
+        let first = response.cookie.unwrap_or(first);
+        assert!(client.is_logged_in(&first).await);
+        assert!(!client.is_logged_in(&second).await);
+
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            client.login("alice", "secret").await.status
+        );
+        assert_eq!(
+            StatusCode::OK,
+            client.login("alice", "changed").await.status
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_password_requires_old_password() {
+        let client = TestClient::new().await;
+        let session = client.session("bob").await;
+
+        let response = client
+            .request(
+                "POST",
+                "/change_password",
+                Some(&session),
+                serde_json::json!({ "old_password": "wrong", "new_password": "changed" }),
+            )
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status);
+        assert!(client.is_logged_in(&session).await);
+        assert_eq!(StatusCode::OK, client.login("bob", "secret").await.status);
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_invalidates_sessions() {
+        let client = TestClient::new().await;
+        let admin = client.session("alice").await;
+        let bob = client.session("bob").await;
+
+        // only admins can issue reset tokens
+        let response = client
+            .request(
+                "POST",
+                "/add_password_reset",
+                Some(&bob),
+                serde_json::json!({ "user_id": 1 }),
+            )
+            .await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status);
+
+        let response = client
+            .request(
+                "POST",
+                "/add_password_reset",
+                Some(&admin),
+                serde_json::json!({ "user_id": 2 }),
+            )
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+        let token = response.body["data"]["token"].as_str().unwrap().to_string();
+
+        let reset = serde_json::json!({ "token": token, "new_password": "changed" });
+        let response = client
+            .request("POST", "/reset_password", None, reset.clone())
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        assert!(!client.is_logged_in(&bob).await);
+        assert!(client.is_logged_in(&admin).await);
+        assert_eq!(StatusCode::OK, client.login("bob", "changed").await.status);
+
+        // the token can only be used once
+        let response = client.request("POST", "/reset_password", None, reset).await;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status);
+    }
+
+    async fn api_token(client: &TestClient, session: &str, data: serde_json::Value) -> String {
+        let response = client
+            .request("POST", "/add_api_token", Some(session), data)
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        format!(
+            "Bearer {}",
+            response.body["data"]["token"].as_str().unwrap()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_api_token_can_be_used_instead_of_a_session() {
+        let client = TestClient::new().await;
+        let session = client.session("alice").await;
+        let token = api_token(&client, &session, serde_json::json!({ "name": "script" })).await;
+
+        assert!(client.is_logged_in(&token).await);
+
+        let trip = serde_json::json!({ "start": 0, "end": 100, "users": [1] });
+        let response = client
+            .request("POST", "/add_trip", Some(&token), trip)
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        let response = client
+            .request(
+                "GET",
+                "/list_api_tokens",
+                Some(&session),
+                serde_json::Value::Null,
+            )
+            .await;
+        let tokens = response.body["data"].as_array().unwrap();
+        assert_eq!(1, tokens.len());
+        assert!(!tokens[0]["last_used_at"].is_null());
+
+        let id = tokens[0]["id"].clone();
+        let response = client
+            .request(
+                "POST",
+                "/delete_api_token",
+                Some(&session),
+                serde_json::json!({ "id": id }),
+            )
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        let response = client
+            .request("GET", "/list_users", Some(&token), serde_json::Value::Null)
+            .await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_api_token() {
+        let client = TestClient::new().await;
+        let session = client.session("alice").await;
+        let token = api_token(
+            &client,
+            &session,
+            serde_json::json!({ "name": "dashboard", "read_only": true }),
+        )
+        .await;
+
+        assert!(client.is_logged_in(&token).await);
+
+        let trip = serde_json::json!({ "start": 0, "end": 100, "users": [1] });
+        let response = client
+            .request("POST", "/add_trip", Some(&token), trip)
+            .await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status);
+
+        let response = client
+            .request(
+                "POST",
+                "/add_api_token",
+                Some(&token),
+                serde_json::json!({ "name": "escalation" }),
+            )
+            .await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_api_token_is_rejected() {
+        let client = TestClient::new().await;
+
+        let response = client
+            .request(
+                "GET",
+                "/list_users",
+                Some("Bearer unknown"),
+                serde_json::Value::Null,
+            )
+            .await;
+
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status);
+        assert_eq!("unauthorized", response.body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn test_history_of_a_trip() {
+        let client = TestClient::new().await;
+        let alice = client.session("alice").await;
+        let bob = client.session("bob").await;
+
+        let response = client
+            .request(
+                "POST",
+                "/add_trip",
+                Some(&alice),
+                serde_json::json!({ "start": 0, "end": 100, "users": [1] }),
+            )
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        let response = client
+            .request(
+                "GET",
+                "/list_history?entity=trip&id=1",
+                Some(&bob),
+                serde_json::Value::Null,
+            )
+            .await;
+        assert_eq!(StatusCode::OK, response.status);
+
+        let entries = response.body["data"].as_array().unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(1, entries[0]["actor_id"]);
+        assert_eq!("insert", entries[0]["action"]);
+        assert_eq!(100, entries[0]["after"]["end"]);
+
+        // only admins can see the history of users
+        let response = client
+            .request(
+                "GET",
+                "/list_history?entity=user&id=1",
+                Some(&bob),
+                serde_json::Value::Null,
+            )
+            .await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status);
     }
 }
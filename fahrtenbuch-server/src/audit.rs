@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::types::Json;
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::api::settlement::Settlement;
+use crate::auth::UserId;
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Entity {
+    Trip,
+    Expense,
+    User,
+    Settlement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+    /// The password of a user has been changed, the snapshots never contain it.
+    Password,
+}
+
+/// A change that has been recorded in the audit log.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// The user who made the change, `None` for changes made with the command line.
+    pub actor_id: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+    pub entity: Entity,
+    pub entity_id: i64,
+    pub action: Action,
+    pub before: Option<Json<Value>>,
+    pub after: Option<Json<Value>>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct TripSnapshot {
+    id: i64,
+    vehicle_id: i64,
+    created_at: DateTime<Utc>,
+    start: i64,
+    end: i64,
+    description: Option<String>,
+    classification: String,
+    destination: Option<String>,
+    purpose: Option<String>,
+    business_partner: Option<String>,
+    #[sqlx(skip)]
+    users: Vec<UserId>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct ExpenseSnapshot {
+    id: i64,
+    vehicle_id: Option<i64>,
+    created_at: DateTime<Utc>,
+    amount: i64,
+    description: Option<String>,
+    category_id: Option<i64>,
+    #[sqlx(skip)]
+    users: Vec<UserId>,
+    /// How much each user has paid, empty if the amount is split equally.
+    #[sqlx(skip)]
+    shares: BTreeMap<UserId, i64>,
+}
+
+/// The password hash is left out on purpose.
+#[derive(Debug, Serialize, FromRow)]
+struct UserSnapshot {
+    id: i64,
+    username: String,
+    role: String,
+}
+
+async fn users_of(
+    db: &mut SqliteConnection,
+    query: &str,
+    id: i64,
+) -> Result<Vec<UserId>, sqlx::Error> {
+    let users: Vec<(UserId,)> = sqlx::query_as(query).bind(id).fetch_all(&mut *db).await?;
+
+    Ok(utils::sorted_vec(users.into_iter().map(|(user,)| user)))
+}
+
+/// Returns the current state of the entry as json, or `None` if it does not exist.
+pub async fn snapshot(
+    db: &mut SqliteConnection,
+    entity: Entity,
+    id: i64,
+) -> Result<Option<Value>, sqlx::Error> {
+    match entity {
+        Entity::Trip => {
+            let trip: Option<TripSnapshot> = sqlx::query_as(
+                "select id, vehicle_id, created_at, start, end, description, classification, destination, purpose, business_partner \
+                 from trips where id = ?",
+            )
+            .bind(id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+            let Some(mut trip) = trip else {
+                return Ok(None);
+            };
+            trip.users =
+                users_of(db, "select user_id from trip_users where trip_id = ?", id).await?;
+
+            Ok(Some(json!(trip)))
+        }
+        Entity::Expense => {
+            let expense: Option<ExpenseSnapshot> = sqlx::query_as(
+                "select id, vehicle_id, created_at, amount, description, category_id from expenses where id = ?",
+            )
+            .bind(id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+            let Some(mut expense) = expense else {
+                return Ok(None);
+            };
+            let shares: Vec<(UserId, Option<i64>)> =
+                sqlx::query_as("select user_id, amount from expense_users where expense_id = ?")
+                    .bind(id)
+                    .fetch_all(&mut *db)
+                    .await?;
+
+            expense.users = utils::sorted_vec(shares.iter().map(|(user, _)| *user));
+            expense.shares = shares
+                .into_iter()
+                .filter_map(|(user, share)| Some((user, share?)))
+                .collect();
+
+            Ok(Some(json!(expense)))
+        }
+        Entity::User => {
+            let user: Option<UserSnapshot> =
+                sqlx::query_as("select id, username, role from users where id = ?")
+                    .bind(id)
+                    .fetch_optional(&mut *db)
+                    .await?;
+
+            Ok(user.map(|user| json!(user)))
+        }
+        Entity::Settlement => {
+            let settlement: Option<Settlement> =
+                sqlx::query_as("select * from settlements where id = ?")
+                    .bind(id)
+                    .fetch_optional(&mut *db)
+                    .await?;
+
+            Ok(settlement.map(|settlement| json!(settlement)))
+        }
+    }
+}
+
+/// Appends an entry to the audit log.
+pub async fn record(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    entity: Entity,
+    entity_id: i64,
+    action: Action,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "insert into audit_log (actor_id, created_at, entity, entity_id, action, before, after) values (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(actor)
+    .bind(Utc::now())
+    .bind(entity)
+    .bind(entity_id)
+    .bind(action)
+    .bind(before.map(Json))
+    .bind(after.map(Json))
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Records the change of an entry, `before` must be the [`snapshot`] taken
+/// before the change.
+///
+/// The action is derived from the snapshots, nothing is recorded if the entry
+/// has not changed.
+pub async fn record_change(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    entity: Entity,
+    entity_id: i64,
+    before: Option<Value>,
+) -> Result<(), sqlx::Error> {
+    let after = snapshot(&mut *db, entity, entity_id).await?;
+
+    let action = match (&before, &after) {
+        (None, None) => return Ok(()),
+        (None, Some(_)) => Action::Insert,
+        (Some(_), None) => Action::Delete,
+        (Some(before), Some(after)) if before == after => return Ok(()),
+        (Some(_), Some(_)) => Action::Update,
+    };
+
+    record(db, actor, entity, entity_id, action, before, after).await
+}
+
+/// Returns the recorded changes of an entry, the oldest first.
+pub async fn history(
+    db: &mut SqliteConnection,
+    entity: Entity,
+    entity_id: i64,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as("select * from audit_log where entity = ? and entity_id = ? order by id")
+        .bind(entity)
+        .bind(entity_id)
+        .fetch_all(&mut *db)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_record_change() {
+        let db = utils::test_db().await;
+        let mut conn = db.acquire().await.unwrap();
+
+        sqlx::query("insert into expenses (id, vehicle_id, created_at, amount) values (1, 1, '2024-01-01T00:00:00Z', 100)")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        record_change(&mut conn, Some(1), Entity::Expense, 1, None)
+            .await
+            .unwrap();
+
+        // unchanged entries are not recorded
+        let before = snapshot(&mut conn, Entity::Expense, 1).await.unwrap();
+        record_change(&mut conn, Some(1), Entity::Expense, 1, before.clone())
+            .await
+            .unwrap();
+
+        sqlx::query("delete from expenses where id = 1")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        record_change(&mut conn, Some(2), Entity::Expense, 1, before)
+            .await
+            .unwrap();
+
+        let entries = history(&mut conn, Entity::Expense, 1).await.unwrap();
+        assert_eq!(
+            vec![(Some(1), Action::Insert), (Some(2), Action::Delete)],
+            entries
+                .iter()
+                .map(|entry| (entry.actor_id, entry.action))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(100),
+            entries[1]
+                .before
+                .as_ref()
+                .and_then(|before| before.0["amount"].as_i64())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_is_append_only() {
+        let db = utils::test_db().await;
+        let mut conn = db.acquire().await.unwrap();
+
+        record(
+            &mut conn,
+            None,
+            Entity::User,
+            1,
+            Action::Password,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(sqlx::query("update audit_log set actor_id = 2")
+            .execute(&mut *conn)
+            .await
+            .is_err());
+        assert!(sqlx::query("delete from audit_log")
+            .execute(&mut *conn)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use axum::extract::Request;
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::response::ApiError;
+use crate::token::{self, Token};
+
+use super::{AuthBackend, AuthBackendError, AuthSession, User, UserId};
+
+/// An api token allows scripts to use the api in the name of a user.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: UserId,
+    /// Describes what the token is used for.
+    pub name: String,
+    /// A read-only token can only be used to list and export entries.
+    pub read_only: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Creates a new api token, the returned token must be handed to the user.
+pub async fn create_api_token(
+    db: &mut SqliteConnection,
+    user_id: UserId,
+    name: &str,
+    read_only: bool,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(i64, Token), AuthBackendError> {
+    let token = Token::generate();
+
+    let result = sqlx::query(
+        "insert into api_tokens (user_id, name, token_hash, read_only, created_at, expires_at) values (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(token.hash())
+    .bind(read_only)
+    .bind(Utc::now())
+    .bind(expires_at)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.last_insert_rowid(), token))
+}
+
+impl AuthBackend {
+    /// Returns the user of the api token, if the token is valid.
+    pub async fn authenticate_token(&self, token: &str) -> Result<Option<User>, AuthBackendError> {
+        let now = Utc::now();
+
+        let api_token: Option<ApiToken> =
+            sqlx::query_as("select * from api_tokens where token_hash = ?")
+                .bind(token::hash_token(token))
+                .fetch_optional(self.db().await)
+                .await?;
+
+        let Some(api_token) = api_token.filter(|api_token| {
+            api_token
+                .expires_at
+                .is_none_or(|expires_at| expires_at > now)
+        }) else {
+            return Ok(None);
+        };
+
+        let user: Option<User> = sqlx::query_as("select * from users where id = ?")
+            .bind(api_token.user_id)
+            .fetch_optional(self.db().await)
+            .await?;
+
+        sqlx::query("update api_tokens set last_used_at = ? where id = ?")
+            .bind(now)
+            .bind(api_token.id)
+            .execute(self.db().await)
+            .await?;
+
+        Ok(user.map(|user| User {
+            read_only: api_token.read_only,
+            ..user
+        }))
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Logs in the user of the api token in the `Authorization` header for this request.
+///
+/// Requests without the header are passed on unchanged, so they can use the session.
+pub async fn bearer_auth(mut request: Request, next: Next) -> Response {
+    let Some(token) = bearer_token(request.headers()).map(str::to_string) else {
+        return next.run(request).await;
+    };
+
+    let Some(auth_session) = request.extensions_mut().get_mut::<AuthSession>() else {
+        return ApiError::Internal(anyhow::anyhow!("the auth layer is missing")).into_response();
+    };
+
+    match auth_session.backend.authenticate_token(&token).await {
+        Ok(Some(user)) => auth_session.user = Some(user),
+        Ok(None) => {
+            return ApiError::unauthorized("The api token is invalid or has expired")
+                .into_response()
+        }
+        Err(error) => return ApiError::from(error).into_response(),
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+
+    use crate::utils;
+
+    #[tokio::test]
+    async fn test_expired_token_is_rejected() {
+        let db = utils::test_db().await;
+        let backend = AuthBackend::new(db.clone());
+        let mut conn = db.acquire().await.unwrap();
+
+        let (_, valid) = create_api_token(&mut conn, 1, "valid", true, None)
+            .await
+            .unwrap();
+        let (_, expired) = create_api_token(
+            &mut conn,
+            1,
+            "expired",
+            false,
+            Some(Utc::now() - Duration::days(1)),
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        let user = backend
+            .authenticate_token(&valid.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.read_only);
+
+        assert!(backend
+            .authenticate_token(&expired.to_string())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -2,12 +2,14 @@ use async_trait::async_trait;
 use axum_login::AuthnBackend;
 use password_auth::verify_password;
 use serde::Deserialize;
-use sqlx::SqlitePool;
+use sqlx::{SqliteConnection, SqlitePool};
 use tokio::task;
 
+use crate::audit::{self, Action, Entity};
 use crate::username::Username;
+use crate::utils;
 
-use super::{Credentials, User};
+use super::{invitation, password_reset, Credentials, Role, User};
 
 // We use a type alias for convenience.
 //
@@ -24,6 +26,9 @@ pub struct AuthBackend {
 pub struct RegistrationData {
     #[serde(flatten)]
     pub credentials: Credentials,
+    /// The token of an invitation, required if registration is closed.
+    #[serde(default)]
+    pub invitation: Option<String>,
 }
 
 impl AuthBackend {
@@ -35,15 +40,15 @@ impl AuthBackend {
         &self.db
     }
 
-    pub async fn list_users(&self) -> Result<Vec<(UserId, Username)>, AuthBackendError> {
-        let result = sqlx::query_as("select id, username from users")
+    pub async fn list_users(&self) -> Result<Vec<(UserId, Username, Role)>, AuthBackendError> {
+        let result = sqlx::query_as("select id, username, role from users")
             .fetch_all(&self.db)
             .await?;
 
         Ok(result)
     }
 
-    async fn get_user(&self, username: &Username) -> Result<Option<User>, AuthBackendError> {
+    pub async fn get_user(&self, username: &Username) -> Result<Option<User>, AuthBackendError> {
         let user = sqlx::query_as("select * from users where username = ?")
             .bind(username)
             .fetch_optional(&self.db)
@@ -53,22 +58,196 @@ impl AuthBackend {
     }
 
     pub async fn register(&self, data: RegistrationData) -> Result<(), AuthBackendError> {
+        validate_password(&data.credentials.password)?;
+
+        // checked again in the transaction, this only avoids hashing the password
         if let Some(user) = self.get_user(&data.credentials.username).await? {
             return Err(AuthBackendError::UserAlreadyExists(user.username));
         }
 
+        let hashed_password = {
+            let password = data.credentials.password.clone();
+            task::spawn_blocking(|| password_auth::generate_hash(password)).await?
+        };
+
+        utils::transaction(&self.db, |tx| {
+            Box::pin(async move {
+                ensure_new_user(&mut *tx, &data.credentials.username).await?;
+
+                let (users,): (i64,) = sqlx::query_as("select count(*) from users")
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+                let role = if users == 0 {
+                    // the first user sets up the fahrtenbuch, so they manage it
+                    Role::Admin
+                } else if let Some(token) = &data.invitation {
+                    invitation::consume_invitation(&mut *tx, token, &data.credentials.username)
+                        .await?
+                } else if invitation::is_registration_open(&mut *tx).await? {
+                    Role::Member
+                } else {
+                    return Err(AuthBackendError::RegistrationClosed);
+                };
+
+                let user_id =
+                    sqlx::query("insert into users (username, password, role) values (?, ?, ?)")
+                        .bind(data.credentials.username)
+                        .bind(hashed_password)
+                        .bind(role)
+                        .execute(&mut *tx)
+                        .await?
+                        .last_insert_rowid();
+
+                // the new user is the actor of their own registration
+                audit::record_change(&mut *tx, Some(user_id), Entity::User, user_id, None).await?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Creates a user with the role, even if registration is closed.
+    pub async fn create_user(
+        &self,
+        actor: Option<UserId>,
+        credentials: Credentials,
+        role: Role,
+    ) -> Result<User, AuthBackendError> {
+        validate_password(&credentials.password)?;
+
+        // checked again in the transaction, this only avoids hashing the password
+        if let Some(user) = self.get_user(&credentials.username).await? {
+            return Err(AuthBackendError::UserAlreadyExists(user.username));
+        }
+
         let hashed_password =
-            task::spawn_blocking(|| password_auth::generate_hash(data.credentials.password))
+            task::spawn_blocking(|| password_auth::generate_hash(credentials.password)).await?;
+
+        utils::transaction(&self.db, |tx| {
+            Box::pin(async move {
+                ensure_new_user(&mut *tx, &credentials.username).await?;
+
+                let user: User = sqlx::query_as(
+                    "insert into users (username, password, role) values (?, ?, ?) returning *",
+                )
+                .bind(credentials.username)
+                .bind(hashed_password)
+                .bind(role)
+                .fetch_one(&mut *tx)
                 .await?;
+                audit::record_change(&mut *tx, actor, Entity::User, user.id, None).await?;
+
+                Ok(user)
+            })
+        })
+        .await
+    }
 
-        sqlx::query("insert into users (username, password) values (?, ?)")
-            .bind(data.credentials.username)
-            .bind(hashed_password)
-            .execute(&self.db)
+    /// Sets a new password if the old password is correct and returns the updated user.
+    ///
+    /// All sessions of the user become invalid, because the password hash is the
+    /// session auth hash.
+    pub async fn change_password(
+        &self,
+        user_id: UserId,
+        old_password: String,
+        new_password: String,
+    ) -> Result<User, AuthBackendError> {
+        validate_password(&new_password)?;
+
+        let user: User = sqlx::query_as("select * from users where id = ?")
+            .bind(user_id)
+            .fetch_one(&self.db)
             .await?;
 
-        Ok(())
+        let hash = user.password.clone();
+        task::spawn_blocking(move || verify_password(old_password, &hash))
+            .await?
+            .map_err(|_| AuthBackendError::WrongPassword)?;
+
+        utils::transaction(&self.db, |tx| {
+            Box::pin(set_password(tx, Some(user_id), user_id, new_password))
+        })
+        .await
     }
+
+    /// Sets a new password with a reset token that has been issued by an admin.
+    pub async fn reset_password(
+        &self,
+        token: &str,
+        new_password: String,
+    ) -> Result<(), AuthBackendError> {
+        validate_password(&new_password)?;
+
+        let token = token.to_string();
+        utils::transaction(&self.db, |tx| {
+            Box::pin(async move {
+                let user_id = password_reset::consume_password_reset(&mut *tx, &token).await?;
+                set_password(&mut *tx, Some(user_id), user_id, new_password).await?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+}
+
+/// Ensures that there is no user with the username yet.
+async fn ensure_new_user(
+    db: &mut SqliteConnection,
+    username: &Username,
+) -> Result<(), AuthBackendError> {
+    let user: Option<User> = sqlx::query_as("select * from users where username = ?")
+        .bind(username)
+        .fetch_optional(db)
+        .await?;
+
+    match user {
+        Some(user) => Err(AuthBackendError::UserAlreadyExists(user.username)),
+        None => Ok(()),
+    }
+}
+
+fn validate_password(password: &str) -> Result<(), AuthBackendError> {
+    if password.is_empty() {
+        return Err(AuthBackendError::InvalidPassword(
+            "The password must not be empty".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sets the password without checking the old one.
+pub async fn set_password(
+    db: &mut SqliteConnection,
+    actor: Option<UserId>,
+    user_id: UserId,
+    password: String,
+) -> Result<User, AuthBackendError> {
+    let hashed_password = task::spawn_blocking(|| password_auth::generate_hash(password)).await?;
+
+    let user = sqlx::query_as("update users set password = ? where id = ? returning *")
+        .bind(hashed_password)
+        .bind(user_id)
+        .fetch_one(&mut *db)
+        .await?;
+
+    let snapshot = audit::snapshot(&mut *db, Entity::User, user_id).await?;
+    audit::record(
+        db,
+        actor,
+        Entity::User,
+        user_id,
+        Action::Password,
+        snapshot.clone(),
+        snapshot,
+    )
+    .await?;
+
+    Ok(user)
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -79,6 +258,16 @@ pub enum AuthBackendError {
     TaskJoin(#[from] task::JoinError),
     #[error("The user '{0}' already exists")]
     UserAlreadyExists(Username),
+    #[error("Registration is closed, you need an invitation")]
+    RegistrationClosed,
+    #[error("The invitation is invalid, expired or has already been used")]
+    InvalidInvitation,
+    #[error("The reset token is invalid, expired or has already been used")]
+    InvalidResetToken,
+    #[error("The old password is wrong")]
+    WrongPassword,
+    #[error("{0}")]
+    InvalidPassword(String),
 }
 
 #[async_trait]
@@ -115,3 +304,142 @@ impl AuthnBackend for AuthBackend {
         Ok(user)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use chrono::{Duration, Utc};
+    use pretty_assertions::assert_eq;
+
+    use crate::utils;
+
+    fn registration(username: &str) -> RegistrationData {
+        RegistrationData {
+            credentials: Credentials {
+                username: Username::from_str(username).unwrap(),
+                password: "secret".to_string(),
+            },
+            invitation: None,
+        }
+    }
+
+    async fn roles(db: &SqlitePool) -> Vec<(Username, Role)> {
+        sqlx::query_as("select username, role from users order by id")
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    fn username(name: &str) -> Username {
+        Username::from_str(name).unwrap()
+    }
+
+    async fn invite(db: &SqlitePool, username: Option<&str>, expires_in: Duration) -> String {
+        let mut conn = db.acquire().await.unwrap();
+        let (_, token) = invitation::create_invitation(
+            &mut conn,
+            1,
+            username.map(self::username),
+            Role::Viewer,
+            Utc::now() + expires_in,
+        )
+        .await
+        .unwrap();
+
+        token.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_first_user_becomes_admin() {
+        let db = utils::test_db().await;
+        sqlx::query("delete from users").execute(&db).await.unwrap();
+
+        let backend = AuthBackend::new(db.clone());
+        backend.register(registration("alice")).await.unwrap();
+
+        assert_eq!(vec![(username("alice"), Role::Admin)], roles(&db).await);
+    }
+
+    #[tokio::test]
+    async fn test_registration_is_closed_without_invitation() {
+        let db = utils::test_db().await;
+        let backend = AuthBackend::new(db.clone());
+
+        assert!(matches!(
+            backend.register(registration("charlie")).await,
+            Err(AuthBackendError::RegistrationClosed)
+        ));
+
+        invitation::set_registration_open(&mut db.acquire().await.unwrap(), true)
+            .await
+            .unwrap();
+        backend.register(registration("charlie")).await.unwrap();
+
+        assert_eq!(
+            (username("charlie"), Role::Member),
+            roles(&db).await.pop().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invitation_is_single_use() {
+        let db = utils::test_db().await;
+        let backend = AuthBackend::new(db.clone());
+        let token = invite(&db, None, Duration::days(1)).await;
+
+        backend
+            .register(RegistrationData {
+                invitation: Some(token.clone()),
+                ..registration("charlie")
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            (username("charlie"), Role::Viewer),
+            roles(&db).await.pop().unwrap()
+        );
+
+        assert!(matches!(
+            backend
+                .register(RegistrationData {
+                    invitation: Some(token),
+                    ..registration("dave")
+                })
+                .await,
+            Err(AuthBackendError::InvalidInvitation)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_invitations_are_rejected() {
+        let db = utils::test_db().await;
+        let backend = AuthBackend::new(db.clone());
+
+        let expired = invite(&db, None, Duration::days(-1)).await;
+        let bound = invite(&db, Some("dave"), Duration::days(1)).await;
+
+        for token in [expired, bound.clone(), "unknown".to_string()] {
+            assert!(matches!(
+                backend
+                    .register(RegistrationData {
+                        invitation: Some(token),
+                        ..registration("charlie")
+                    })
+                    .await,
+                Err(AuthBackendError::InvalidInvitation)
+            ));
+        }
+
+        // the bound invitation can still be used by the right user
+        backend
+            .register(RegistrationData {
+                invitation: Some(bound),
+                ..registration("Dave")
+            })
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+use axum::Json;
+use axum_messages::Messages;
+use serde::Deserialize;
+
+use super::AuthSession;
+use crate::response::{ApiError, ApiResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangePasswordData {
+    old_password: String,
+    new_password: String,
+}
+
+pub async fn change_password(
+    mut auth_session: AuthSession,
+    messages: Messages,
+    Json(data): Json<ChangePasswordData>,
+) -> ApiResult<Option<()>> {
+    let Some(user) = auth_session.user.clone() else {
+        return ApiResult::error(ApiError::unauthorized("You are not logged in"));
+    };
+
+    let user = match auth_session
+        .backend
+        .change_password(user.id, data.old_password, data.new_password)
+        .await
+    {
+        Ok(user) => user,
+        Err(error) => return ApiResult::error(error),
+    };
+
+    // all other sessions of the user are invalid now, only this one is renewed
+    if let Err(error) = auth_session.login(&user).await {
+        return ApiResult::error(anyhow::Error::from(error));
+    }
+
+    messages.success("Successfully changed the password");
+
+    ApiResult::empty()
+}
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::token::{self, SingleUse, Token};
+use crate::username::Username;
+
+use super::{AuthBackendError, Role, UserId};
+
+/// An invitation allows one person to register, even if registration is closed.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Invitation {
+    pub id: i64,
+    /// If set, only this username can be registered with the invitation.
+    pub username: Option<Username>,
+    /// The role the invited user will get.
+    pub role: Role,
+    pub created_by: UserId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// Creates a new invitation, the returned token must be handed to the invited person.
+pub async fn create_invitation(
+    db: &mut SqliteConnection,
+    created_by: UserId,
+    username: Option<Username>,
+    role: Role,
+    expires_at: DateTime<Utc>,
+) -> Result<(i64, Token), AuthBackendError> {
+    let token = Token::generate();
+
+    let result = sqlx::query(
+        "insert into invitations (token_hash, username, role, created_by, created_at, expires_at) values (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(token.hash())
+    .bind(username)
+    .bind(role)
+    .bind(created_by)
+    .bind(Utc::now())
+    .bind(expires_at)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.last_insert_rowid(), token))
+}
+
+/// Marks the invitation as used and returns the role the new user gets.
+///
+/// Fails if the invitation does not exist, has been used, has expired or
+/// is meant for another username.
+pub async fn consume_invitation(
+    db: &mut SqliteConnection,
+    token: &str,
+    username: &Username,
+) -> Result<Role, AuthBackendError> {
+    let invitation: Option<Invitation> =
+        sqlx::query_as("select * from invitations where token_hash = ?")
+            .bind(token::hash_token(token))
+            .fetch_optional(&mut *db)
+            .await?;
+
+    let invitation = invitation
+        .filter(|invitation| {
+            invitation
+                .username
+                .as_ref()
+                .is_none_or(|bound| bound == username)
+        })
+        .ok_or(AuthBackendError::InvalidInvitation)?;
+
+    if SingleUse::Invitation
+        .consume(&mut *db, token)
+        .await?
+        .is_none()
+    {
+        return Err(AuthBackendError::InvalidInvitation);
+    }
+
+    Ok(invitation.role)
+}
+
+/// Whether anyone can register without an invitation.
+pub async fn is_registration_open(db: &mut SqliteConnection) -> Result<bool, AuthBackendError> {
+    let value: Option<(String,)> =
+        sqlx::query_as("select value from settings where key = 'registration_open'")
+            .fetch_optional(&mut *db)
+            .await?;
+
+    Ok(value.is_some_and(|(value,)| value == "true"))
+}
+
+pub async fn set_registration_open(
+    db: &mut SqliteConnection,
+    open: bool,
+) -> Result<(), AuthBackendError> {
+    sqlx::query(
+        "insert into settings (key, value) values ('registration_open', ?) on conflict(key) do update set value = excluded.value",
+    )
+    .bind(open.to_string())
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
@@ -2,7 +2,7 @@ use axum::Json;
 use axum_messages::Messages;
 
 use super::{AuthSession, Credentials};
-use crate::response::ApiResult;
+use crate::response::{ApiError, ApiResult};
 
 pub async fn login(
     mut auth_session: AuthSession,
@@ -14,13 +14,15 @@ pub async fn login(
         Ok(None) => {
             messages.error("Invalid credentials");
 
-            return ApiResult::error("Failed to login: Invalid credentials".to_string());
+            return ApiResult::error(ApiError::unauthorized(
+                "Failed to login: Invalid credentials",
+            ));
         }
-        Err(_) => return ApiResult::error("Failed to login.".to_string()),
+        Err(error) => return ApiResult::error(anyhow::Error::from(error)),
     };
 
-    if let Err(err) = auth_session.login(&user).await {
-        return ApiResult::error(format!("Failed to login: {:?}", err));
+    if let Err(error) = auth_session.login(&user).await {
+        return ApiResult::error(anyhow::Error::from(error));
     }
 
     messages.success(format!("Successfully logged in as {}", user.username));
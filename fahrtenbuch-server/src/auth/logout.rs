@@ -3,7 +3,7 @@ use crate::response::ApiResult;
 
 pub async fn logout(mut auth_session: AuthSession) -> ApiResult<Option<()>> {
     if let Err(error) = auth_session.logout().await {
-        return ApiResult::error(format!("Internal error: {}", error));
+        return ApiResult::error(anyhow::Error::from(error));
     }
 
     ApiResult::empty()
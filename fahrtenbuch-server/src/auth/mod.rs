@@ -6,14 +6,28 @@ use axum_login::AuthUser;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+mod api_token;
+pub use api_token::*;
+
 mod backend;
 pub use backend::*;
 
+mod invitation;
+pub use invitation::*;
+
+mod password_reset;
+pub use password_reset::*;
+
+mod permission;
+pub use permission::*;
+
 use crate::username::Username;
 
+mod change_password;
 mod login;
 mod logout;
 mod register;
+mod reset_password;
 
 pub fn router() -> Router<()> {
     Router::new()
@@ -21,6 +35,9 @@ pub fn router() -> Router<()> {
         // call this endpoint to register a new user and log in
         .route("/register", post(register::register))
         .route("/logout", get(logout::logout))
+        .route("/change_password", post(change_password::change_password))
+        // a reset token is issued by an admin, so this does not require a login
+        .route("/reset_password", post(reset_password::reset_password))
 }
 
 #[derive(Clone, Serialize, Deserialize, FromRow)]
@@ -28,6 +45,11 @@ pub struct User {
     id: i64,
     pub username: Username,
     pub(super) password: String,
+    pub role: Role,
+    /// Set if the user is authenticated with a read-only api token.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub read_only: bool,
 }
 
 // Here we've implemented `Debug` manually to avoid accidentally logging the
@@ -38,6 +60,8 @@ impl std::fmt::Debug for User {
             .field("id", &self.id)
             .field("username", &self.username)
             .field("password", &"[redacted]")
+            .field("role", &self.role)
+            .field("read_only", &self.read_only)
             .finish()
     }
 }
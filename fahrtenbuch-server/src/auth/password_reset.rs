@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+
+use crate::token::{SingleUse, Token};
+
+use super::{AuthBackendError, UserId};
+
+/// Creates a token that allows to set a new password for the user once.
+pub async fn create_password_reset(
+    db: &mut SqliteConnection,
+    created_by: UserId,
+    user_id: UserId,
+    expires_at: DateTime<Utc>,
+) -> Result<Token, AuthBackendError> {
+    let token = Token::generate();
+
+    sqlx::query(
+        "insert into password_resets (token_hash, user_id, created_by, created_at, expires_at) values (?, ?, ?, ?, ?)",
+    )
+    .bind(token.hash())
+    .bind(user_id)
+    .bind(created_by)
+    .bind(Utc::now())
+    .bind(expires_at)
+    .execute(&mut *db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Marks the reset token as used and returns the user whose password can be set.
+pub async fn consume_password_reset(
+    db: &mut SqliteConnection,
+    token: &str,
+) -> Result<UserId, AuthBackendError> {
+    let Some(id) = SingleUse::PasswordReset.consume(&mut *db, token).await? else {
+        return Err(AuthBackendError::InvalidResetToken);
+    };
+
+    let (user_id,): (UserId,) = sqlx::query_as("select user_id from password_resets where id = ?")
+        .bind(id)
+        .fetch_one(&mut *db)
+        .await?;
+
+    Ok(user_id)
+}
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use axum_login::AuthzBackend;
+use serde::{Deserialize, Serialize};
+
+use crate::response::ApiError;
+
+use super::{AuthBackend, AuthBackendError, AuthSession, User, UserId};
+
+/// The role of a user decides what they are allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Role {
+    /// Can manage everything, including vehicles, tariffs and the roles of users.
+    Admin,
+    /// Can add entries and edit the entries they are part of.
+    Member,
+    /// Can only read the entries.
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// List and export the entries.
+    Read,
+    /// Add entries and edit the entries the user is part of.
+    Write,
+    /// Edit all entries and manage vehicles, tariffs and users.
+    Manage,
+}
+
+impl Role {
+    pub fn permissions(self) -> HashSet<Permission> {
+        match self {
+            Self::Admin => [Permission::Read, Permission::Write, Permission::Manage].into(),
+            Self::Member => [Permission::Read, Permission::Write].into(),
+            Self::Viewer => [Permission::Read].into(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthzBackend for AuthBackend {
+    type Permission = Permission;
+
+    async fn get_user_permissions(
+        &self,
+        user: &Self::User,
+    ) -> Result<HashSet<Self::Permission>, Self::Error> {
+        let mut permissions = user.role.permissions();
+
+        // the token of a script should not be able to do more than necessary
+        if user.read_only {
+            permissions.retain(|permission| *permission == Permission::Read);
+        }
+
+        Ok(permissions)
+    }
+}
+
+/// Returns the logged in user if they have the permission.
+pub async fn require_permission(
+    auth_session: &AuthSession,
+    permission: Permission,
+) -> Result<User, ApiError> {
+    let Some(user) = auth_session.user.clone() else {
+        return Err(ApiError::unauthorized("You are not logged in"));
+    };
+
+    let allowed = auth_session
+        .backend
+        .has_perm(&user, permission)
+        .await
+        .map_err(|error: AuthBackendError| ApiError::from(error))?;
+
+    if !allowed {
+        return Err(ApiError::forbidden(
+            "You do not have the permission to do this",
+        ));
+    }
+
+    Ok(user)
+}
+
+/// Members can only change the entries they are part of, admins can change all entries.
+pub fn require_participation(user: &User, users: &HashSet<UserId>) -> Result<(), ApiError> {
+    if user.role.permissions().contains(&Permission::Manage) || users.contains(&user.id) {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden(
+            "You can only change the entries you are part of",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::username::Username;
+
+    fn user(id: UserId, role: Role) -> User {
+        User {
+            id,
+            username: Username::from_str("alice").unwrap(),
+            password: String::new(),
+            role,
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_roles_are_ordered_by_permissions() {
+        assert!(Role::Admin
+            .permissions()
+            .is_superset(&Role::Member.permissions()));
+        assert!(Role::Member
+            .permissions()
+            .is_superset(&Role::Viewer.permissions()));
+        assert!(!Role::Viewer.permissions().contains(&Permission::Write));
+        assert!(!Role::Member.permissions().contains(&Permission::Manage));
+    }
+
+    #[test]
+    fn test_members_only_change_their_entries() {
+        let users = HashSet::from([2, 3]);
+
+        assert!(require_participation(&user(2, Role::Member), &users).is_ok());
+        assert!(require_participation(&user(1, Role::Admin), &users).is_ok());
+        assert_eq!(
+            "forbidden",
+            require_participation(&user(1, Role::Member), &users)
+                .unwrap_err()
+                .code()
+        );
+    }
+}
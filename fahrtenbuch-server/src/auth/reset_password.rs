@@ -0,0 +1,30 @@
+use axum::Json;
+use axum_messages::Messages;
+use serde::Deserialize;
+
+use super::AuthSession;
+use crate::response::ApiResult;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResetPasswordData {
+    token: String,
+    new_password: String,
+}
+
+pub async fn reset_password(
+    auth_session: AuthSession,
+    messages: Messages,
+    Json(data): Json<ResetPasswordData>,
+) -> ApiResult<Option<()>> {
+    if let Err(error) = auth_session
+        .backend
+        .reset_password(&data.token, data.new_password)
+        .await
+    {
+        return ApiResult::error(error);
+    }
+
+    messages.success("Successfully reset the password");
+
+    ApiResult::empty()
+}
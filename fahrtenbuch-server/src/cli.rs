@@ -0,0 +1,439 @@
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Duration, Months, NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::api::export::{self, ExportSummaryOptions};
+use crate::api::import::{self, ImportData};
+use crate::api::set_role::{self, SetRoleData};
+use crate::api::vehicle::VehicleId;
+use crate::api::{trip, trip_chain};
+use crate::app::App;
+use crate::auth::{self, Credentials, Role};
+use crate::response::ApiError;
+use crate::username::Username;
+use crate::utils;
+
+/// The server of the fahrtenbuch, the subcommands help to administer it.
+///
+/// The configuration is read like for `serve`, see `FAHRTENBUCH_CONFIG`.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Default, Subcommand)]
+pub enum Command {
+    /// Runs the server (the default).
+    #[default]
+    Serve,
+    /// Applies the pending migrations to the database.
+    Migrate,
+    /// Creates a user, the password is read from the terminal or stdin.
+    CreateUser {
+        username: Username,
+        #[arg(long, default_value = "member", value_parser = parse_role)]
+        role: Role,
+    },
+    /// Changes the role of a user.
+    SetRole {
+        username: Username,
+        #[arg(value_parser = parse_role)]
+        role: Role,
+    },
+    /// Sets a new password, the password is read from the terminal or stdin.
+    SetPassword { username: Username },
+    /// Checks that the trips of each vehicle have no gaps or overlaps.
+    CheckOdometer {
+        #[arg(long)]
+        vehicle: Option<VehicleId>,
+    },
+    /// Verifies the hash chain of the trips, to find trips that have been changed without a trace.
+    VerifyTrips {
+        #[arg(long)]
+        vehicle: Option<VehicleId>,
+    },
+    /// Computes the summary of every user for a month (e.g. `2024-03`) as csv.
+    Summary {
+        #[arg(value_parser = parse_month)]
+        month: NaiveDate,
+        #[arg(long)]
+        vehicle: Option<VehicleId>,
+    },
+    /// Exports the data as csv, like the export of the api.
+    Export {
+        kind: ExportKind,
+        /// The file to write to, instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Imports trips and expenses, either all of them are stored or none.
+    Import {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ImportFormat::Json)]
+        format: ImportFormat,
+        /// Only validate the data, nothing will be stored.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Writes a consistent copy of the database to the file.
+    Backup { file: PathBuf },
+}
+
+impl Command {
+    /// The default log level, the subcommands should only log problems.
+    pub fn log_level(&self) -> &'static str {
+        match self {
+            Command::Serve => "trace",
+            _ => "warn",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportKind {
+    Trips,
+    Expenses,
+    Summary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// The format of the `/import` endpoint.
+    Json,
+    TripsCsv,
+    ExpensesCsv,
+}
+
+fn parse_role(role: &str) -> Result<Role, String> {
+    Role::deserialize(
+        serde::de::value::StrDeserializer::<serde::de::value::Error>::new(&role.to_lowercase()),
+    )
+    .map_err(|error| error.to_string())
+}
+
+fn parse_month(month: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+        .map_err(|_| format!("invalid month '{}', expected e.g. 2024-03", month))
+}
+
+/// Returns the first and the last second of the month.
+fn month_range(month: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = month.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let end = start + Months::new(1) - Duration::seconds(1);
+
+    (start, end)
+}
+
+/// Reads the password from the terminal, or from the first line of stdin for scripts.
+fn read_password() -> anyhow::Result<String> {
+    if io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("Password: ")?;
+        if password != rpassword::prompt_password("Repeat the password: ")? {
+            bail!("the passwords do not match");
+        }
+
+        return Ok(password);
+    }
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn user_id(db: &SqlitePool, username: &Username) -> anyhow::Result<auth::UserId> {
+    let user: Option<(auth::UserId,)> = sqlx::query_as("select id from users where username = ?")
+        .bind(username)
+        .fetch_optional(db)
+        .await?;
+
+    match user {
+        Some((id,)) => Ok(id),
+        None => bail!("the user '{}' does not exist", username),
+    }
+}
+
+async fn backup(db: &SqlitePool, file: &Path) -> anyhow::Result<()> {
+    if file.exists() {
+        bail!("the file {} already exists", file.display());
+    }
+
+    // unlike copying the file, this is consistent while the server is running
+    sqlx::query("vacuum into ?")
+        .bind(file.to_string_lossy())
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Writes the rows of an export as they are loaded.
+async fn write_rows(
+    rows: impl Stream<Item = Result<Vec<u8>, ApiError>>,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut rows = pin!(rows);
+    while let Some(chunk) = rows.try_next().await? {
+        writer.write_all(&chunk)?;
+    }
+
+    Ok(writer.flush()?)
+}
+
+/// Runs the command, all commands except `serve` exit when they are done.
+pub async fn run(app: App, command: Command) -> anyhow::Result<()> {
+    let db = app.db().clone();
+
+    match command {
+        Command::Serve => app.serve().await?,
+        Command::Migrate => {
+            // the migrations have been applied when connecting to the database
+            let (version,): (Option<i64>,) =
+                sqlx::query_as("select max(version) from _sqlx_migrations")
+                    .fetch_one(&db)
+                    .await?;
+
+            println!("The database is at version {}", version.unwrap_or_default());
+        }
+        Command::CreateUser { username, role } => {
+            let password = read_password()?;
+            let backend = auth::AuthBackend::new(db);
+            backend
+                .create_user(None, Credentials { username, password }, role)
+                .await?;
+        }
+        Command::SetRole { username, role } => {
+            let user_id = user_id(&db, &username).await?;
+            utils::transaction(&db, |tx| {
+                Box::pin(set_role::query_set_role(
+                    tx,
+                    None,
+                    SetRoleData { user_id, role },
+                ))
+            })
+            .await?;
+        }
+        Command::SetPassword { username } => {
+            let user_id = user_id(&db, &username).await?;
+            let password = read_password()?;
+            if password.is_empty() {
+                bail!("the password must not be empty");
+            }
+
+            utils::transaction(&db, |tx| {
+                Box::pin(auth::set_password(tx, None, user_id, password))
+            })
+            .await?;
+        }
+        Command::CheckOdometer { vehicle } => {
+            let breaks = trip::check_odometer(&mut *db.acquire().await?, vehicle).await?;
+            for odometer_break in &breaks {
+                println!(
+                    "vehicle {}: the trip {} starts at {}, but the previous trip ends at {} ({})",
+                    odometer_break.vehicle_id,
+                    odometer_break.trip_id,
+                    odometer_break.start,
+                    odometer_break.previous_end,
+                    if odometer_break.is_gap() {
+                        "gap"
+                    } else {
+                        "overlap"
+                    }
+                );
+            }
+
+            if !breaks.is_empty() {
+                bail!("the odometer has {} breaks", breaks.len());
+            }
+        }
+        Command::VerifyTrips { vehicle } => {
+            let breaks = trip_chain::verify_chain(&mut *db.acquire().await?, vehicle).await?;
+            for chain_break in &breaks {
+                println!(
+                    "vehicle {}: the trip {} {}",
+                    chain_break.vehicle_id,
+                    chain_break.trip_id,
+                    match chain_break.problem {
+                        trip_chain::ChainProblem::BrokenLink =>
+                            "has a version that does not match its hash or the previous version",
+                        trip_chain::ChainProblem::NotRecorded => "has no recorded version",
+                        trip_chain::ChainProblem::Modified => "differs from its latest version",
+                        trip_chain::ChainProblem::Removed => "has been removed without a trace",
+                    }
+                );
+            }
+
+            if !breaks.is_empty() {
+                bail!("the hash chain of the trips has {} breaks", breaks.len());
+            }
+        }
+        Command::Summary { month, vehicle } => {
+            let (start, end) = month_range(month);
+            let data = export::query_export_summary(
+                &db,
+                ExportSummaryOptions {
+                    start: Some(start),
+                    end: Some(end),
+                    users: vec![],
+                    vehicle_id: vehicle,
+                },
+            )
+            .await?;
+
+            write_rows(data, &mut io::stdout()).await?;
+        }
+        Command::Export { kind, output } => {
+            let data = match kind {
+                ExportKind::Trips => export::query_export_trips(&db, Default::default())
+                    .await?
+                    .boxed(),
+                ExportKind::Expenses => export::query_export_expenses(&db, Default::default())
+                    .await?
+                    .boxed(),
+                ExportKind::Summary => export::query_export_summary(
+                    &db,
+                    ExportSummaryOptions {
+                        start: None,
+                        end: None,
+                        users: vec![],
+                        vehicle_id: None,
+                    },
+                )
+                .await?
+                .boxed(),
+            };
+
+            match output {
+                Some(path) => {
+                    let mut file = fs::File::create(&path)
+                        .with_context(|| format!("failed to create {}", path.display()))?;
+                    write_rows(data, &mut file)
+                        .await
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                }
+                None => write_rows(data, &mut io::stdout()).await?,
+            }
+        }
+        Command::Import {
+            file,
+            format,
+            dry_run,
+        } => {
+            let content = fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+
+            let data = match format {
+                ImportFormat::Json => ImportData {
+                    dry_run,
+                    ..serde_json::from_str(&content)?
+                },
+                ImportFormat::TripsCsv => ImportData {
+                    dry_run,
+                    trips: import::parse_csv_trips(&content)?,
+                    ..Default::default()
+                },
+                ImportFormat::ExpensesCsv => ImportData {
+                    dry_run,
+                    expenses: import::parse_csv_expenses(&content)?,
+                    ..Default::default()
+                },
+            };
+
+            let result = import::query_import(&db, None, data).await?;
+            println!("{}", serde_json::to_string_pretty(&result)?);
+
+            if !result.errors.is_empty() {
+                bail!("{} rows could not be imported", result.errors.len());
+            }
+        }
+        Command::Backup { file } => backup(&db, &file).await?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use clap::CommandFactory;
+    use pretty_assertions::assert_eq;
+    use sqlx::sqlite::SqliteConnectOptions;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli =
+            Cli::try_parse_from(["server", "create-user", "Alice", "--role", "Admin"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::CreateUser {
+                role: Role::Admin,
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["server", "set-role", "alice", "owner"]).is_err());
+    }
+
+    #[test]
+    fn test_month_range() {
+        assert_eq!(
+            (
+                Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 2, 29, 23, 59, 59).unwrap()
+            ),
+            month_range(parse_month("2024-02").unwrap())
+        );
+        assert!(parse_month("2024-13").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_backup() {
+        // `vacuum into` does not write in-memory databases to a file
+        let dir = std::env::temp_dir().join(format!("fahrtenbuch-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("backup.db");
+
+        let db = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(dir.join("data.db"))
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        sqlx::query("insert into users (username, password) values ('alice', '')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        backup(&db, &file).await.unwrap();
+        // an existing backup is never overwritten
+        assert!(backup(&db, &file).await.is_err());
+
+        let copy = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&file))
+            .await
+            .unwrap();
+        let (users,): (i64,) = sqlx::query_as("select count(*) from users")
+            .fetch_one(&copy)
+            .await
+            .unwrap();
+        copy.close().await;
+        db.close().await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(1, users);
+    }
+}
@@ -0,0 +1,367 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use axum::http::HeaderValue;
+use log::info;
+use serde::Deserialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use tower_sessions::cookie::{Key, SameSite};
+
+/// The prefix of the environment variables that override the configuration file.
+const ENV_PREFIX: &str = "FAHRTENBUCH_";
+
+/// The configuration file is read from this path, unless `FAHRTENBUCH_CONFIG` is set.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSitePolicy> for SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl From<JournalMode> for SqliteJournalMode {
+    fn from(mode: JournalMode) -> Self {
+        match mode {
+            JournalMode::Delete => SqliteJournalMode::Delete,
+            JournalMode::Truncate => SqliteJournalMode::Truncate,
+            JournalMode::Persist => SqliteJournalMode::Persist,
+            JournalMode::Memory => SqliteJournalMode::Memory,
+            JournalMode::Wal => SqliteJournalMode::Wal,
+            JournalMode::Off => SqliteJournalMode::Off,
+        }
+    }
+}
+
+/// The configuration of the server.
+///
+/// It is read from a toml file, every value can be overridden by an environment
+/// variable with the prefix `FAHRTENBUCH_`, e.g. `FAHRTENBUCH_DATABASE_URL`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database_url: String,
+    /// The address the server listens on.
+    pub addr: String,
+    /// The key to sign the session cookies, it is generated if the file does not exist.
+    pub key_file: PathBuf,
+    /// Only send the session cookie over https.
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSitePolicy,
+    /// A session expires after this many hours without a request.
+    pub session_lifetime_hours: u32,
+    /// The origins that are allowed to make cross-origin requests, e.g. `https://example.com`.
+    pub cors_origins: Vec<String>,
+    /// The maximum number of connections to the database.
+    pub max_connections: u32,
+    pub journal_mode: JournalMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database_url: "sqlite:data.db".to_string(),
+            addr: "127.0.0.1:3000".to_string(),
+            key_file: PathBuf::from("session.key"),
+            cookie_secure: true,
+            cookie_same_site: SameSitePolicy::Strict,
+            session_lifetime_hours: 24,
+            cors_origins: Vec::new(),
+            max_connections: 5,
+            journal_mode: JournalMode::Wal,
+        }
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|error| anyhow::anyhow!("invalid value for {}{}: {}", ENV_PREFIX, name, error))
+}
+
+/// Parses a value of an enum, like it would be written in the configuration file.
+fn parse_env_enum<T: for<'de> Deserialize<'de>>(name: &str, value: &str) -> anyhow::Result<T> {
+    T::deserialize(
+        serde::de::value::StrDeserializer::<serde::de::value::Error>::new(
+            &value.trim().to_lowercase(),
+        ),
+    )
+    .map_err(|error| anyhow::anyhow!("invalid value for {}{}: {}", ENV_PREFIX, name, error))
+}
+
+impl Config {
+    /// Reads the configuration file (if it exists), applies the environment
+    /// variables and validates the result.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = std::env::var(format!("{}CONFIG", ENV_PREFIX));
+        let mut config = match &path {
+            Ok(path) => Self::from_file(Path::new(path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            Err(_) => Self::default(),
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read the configuration {}", path.display()))?;
+
+        toml::from_str(&content)
+            .with_context(|| format!("invalid configuration in {}", path.display()))
+    }
+
+    /// Overrides the values with the environment variables returned by `var`.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        let env = |name: &str| var(&format!("{}{}", ENV_PREFIX, name));
+
+        if let Some(value) = env("DATABASE_URL") {
+            self.database_url = value;
+        }
+        // `ADDR` has been used before the configuration existed
+        if let Some(value) = env("ADDR").or_else(|| var("ADDR")) {
+            self.addr = value;
+        }
+        if let Some(value) = env("KEY_FILE") {
+            self.key_file = PathBuf::from(value);
+        }
+        if let Some(value) = env("COOKIE_SECURE") {
+            self.cookie_secure = parse_env("COOKIE_SECURE", &value)?;
+        }
+        if let Some(value) = env("COOKIE_SAME_SITE") {
+            self.cookie_same_site = parse_env_enum("COOKIE_SAME_SITE", &value)?;
+        }
+        if let Some(value) = env("SESSION_LIFETIME_HOURS") {
+            self.session_lifetime_hours = parse_env("SESSION_LIFETIME_HOURS", &value)?;
+        }
+        if let Some(value) = env("CORS_ORIGINS") {
+            self.cors_origins = value
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(value) = env("MAX_CONNECTIONS") {
+            self.max_connections = parse_env("MAX_CONNECTIONS", &value)?;
+        }
+        if let Some(value) = env("JOURNAL_MODE") {
+            self.journal_mode = parse_env_enum("JOURNAL_MODE", &value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the configuration, so the server fails at startup instead of later.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        SqliteConnectOptions::from_str(&self.database_url)
+            .with_context(|| format!("invalid database_url '{}'", self.database_url))?;
+
+        if !self.addr.contains(':') {
+            bail!("the addr '{}' must contain a port", self.addr);
+        }
+
+        if self.session_lifetime_hours == 0 {
+            bail!("the session_lifetime_hours must be at least 1");
+        }
+
+        if self.max_connections == 0 {
+            bail!("the max_connections must be at least 1");
+        }
+
+        // browsers reject cookies with `SameSite=None` that are not secure
+        if self.cookie_same_site == SameSitePolicy::None && !self.cookie_secure {
+            bail!("the cookie_same_site 'none' requires cookie_secure");
+        }
+
+        for origin in &self.cors_origins {
+            if !(origin.starts_with("http://") || origin.starts_with("https://"))
+                || HeaderValue::from_str(origin).is_err()
+            {
+                bail!("invalid cors origin '{}'", origin);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn cors_origins(&self) -> Vec<HeaderValue> {
+        self.cors_origins
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok())
+            .collect()
+    }
+
+    /// Reads the key to sign the session cookies, or generates and stores a new one.
+    ///
+    /// Keeping the key ensures that users stay logged in after a restart.
+    pub fn load_key(&self) -> anyhow::Result<Key> {
+        if self.key_file.exists() {
+            let bytes = fs::read(&self.key_file)
+                .with_context(|| format!("failed to read {}", self.key_file.display()))?;
+
+            return Key::try_from(bytes.as_slice())
+                .with_context(|| format!("invalid key in {}", self.key_file.display()));
+        }
+
+        let key = Key::generate();
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options
+            .open(&self.key_file)
+            .and_then(|mut file| file.write_all(key.master()))
+            .with_context(|| format!("failed to write {}", self.key_file.display()))?;
+
+        info!("Generated a new session key in {}", self.key_file.display());
+
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_file_with_env_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            addr = "0.0.0.0:3000"
+            cookie_secure = false
+            cors_origins = ["http://localhost:8080"]
+            journal_mode = "delete"
+            "#,
+        )
+        .unwrap();
+
+        let env = HashMap::from([
+            ("FAHRTENBUCH_MAX_CONNECTIONS", "10"),
+            ("FAHRTENBUCH_COOKIE_SAME_SITE", "Lax"),
+            (
+                "FAHRTENBUCH_CORS_ORIGINS",
+                "https://a.example, https://b.example",
+            ),
+        ]);
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(
+            Config {
+                addr: "0.0.0.0:3000".to_string(),
+                cookie_secure: false,
+                cookie_same_site: SameSitePolicy::Lax,
+                cors_origins: vec![
+                    "https://a.example".to_string(),
+                    "https://b.example".to_string()
+                ],
+                max_connections: 10,
+                journal_mode: JournalMode::Delete,
+                ..Config::default()
+            },
+            config
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_legacy_addr() {
+        let mut config = Config::default();
+        config
+            .apply_env(|name| (name == "ADDR").then(|| "0.0.0.0:80".to_string()))
+            .unwrap();
+
+        assert_eq!("0.0.0.0:80", config.addr);
+    }
+
+    #[test]
+    fn test_invalid_configurations() {
+        assert!(toml::from_str::<Config>("unknown = 1").is_err());
+        assert!(Config::default()
+            .apply_env(|_| Some("many".to_string()))
+            .is_err());
+
+        let invalid = [
+            Config {
+                addr: "localhost".to_string(),
+                ..Config::default()
+            },
+            Config {
+                max_connections: 0,
+                ..Config::default()
+            },
+            Config {
+                cookie_secure: false,
+                cookie_same_site: SameSitePolicy::None,
+                ..Config::default()
+            },
+            Config {
+                cors_origins: vec!["example.com".to_string()],
+                ..Config::default()
+            },
+        ];
+
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn test_key_is_persisted() {
+        let dir = std::env::temp_dir().join(format!("fahrtenbuch-key-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let config = Config {
+            key_file: dir.join("session.key"),
+            ..Config::default()
+        };
+
+        let first = config.load_key().unwrap();
+        let second = config.load_key().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first.master(), second.master());
+    }
+}
@@ -1,7 +1,11 @@
 mod api;
 mod app;
+mod audit;
 mod auth;
+mod cli;
+mod config;
 mod response;
+mod token;
 mod username;
 pub(crate) mod utils;
 
@@ -11,6 +15,9 @@ use std::ffi::OsStr;
 use log::error;
 
 use app::App;
+use clap::Parser;
+use cli::Cli;
+use config::Config;
 
 fn set_env_if_absent<K: AsRef<OsStr>, V: AsRef<OsStr>>(var: K, default: impl FnOnce() -> V) {
     if env::var(var.as_ref()).is_err() {
@@ -20,21 +27,21 @@ fn set_env_if_absent<K: AsRef<OsStr>, V: AsRef<OsStr>>(var: K, default: impl FnO
 
 #[tokio::main]
 async fn main() {
-    set_env_if_absent("RUST_APP_LOG", || "trace");
-    set_env_if_absent("ADDR", || "127.0.0.1:3000");
+    let command = Cli::parse().command.unwrap_or_default();
+
+    set_env_if_absent("RUST_APP_LOG", || command.log_level());
     color_backtrace::install();
     pretty_env_logger::init_custom_env("RUST_APP_LOG");
 
-    if let Err(e) = run().await {
+    if let Err(e) = run(command).await {
         error!("{:?}", e);
         ::std::process::exit(1);
     }
 }
 
-async fn run() -> anyhow::Result<()> {
-    let app = App::connect("sqlite:data.db").await?;
-    //let app = App::connect("sqlite::memory:").await?;
-    app.serve(env::var("ADDR")?).await?;
+async fn run(command: cli::Command) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let app = App::connect(config).await?;
 
-    Ok(())
+    cli::run(app, command).await
 }
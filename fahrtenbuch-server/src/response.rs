@@ -1,28 +1,41 @@
+use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use log::error;
 use serde::Serialize;
 
-pub enum ApiResult<T: Serialize, E: ToString = String> {
+use crate::auth::AuthBackendError;
+
+pub enum ApiResult<T: Serialize> {
     Ok(T),
-    Err(E),
+    Err(ApiError),
 }
 
-impl<T: Serialize, E: ToString> ApiResult<T, E> {
+impl<T: Serialize> ApiResult<T> {
     pub fn ok(data: T) -> Self {
         ApiResult::Ok(data)
     }
 
-    pub fn error(message: E) -> Self {
-        ApiResult::Err(message)
+    pub fn error(error: impl Into<ApiError>) -> Self {
+        ApiResult::Err(error.into())
     }
 }
 
-impl<T: Serialize, E: ToString> ApiResult<Option<T>, E> {
+impl<T: Serialize> ApiResult<Option<T>> {
     pub fn empty() -> Self {
         ApiResult::Ok(None)
     }
 }
 
-impl<T: Serialize, E: ToString> IntoResponse for ApiResult<T, E> {
+impl<T: Serialize, E: Into<ApiError>> From<Result<T, E>> for ApiResult<T> {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(data) => ApiResult::Ok(data),
+            Err(error) => ApiResult::Err(error.into()),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for ApiResult<T> {
     fn into_response(self) -> Response {
         match self {
             ApiResult::Ok(data) => axum::Json(serde_json::json!({
@@ -30,11 +43,218 @@ impl<T: Serialize, E: ToString> IntoResponse for ApiResult<T, E> {
                 "data": data,
             }))
             .into_response(),
-            ApiResult::Err(message) => axum::Json(serde_json::json!({
-                "success": false,
-                "message": message.to_string(),
-            }))
-            .into_response(),
+            ApiResult::Err(error) => error.into_response(),
+        }
+    }
+}
+
+/// An error in a specific field of the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// The errors that can be returned by the api.
+///
+/// Internal errors are logged, but their details are never sent to the client.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// The request is malformed or the data would be invalid.
+    #[error("{message}")]
+    Validation {
+        message: String,
+        details: Vec<FieldError>,
+    },
+    /// The requested entry does not exist.
+    #[error("{0}")]
+    NotFound(String),
+    /// The request conflicts with the data that is already stored.
+    #[error("{0}")]
+    Conflict(String),
+    /// The user is not logged in or the credentials are invalid.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The user is logged in, but not allowed to do this.
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("Internal server error")]
+    Internal(#[source] anyhow::Error),
+}
+
+impl ApiError {
+    /// A validation error that is caused by a single field of the request.
+    pub fn invalid_field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        let message = message.into();
+
+        Self::Validation {
+            details: vec![FieldError {
+                field: field.into(),
+                message: message.clone(),
+            }],
+            message,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::Conflict(message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::Forbidden(message.into())
+    }
+
+    /// A machine-readable identifier for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation { .. } => "validation_error",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation { .. } => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        // errors that have been wrapped in an anyhow::Error keep their kind
+        match error.downcast::<ApiError>() {
+            Ok(error) => error,
+            Err(error) => Self::Internal(error),
         }
     }
 }
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        Self::Internal(error.into())
+    }
+}
+
+impl From<AuthBackendError> for ApiError {
+    fn from(error: AuthBackendError) -> Self {
+        match error {
+            AuthBackendError::UserAlreadyExists(username) => {
+                Self::conflict(format!("The user '{}' already exists", username))
+            }
+            AuthBackendError::RegistrationClosed => Self::forbidden(error.to_string()),
+            AuthBackendError::InvalidInvitation => {
+                Self::invalid_field("invitation", error.to_string())
+            }
+            AuthBackendError::InvalidResetToken => Self::invalid_field("token", error.to_string()),
+            AuthBackendError::WrongPassword => {
+                Self::invalid_field("old_password", error.to_string())
+            }
+            AuthBackendError::InvalidPassword(message) => Self::invalid_field("password", message),
+            error => Self::Internal(error.into()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let Self::Internal(error) = &self {
+            error!("Internal error: {:?}", error);
+        }
+
+        let details = match &self {
+            Self::Validation { details, .. } if !details.is_empty() => Some(details.clone()),
+            _ => None,
+        };
+
+        (
+            self.status(),
+            axum::Json(serde_json::json!({
+                "success": false,
+                "message": self.to_string(),
+                "error": {
+                    "code": self.code(),
+                    "message": self.to_string(),
+                    "details": details,
+                },
+            })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    async fn response_body(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_validation_error_has_details() {
+        let (status, body) = response_body(ApiError::invalid_field(
+            "start",
+            "The start must be before the end",
+        ))
+        .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(
+            serde_json::json!({
+                "success": false,
+                "message": "The start must be before the end",
+                "error": {
+                    "code": "validation_error",
+                    "message": "The start must be before the end",
+                    "details": [{
+                        "field": "start",
+                        "message": "The start must be before the end",
+                    }],
+                },
+            }),
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_is_not_exposed() {
+        let (status, body) =
+            response_body(anyhow::anyhow!("secret database path /data/data.db").into()).await;
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!("internal_error", body["error"]["code"]);
+        assert!(!body.to_string().contains("/data/data.db"));
+    }
+
+    #[test]
+    fn test_wrapped_error_keeps_its_kind() {
+        let error: anyhow::Error = ApiError::not_found("The trip does not exist").into();
+
+        assert_eq!("not_found", ApiError::from(error).code());
+    }
+}
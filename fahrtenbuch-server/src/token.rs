@@ -0,0 +1,146 @@
+use std::fmt;
+
+use chrono::Utc;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::SqliteConnection;
+
+/// A secret that is handed out to the user once, only its hash is stored.
+///
+/// The token is random, so a fast hash is enough to protect it (unlike passwords).
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token(String);
+
+impl Token {
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+
+        Self(hex::encode(bytes))
+    }
+
+    /// The hash that is stored in the database instead of the token.
+    pub fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+/// Hashes a token provided by a client, to look it up in the database.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// The token must never end up in a log.
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Token").field(&"[redacted]").finish()
+    }
+}
+
+/// The kinds of tokens that can only be used once before they expire.
+///
+/// Their tables store the `token_hash`, when the token `expires_at` and when it has been `used_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SingleUse {
+    Invitation,
+    PasswordReset,
+}
+
+impl SingleUse {
+    fn table(self) -> &'static str {
+        match self {
+            Self::Invitation => "invitations",
+            Self::PasswordReset => "password_resets",
+        }
+    }
+
+    /// Marks the token as used and returns the id of its row.
+    ///
+    /// Returns `None` if the token does not exist, has already been used or has expired.
+    pub async fn consume(
+        self,
+        db: &mut SqliteConnection,
+        token: &str,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let now = Utc::now();
+
+        // the check for `used_at` prevents that the same token is used twice
+        let id: Option<(i64,)> = sqlx::query_as(&format!(
+            "update {} set used_at = ? where token_hash = ? and used_at is null and datetime(expires_at) > datetime(?) returning id",
+            self.table()
+        ))
+        .bind(now)
+        .bind(hash_token(token))
+        .bind(now)
+        .fetch_optional(db)
+        .await?;
+
+        Ok(id.map(|(id,)| id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use crate::utils;
+
+    #[test]
+    fn test_tokens_are_unique_and_hashed() {
+        let first = Token::generate();
+        let second = Token::generate();
+
+        assert_ne!(first, second);
+        assert_eq!(64, first.to_string().len());
+        assert_eq!(first.hash(), hash_token(&first.to_string()));
+        assert_ne!(first.to_string(), first.hash());
+        assert!(!format!("{:?}", first).contains(&first.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_used_once_before_they_expire() {
+        let db = utils::test_db().await;
+        let valid = Token::generate();
+        let expired = Token::generate();
+
+        for (token, expires_at) in [
+            (&valid, Utc::now() + Duration::hours(1)),
+            (&expired, Utc::now() - Duration::hours(1)),
+        ] {
+            sqlx::query(
+                "insert into password_resets (token_hash, user_id, created_by, created_at, expires_at) values (?, 2, 1, ?, ?)",
+            )
+            .bind(token.hash())
+            .bind(Utc::now())
+            .bind(expires_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let mut conn = db.acquire().await.unwrap();
+        let unknown = Token::generate();
+
+        for (token, expected) in [
+            (&expired, None),
+            (&unknown, None),
+            (&valid, Some(1)),
+            (&valid, None),
+        ] {
+            let id = SingleUse::PasswordReset
+                .consume(&mut conn, &token.to_string())
+                .await
+                .unwrap();
+            assert_eq!(expected, id);
+        }
+    }
+}
@@ -1,15 +1,20 @@
+use std::future::Future;
 use std::iter;
+use std::pin::Pin;
 
 use chrono::{DateTime, Utc};
 use num_traits::{AsPrimitive, NumAssign, PrimInt};
-use sqlx::{Encode, QueryBuilder};
+use sqlx::pool::PoolConnection;
+use sqlx::{Encode, QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
 
 pub fn divide_equally<N: PrimInt + AsPrimitive<usize>>(amount: N, n: N) -> impl Iterator<Item = N> {
     let part = amount / n;
     let remainder = amount % n;
 
-    iter::once(part + remainder)
-        .chain(iter::repeat(part).take((n - N::one()).to_usize().unwrap_or_default()))
+    iter::once(part + remainder).chain(iter::repeat_n(
+        part,
+        (n - N::one()).to_usize().unwrap_or_default(),
+    ))
 }
 
 /// Divides the `numerator` into `N` parts, sized proportionally to the
@@ -20,16 +25,16 @@ pub fn divide_proportionally<N: PrimInt + NumAssign>(numerator: N, proportion: &
     let total = {
         let mut total = N::zero();
 
-        for i in 0..proportion.len() {
-            total += proportion[i];
+        for value in proportion.iter() {
+            total += *value;
         }
 
         total
     };
 
     if total == N::zero() {
-        for i in 0..proportion.len() {
-            proportion[i] = N::zero();
+        for value in proportion.iter_mut() {
+            *value = N::zero();
         }
 
         return N::zero();
@@ -37,14 +42,110 @@ pub fn divide_proportionally<N: PrimInt + NumAssign>(numerator: N, proportion: &
 
     let mut remainder = numerator;
 
-    for i in 0..proportion.len() {
-        proportion[i] = (numerator * proportion[i]) / total;
-        remainder -= proportion[i];
+    for value in proportion.iter_mut() {
+        *value = (numerator * *value) / total;
+        remainder -= *value;
     }
 
     remainder
 }
 
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Runs `f` inside a transaction, which is only committed if `f` succeeds.
+///
+/// If `f` fails, the transaction is rolled back, so none of its changes are persisted.
+///
+/// The transaction takes the write lock when it begins, so two transactions that read
+/// the odometer chain before changing it can not interleave and fail on commit.
+pub async fn transaction<T, E, F>(db: &SqlitePool, f: F) -> Result<T, E>
+where
+    F: for<'c> FnOnce(&'c mut SqliteConnection) -> BoxFuture<'c, Result<T, E>>,
+    E: From<sqlx::Error>,
+{
+    conditional_transaction(db, f, |_| true).await
+}
+
+/// Like [`transaction`], but a successful result is only committed if `commit`
+/// returns `true` for it, otherwise the transaction is rolled back (e.g. for a dry run).
+pub async fn conditional_transaction<T, E, F, C>(db: &SqlitePool, f: F, commit: C) -> Result<T, E>
+where
+    F: for<'c> FnOnce(&'c mut SqliteConnection) -> BoxFuture<'c, Result<T, E>>,
+    C: FnOnce(&T) -> bool,
+    E: From<sqlx::Error>,
+{
+    let mut tx = ImmediateTransaction::begin(db).await?;
+
+    match f(tx.connection()).await {
+        Ok(result) => {
+            let statement = if commit(&result) {
+                "commit"
+            } else {
+                "rollback"
+            };
+            tx.finish(statement).await?;
+            Ok(result)
+        }
+        Err(error) => {
+            // if the rollback fails, the connection is closed, which rolls back as well
+            let _ = tx.finish("rollback").await;
+            Err(error)
+        }
+    }
+}
+
+/// A transaction started with `begin immediate`, which sqlx 0.7 does not support.
+///
+/// If the transaction is dropped before it has been finished (e.g. because the
+/// request has been cancelled), its connection is closed instead of being returned
+/// to the pool, which rolls back the transaction.
+struct ImmediateTransaction {
+    connection: Option<PoolConnection<Sqlite>>,
+}
+
+impl ImmediateTransaction {
+    async fn begin(db: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let mut tx = Self {
+            connection: Some(db.acquire().await?),
+        };
+
+        if let Err(error) = sqlx::query("begin immediate")
+            .execute(tx.connection())
+            .await
+        {
+            // no transaction has been started, so the connection can be reused
+            tx.connection = None;
+            return Err(error);
+        }
+
+        Ok(tx)
+    }
+
+    fn connection(&mut self) -> &mut SqliteConnection {
+        self.connection
+            .as_mut()
+            .expect("the transaction has already been finished")
+    }
+
+    /// Ends the transaction with the given statement (`commit` or `rollback`).
+    async fn finish(mut self, statement: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(statement).execute(self.connection()).await?;
+
+        // returns the connection to the pool
+        self.connection = None;
+
+        Ok(())
+    }
+}
+
+impl Drop for ImmediateTransaction {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            drop(connection.detach());
+        }
+    }
+}
+
 pub fn sorted_vec<T>(into_iter: impl IntoIterator<Item = T>) -> Vec<T>
 where
     T: Ord,
@@ -55,12 +156,8 @@ where
 }
 
 pub trait SqlBuilderExt<'args, DB: sqlx::Database> {
-    /// Add a constraint to the query that the field must be in the given values.
-    ///
-    /// If the given values are empty, this method does nothing.
-    fn push_in<T>(&mut self, field: &str, values: impl IntoIterator<Item = T>) -> &mut Self
-    where
-        T: 'args + Encode<'args, DB> + Send + sqlx::Type<DB>;
+    /// Starts the where clause of the query, see [`Filter`].
+    fn filter(&mut self) -> Filter<'_, 'args, DB>;
 
     fn push_utc_bind(&mut self, field: DateTime<Utc>) -> &mut Self
     where
@@ -68,23 +165,71 @@ pub trait SqlBuilderExt<'args, DB: sqlx::Database> {
 }
 
 impl<'args, DB: sqlx::Database> SqlBuilderExt<'args, DB> for QueryBuilder<'args, DB> {
-    fn push_in<T>(&mut self, field: &str, values: impl IntoIterator<Item = T>) -> &mut Self
+    fn filter(&mut self) -> Filter<'_, 'args, DB> {
+        Filter {
+            builder: self,
+            is_empty: true,
+        }
+    }
+
+    fn push_utc_bind(&mut self, field: DateTime<Utc>) -> &mut Self
+    where
+        DateTime<Utc>: Encode<'args, DB> + Send + sqlx::Type<DB>,
+    {
+        self.push("datetime(").push_bind(field).push(", 'utc')")
+    }
+}
+
+/// Builds the where clause of a query, all constraints are combined with `and`.
+///
+/// Constraints without a value are skipped, so the options of a request can be
+/// passed as they are.
+pub struct Filter<'b, 'args, DB: sqlx::Database> {
+    builder: &'b mut QueryBuilder<'args, DB>,
+    is_empty: bool,
+}
+
+impl<'b, 'args, DB: sqlx::Database> Filter<'b, 'args, DB> {
+    /// Starts a new constraint, the returned builder must be used to push it.
+    pub fn condition(&mut self) -> &mut QueryBuilder<'args, DB> {
+        let separator = if self.is_empty { " where " } else { " and " };
+        self.is_empty = false;
+
+        self.builder.push(separator)
+    }
+
+    /// Add a constraint to the query that the field must be equal to the value.
+    pub fn eq<T>(&mut self, field: &str, value: Option<T>) -> &mut Self
     where
         T: 'args + Encode<'args, DB> + Send + sqlx::Type<DB>,
     {
-        let mut iterator = values.into_iter();
-        let first_value = iterator.next();
-        if first_value.is_none() {
-            // nothing to constrain
-            return self;
+        if let Some(value) = value {
+            self.condition()
+                .push(format!("{} = ", field))
+                .push_bind(value);
         }
 
-        let mut separated = self.push(format!(" where {} in (", field)).separated(", ");
+        self
+    }
 
-        if let Some(value) = first_value {
-            separated.push_bind(value);
+    /// Add a constraint to the query that the field must be in the given values.
+    ///
+    /// If the given values are empty, this method does nothing.
+    pub fn any_of<T>(&mut self, field: &str, values: impl IntoIterator<Item = T>) -> &mut Self
+    where
+        T: 'args + Encode<'args, DB> + Send + sqlx::Type<DB>,
+    {
+        let mut iterator = values.into_iter().peekable();
+        if iterator.peek().is_none() {
+            // nothing to constrain
+            return self;
         }
 
+        let mut separated = self
+            .condition()
+            .push(format!("{} in (", field))
+            .separated(", ");
+
         for value in iterator {
             separated.push_bind(value);
         }
@@ -94,10 +239,271 @@ impl<'args, DB: sqlx::Database> SqlBuilderExt<'args, DB> for QueryBuilder<'args,
         self
     }
 
-    fn push_utc_bind(&mut self, field: DateTime<Utc>) -> &mut Self
+    /// Add a constraint that the field must be in the result of the subquery,
+    /// the constraints of the subquery are added by `build`.
+    pub fn in_subquery(
+        &mut self,
+        field: &str,
+        query: &str,
+        build: impl FnOnce(&mut Filter<'_, 'args, DB>),
+    ) -> &mut Self {
+        let builder = self.condition().push(format!("{} in ({}", field, query));
+        build(&mut builder.filter());
+        builder.push(")");
+
+        self
+    }
+
+    /// Add a constraint that the date in the field must be between start and end (inclusive).
+    pub fn date_range(
+        &mut self,
+        field: &str,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> &mut Self
     where
         DateTime<Utc>: Encode<'args, DB> + Send + sqlx::Type<DB>,
     {
-        self.push("datetime(").push_bind(field).push(", 'utc')")
+        if let Some(start) = start {
+            self.condition()
+                .push(format!("datetime({}, 'utc') >= ", field))
+                .push_utc_bind(start);
+        }
+
+        if let Some(end) = end {
+            self.condition()
+                .push(format!("datetime({}, 'utc') <= ", field))
+                .push_utc_bind(end);
+        }
+
+        self
+    }
+
+    /// Add a constraint that the value of the expression must be between min and max (inclusive).
+    pub fn range(&mut self, expression: &str, min: Option<i64>, max: Option<i64>) -> &mut Self
+    where
+        i64: Encode<'args, DB> + Send + sqlx::Type<DB>,
+    {
+        if let Some(min) = min {
+            self.condition()
+                .push(format!("{} >= ", expression))
+                .push_bind(min);
+        }
+
+        if let Some(max) = max {
+            self.condition()
+                .push(format!("{} <= ", expression))
+                .push_bind(max);
+        }
+
+        self
+    }
+
+    /// Add a constraint that the field must contain the text, ignoring the case.
+    pub fn contains(&mut self, field: &str, text: Option<&str>) -> &mut Self
+    where
+        String: Encode<'args, DB> + Send + sqlx::Type<DB>,
+    {
+        let Some(text) = text.map(str::trim).filter(|text| !text.is_empty()) else {
+            return self;
+        };
+
+        // the wildcards of like must not be interpreted in the text
+        let pattern = format!(
+            "%{}%",
+            text.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+
+        self.condition()
+            .push(format!("{} like ", field))
+            .push_bind(pattern)
+            .push(" escape '\\'");
+
+        self
+    }
+}
+
+/// Creates an in-memory database with all migrations applied.
+#[cfg(test)]
+pub async fn test_db() -> sqlx::SqlitePool {
+    // every connection to an in-memory database opens a new database,
+    // so the pool must keep exactly one connection alive
+    let db = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("failed to open in-memory database");
+
+    sqlx::migrate!()
+        .run(&db)
+        .await
+        .expect("failed to run migrations");
+
+    sqlx::query(
+        "insert into users (id, username, password) values (1, 'alice', ''), (2, 'bob', '')",
+    )
+    .execute(&db)
+    .await
+    .expect("failed to insert users");
+
+    db
+}
+
+/// Inserts a trip of alice into the test database, without any validation.
+#[cfg(test)]
+pub async fn insert_trip(db: &SqlitePool, vehicle_id: i64, start: i64, end: i64) {
+    let result = sqlx::query(
+        "insert into trips (vehicle_id, created_at, start, end) values (?, datetime('now'), ?, ?)",
+    )
+    .bind(vehicle_id)
+    .bind(start)
+    .bind(end)
+    .execute(db)
+    .await
+    .expect("failed to insert trip");
+
+    sqlx::query("insert into trip_users (trip_id, user_id) values (?, 1)")
+        .bind(result.last_insert_rowid())
+        .execute(db)
+        .await
+        .expect("failed to insert trip users");
+}
+
+/// Loads a user of the test database, alice and bob are members.
+#[cfg(test)]
+pub async fn test_user(db: &SqlitePool, id: i64) -> crate::auth::User {
+    sqlx::query_as("select * from users where id = ?")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .expect("failed to load user")
+}
+
+/// Returns the start and end of all trips of the vehicle, ordered by the start.
+#[cfg(test)]
+pub async fn list_chain(db: &SqlitePool, vehicle_id: i64) -> Vec<(i64, i64)> {
+    sqlx::query_as("select start, end from trips where vehicle_id = ? order by start")
+        .bind(vehicle_id)
+        .fetch_all(db)
+        .await
+        .expect("failed to list trips")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use sqlx::Sqlite;
+
+    fn filtered_sql(build: impl FnOnce(&mut Filter<'_, '_, Sqlite>)) -> String {
+        let mut builder = QueryBuilder::<Sqlite>::new("select * from trips");
+        build(&mut builder.filter());
+
+        builder.sql().to_string()
+    }
+
+    #[test]
+    fn test_empty_filter_has_no_where() {
+        assert_eq!(
+            "select * from trips",
+            filtered_sql(|filter| {
+                filter
+                    .eq::<i64>("vehicle_id", None)
+                    .any_of::<i64>("id", vec![])
+                    .date_range("created_at", None, None)
+                    .range("end", None, None)
+                    .contains("description", Some("  "));
+            })
+        );
+    }
+
+    #[test]
+    fn test_constraints_are_combined_with_and() {
+        let date = Utc::now();
+
+        assert_eq!(
+            "select * from trips where datetime(created_at, 'utc') <= datetime(?, 'utc') \
+             and id in (?, ?) and id in (select trip_id from trip_users where user_id in (?))",
+            filtered_sql(|filter| {
+                filter
+                    .date_range("created_at", None, Some(date))
+                    .any_of("id", vec![1, 2])
+                    .in_subquery("id", "select trip_id from trip_users", |users| {
+                        users.any_of("user_id", vec![1]);
+                    });
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transaction_takes_the_write_lock() {
+        let path = std::env::temp_dir().join(format!(
+            "fahrtenbuch-transaction-{}.sqlite",
+            std::process::id()
+        ));
+        let options = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+        let db = SqlitePool::connect_with(options.clone()).await.unwrap();
+        // only the other writer gives up right away, the transaction itself may have to
+        // wait for the connections of the other pool being set up
+        let other = SqlitePool::connect_with(options.busy_timeout(std::time::Duration::ZERO))
+            .await
+            .unwrap();
+
+        let result: Result<(), sqlx::Error> = transaction(&db, |tx| {
+            Box::pin(async move {
+                // the transaction has only read so far, but another writer must already wait
+                sqlx::query("select 1").execute(&mut *tx).await?;
+                let locked = sqlx::query("begin immediate").execute(&other).await;
+                assert!(locked.is_err());
+
+                Ok(())
+            })
+        })
+        .await;
+        result.unwrap();
+
+        db.close().await;
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_contains_escapes_wildcards() {
+        let db = test_db().await;
+        sqlx::query("create table texts (description text)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            "insert into texts values ('Trip to the lake'), ('50% off'), ('500 off'), ('a_b')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let mut matches = Vec::new();
+        for search in ["LAKE", "50%", "_", "ab"] {
+            let mut builder = QueryBuilder::<Sqlite>::new("select description from texts");
+            builder.filter().contains("description", Some(search));
+
+            let result: Vec<(String,)> = builder.build_query_as().fetch_all(&db).await.unwrap();
+            matches.push(result.into_iter().map(|(text,)| text).collect::<Vec<_>>());
+        }
+
+        assert_eq!(
+            vec![
+                vec!["Trip to the lake".to_string()],
+                vec!["50% off".to_string()],
+                vec!["a_b".to_string()],
+                vec![],
+            ],
+            matches
+        );
     }
 }
@@ -91,6 +91,12 @@ class ApiSession {
       return Future.value(result.data["data"]);
     } on DioException catch (e) {
       debugPrint("Request failed: ${e.message}");
+      // the server responds with an error status code and the reason in the body
+      var data = e.response?.data;
+      if (data is Map && data["message"] != null) {
+        return Future.error(data["message"]);
+      }
+
       return Future.error(e.message ?? "An error occurred");
     }
   }
@@ -125,10 +131,13 @@ class ApiSession {
   }
 
   Future<void> register(
-      {required String username, required String password}) async {
+      {required String username,
+      required String password,
+      String? invitation}) async {
     await _post("register", json: {
       "username": username,
       "password": password,
+      "invitation": invitation,
     });
 
     await _postLogin(username: username, password: password);
@@ -167,11 +176,12 @@ class ApiSession {
   Future<List<Map<String, dynamic>>> listTrips(
       {DateTime? start, DateTime? end, List<UserId> users = const []}) async {
     debugPrint("Listing trips: $start, $end, $users");
-    List<dynamic> r = await _get("list_trips", json: {
+    var page = await _get("list_trips", json: {
       "start": deserializeDateTime(start),
       "end": deserializeDateTime(end),
       "users": users,
     });
+    List<dynamic> r = page["items"];
 
     debugPrint("List trips: $r");
 
@@ -210,11 +220,12 @@ class ApiSession {
       {DateTime? start, DateTime? end, List<UserId> users = const []}) async {
     debugPrint(
         "Listing expenses: ${start?.toIso8601String()}, ${end?.toIso8601String()}, $users");
-    var r = await _get("list_expenses", json: {
+    var page = await _get("list_expenses", json: {
       "start": deserializeDateTime(start),
       "end": deserializeDateTime(end),
       "users": users,
     });
+    var r = page["items"];
 
     debugPrint("List expenses: $r");
 